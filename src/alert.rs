@@ -0,0 +1,110 @@
+use std::time::Duration;
+
+use crate::config::Severity;
+
+/// What produced an alert.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AlertKind {
+    /// A line matching an alert rule.
+    Rule,
+    /// A missed heartbeat, or a silent journal stream.
+    Heartbeat,
+    /// A heartbeat, or the journal stream, being back, or the resolve line of an
+    /// alert rule.
+    Recovery,
+    /// A notice from the tool itself, e.g. at startup.
+    #[default]
+    System,
+}
+
+/// An alert sent from the processor to the notifier.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alert {
+    // the text of the alert
+    pub message: String,
+    // name of the alert rule that matched, if any
+    pub rule: Option<String>,
+    pub severity: Severity,
+    pub kind: AlertKind,
+    // journal timestamp of the line, kept out of the message so it does not
+    // defeat the duplicate suppression
+    pub timestamp: Option<String>,
+    // number of consecutive identical lines collapsed into the alert, kept out of
+    // the message as the timestamp
+    pub repeated: usize,
+    // bypass the notifier's duplicate suppression
+    pub always_notify: bool,
+    // how long duplicates are suppressed instead of the severity ttl, e.g. until
+    // the end of a suppression window
+    pub suppression_ttl: Option<Duration>,
+    // number of recent journal lines the notifier should attach
+    pub context_lines: usize,
+    // journalctl filter the context is read with, e.g. the monitored unit
+    pub journal_filter: Vec<String>,
+    // the recent journal lines, fetched by the notifier
+    pub context: Option<String>,
+    // names of the notifiers the alert is routed to, all of them when empty
+    pub notify: Vec<String>,
+    // pairs a heartbeat miss with its recovery, posted as a reply to it in Slack
+    pub thread_key: Option<String>,
+    // alert rules whose suppressed duplicates the alert clears, e.g. on a heartbeat recovery
+    pub reset_rules: Vec<String>,
+    // dedup keys of the alerts it clears the suppression of, e.g. the miss of a heartbeat
+    pub reset_keys: Vec<String>,
+    // text duplicates are detected on instead of the message, for messages with a
+    // varying part such as the time since a heartbeat was last seen
+    pub dedup_key: Option<String>,
+}
+
+impl Alert {
+    /// Creates an informational alert.
+    pub fn new(message: impl Into<String>) -> Self {
+        Alert {
+            message: message.into(),
+            rule: None,
+            severity: Severity::Info,
+            kind: AlertKind::System,
+            timestamp: None,
+            repeated: 1,
+            always_notify: false,
+            suppression_ttl: None,
+            context_lines: 0,
+            journal_filter: Vec::new(),
+            context: None,
+            notify: Vec::new(),
+            thread_key: None,
+            reset_rules: Vec::new(),
+            reset_keys: Vec::new(),
+            dedup_key: None,
+        }
+    }
+
+    /// The rules whose suppressed duplicates the alert clears: its own rule when it
+    /// resolves it, and the rules linked to a recovered heartbeat.
+    pub fn resets(&self) -> impl Iterator<Item = &str> {
+        let resolved = self
+            .rule
+            .as_deref()
+            .filter(|_| self.kind == AlertKind::Recovery);
+        resolved
+            .into_iter()
+            .chain(self.reset_rules.iter().map(String::as_str))
+    }
+
+    /// The text duplicates of the alert are detected on.
+    pub fn dedup_text(&self) -> &str {
+        self.dedup_key.as_deref().unwrap_or(&self.message)
+    }
+
+    /// The text to display, with the number of repeats and the timestamp if any.
+    pub fn text(&self) -> String {
+        let mut text = self.message.clone();
+        if self.repeated > 1 {
+            text = format!("{text} (repeated {} times)", self.repeated);
+        }
+        match &self.timestamp {
+            Some(timestamp) => format!("{text} ({timestamp})"),
+            None => text,
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use std::time::Duration;
+
+// upper bound of the delay between restarts of a failing task
+const MAX_DELAY: Duration = Duration::from_secs(300);
+
+/// Delays between the restarts of a failing task, doubling from the initial one up
+/// to five minutes. A task that ran longer than that before failing starts over from
+/// the initial delay.
+pub struct Backoff {
+    initial: Duration,
+    next: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration) -> Self {
+        Backoff {
+            initial,
+            next: initial,
+        }
+    }
+
+    /// The delay before restarting a task that failed after running for `ran`.
+    pub fn delay(&mut self, ran: Duration) -> Duration {
+        if ran >= MAX_DELAY {
+            self.next = self.initial;
+        }
+        let delay = self.next;
+        self.next = (delay * 2).min(MAX_DELAY);
+        delay
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay() {
+        let mut backoff = Backoff::new(Duration::from_secs(60));
+        let delays = (0..5)
+            .map(|_| backoff.delay(Duration::ZERO).as_secs())
+            .collect::<Vec<_>>();
+        assert_eq!(delays, [60, 120, 240, 300, 300]);
+        // a task running fine for a while starts over
+        assert_eq!(backoff.delay(MAX_DELAY).as_secs(), 60);
+        assert_eq!(backoff.delay(Duration::ZERO).as_secs(), 120);
+    }
+}
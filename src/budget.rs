@@ -0,0 +1,167 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, Local, NaiveDate};
+use log::{info, warn};
+
+use crate::alert::{Alert, AlertKind};
+use crate::config::Severity;
+
+/// A daily budget of alerts, a coarse safety net against runaway notifications: once
+/// it is spent the alerts are dropped until local midnight, after a single notice.
+/// Only the alerts of the rules and heartbeats use it, the recoveries and the notices
+/// of the tool itself are always sent.
+#[derive(Default)]
+pub struct AlertBudget {
+    // alerts per day, unlimited when 0
+    max: usize,
+    // send the always_notify alerts over the budget, without using it
+    bypass_always_notify: bool,
+    day: Mutex<Day>,
+}
+
+/// The spending of the current day.
+#[derive(Default)]
+struct Day {
+    date: Option<NaiveDate>,
+    sent: usize,
+    dropped: usize,
+}
+
+/// The state of the budget, for the control socket.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Spending {
+    pub max: usize,
+    pub remaining: usize,
+    pub dropped: usize,
+}
+
+impl AlertBudget {
+    pub fn new(max: usize, bypass_always_notify: bool) -> Self {
+        AlertBudget {
+            max,
+            bypass_always_notify,
+            day: Mutex::default(),
+        }
+    }
+
+    /// Counts the alert against the budget of the day. Returns the alert within the
+    /// budget, the notice of the spent budget instead of the first alert over it, and
+    /// nothing for the following ones until the budget resets.
+    pub fn admit(&self, alert: Alert, today: NaiveDate) -> Option<Alert> {
+        let budgeted = matches!(alert.kind, AlertKind::Rule | AlertKind::Heartbeat)
+            && !(alert.always_notify && self.bypass_always_notify);
+        if self.max == 0 || !budgeted {
+            return Some(alert);
+        }
+        let mut day = self.day(today);
+        if day.sent < self.max {
+            day.sent += 1;
+            return Some(alert);
+        }
+        day.dropped += 1;
+        if day.dropped > 1 {
+            warn!("Alert budget spent, dropped: {}", alert.message);
+            return None;
+        }
+        warn!("Alert budget of {} spent for {}", self.max, today);
+        // the date keeps the notices of two days apart for the duplicate suppression
+        Some(Alert {
+            severity: Severity::Critical,
+            always_notify: true,
+            ..Alert::new(format!(
+                "💸 Daily budget of {} alerts spent on {today}, the alerts are dropped until midnight",
+                self.max
+            ))
+        })
+    }
+
+    /// The spending of the day, `None` when the budget is unlimited.
+    pub fn spending(&self, today: NaiveDate) -> Option<Spending> {
+        if self.max == 0 {
+            return None;
+        }
+        let day = self.day(today);
+        Some(Spending {
+            max: self.max,
+            remaining: self.max.saturating_sub(day.sent),
+            dropped: day.dropped,
+        })
+    }
+
+    /// The spending of the day, reset when the day changed.
+    fn day(&self, today: NaiveDate) -> std::sync::MutexGuard<'_, Day> {
+        let mut day = self.day.lock().unwrap();
+        if day.date != Some(today) {
+            if day.dropped > 0 {
+                info!(
+                    "Alert budget reset, {} alerts were dropped over it",
+                    day.dropped
+                );
+            }
+            *day = Day {
+                date: Some(today),
+                ..Day::default()
+            };
+        }
+        day
+    }
+}
+
+/// Time left until the next local midnight, when the budget resets.
+pub fn until_midnight(now: DateTime<Local>) -> Duration {
+    let tomorrow = now
+        .date_naive()
+        .succ_opt()
+        .and_then(|day| day.and_hms_opt(0, 0, 0));
+    tomorrow
+        .and_then(|midnight| midnight.and_local_timezone(Local).earliest())
+        .map_or(Duration::ZERO, |midnight| {
+            (midnight - now).to_std().unwrap_or_default()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget() {
+        let budget = AlertBudget::new(2, true);
+        let today = NaiveDate::from_ymd_opt(2026, 3, 1).unwrap();
+        let alert = |message: &str| Alert {
+            kind: AlertKind::Rule,
+            ..Alert::new(message)
+        };
+        let admitted = |alert: Alert, today| budget.admit(alert, today).map(|a| a.message);
+        let critical = || Alert {
+            always_notify: true,
+            ..alert("disk full")
+        };
+        assert_eq!(admitted(alert("a"), today).as_deref(), Some("a"));
+        // the always_notify alerts bypass the budget without using it
+        assert!(admitted(critical(), today).is_some());
+        // the notices of the tool itself do not use the budget
+        assert!(admitted(Alert::new("started"), today).is_some());
+        assert_eq!(admitted(alert("b"), today).as_deref(), Some("b"));
+        // a single notice for the alerts over the budget
+        let notice = admitted(alert("c"), today).unwrap();
+        assert!(notice.starts_with("💸 Daily budget of 2 alerts spent on 2026-03-01"));
+        assert_eq!(admitted(alert("d"), today), None);
+        assert!(admitted(critical(), today).is_some());
+        assert_eq!(
+            budget.spending(today),
+            Some(Spending {
+                max: 2,
+                remaining: 0,
+                dropped: 2,
+            })
+        );
+
+        // the budget resets the next day
+        let tomorrow = today.succ_opt().unwrap();
+        assert_eq!(admitted(alert("e"), tomorrow).as_deref(), Some("e"));
+        assert_eq!(budget.spending(tomorrow).unwrap().remaining, 1);
+        assert_eq!(AlertBudget::default().spending(today), None);
+    }
+}
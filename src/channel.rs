@@ -0,0 +1,238 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+
+use flume::{Receiver, Sender, TrySendError};
+use log::{debug, info, warn};
+
+use crate::alert::Alert;
+use crate::config::{Config, OverflowPolicy};
+use crate::error::NotifierError;
+use crate::metrics::Metrics;
+
+/// The sending side of the alert channel, applying the configured overflow policy.
+#[derive(Clone)]
+pub struct AlertSender {
+    // shared by the clones, taken out to close the channel whichever clones are left
+    tx: Arc<RwLock<Option<Sender<Alert>>>>,
+    // a receiver handle used to evict the oldest alert, only for `drop_oldest`
+    rx: Option<Receiver<Alert>>,
+    policy: OverflowPolicy,
+    metrics: Arc<Metrics>,
+    // all the alerts are muted in maintenance mode
+    maintenance: Arc<AtomicBool>,
+}
+
+/// Creates the alert channel, bounded when `channel_capacity` is set.
+pub fn channel(config: &Config, metrics: Arc<Metrics>) -> (AlertSender, Receiver<Alert>) {
+    let (tx, rx) = match config.channel_capacity {
+        0 => flume::unbounded(),
+        capacity => flume::bounded(capacity),
+    };
+    let evict = (config.channel_capacity > 0
+        && config.overflow_policy == OverflowPolicy::DropOldest)
+        .then(|| rx.clone());
+    let sender = AlertSender {
+        tx: Arc::new(RwLock::new(Some(tx))),
+        rx: evict,
+        policy: config.overflow_policy,
+        metrics,
+        maintenance: Arc::default(),
+    };
+    (sender, rx)
+}
+
+impl AlertSender {
+    /// Sends an alert, dropped alerts are counted and do not return an error.
+    pub async fn send(&self, alert: Alert) -> Result<(), NotifierError> {
+        if self.maintenance.load(Ordering::Relaxed) {
+            debug!("Maintenance mode, muted: {}", alert.message);
+            return Ok(());
+        }
+        self.deliver(alert).await
+    }
+
+    /// Mutes all the alerts while `on`, with a notice sent when the mode changes.
+    pub async fn set_maintenance(&self, on: bool) -> Result<(), NotifierError> {
+        if self.maintenance.swap(on, Ordering::Relaxed) == on {
+            return Ok(());
+        }
+        let notice = if on {
+            "🚧 Entering maintenance mode, alerts are muted"
+        } else {
+            "✅ Leaving maintenance mode, alerts are sent again"
+        };
+        info!("{}", notice);
+        self.deliver(Alert::new(notice)).await
+    }
+
+    /// Closes the channel, the notifier gets the queued alerts and then exits even if
+    /// some tasks still hold a sender. The alerts sent afterwards fail.
+    pub fn close(&self) {
+        self.tx.write().unwrap().take();
+    }
+
+    async fn deliver(&self, alert: Alert) -> Result<(), NotifierError> {
+        // the lock is not held across the send, the clone keeps it open meanwhile
+        let Some(tx) = self.tx.read().unwrap().clone() else {
+            return Err(NotifierError::Closed);
+        };
+        match self.policy {
+            OverflowPolicy::Block => tx
+                .send_async(alert)
+                .await
+                .map_err(|_| NotifierError::Closed)?,
+            OverflowPolicy::DropNew => match tx.try_send(alert) {
+                Err(TrySendError::Full(alert)) => self.dropped(&alert),
+                res => res.map_err(|_| NotifierError::Closed)?,
+            },
+            OverflowPolicy::DropOldest => {
+                let mut alert = alert;
+                loop {
+                    match tx.try_send(alert) {
+                        Err(TrySendError::Full(a)) => {
+                            alert = a;
+                            if let Some(oldest) = self.rx.as_ref().and_then(|rx| rx.try_recv().ok())
+                            {
+                                self.dropped(&oldest);
+                            }
+                        }
+                        res => break res.map_err(|_| NotifierError::Closed)?,
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of alerts waiting for the notifier.
+    pub fn len(&self) -> usize {
+        self.tx.read().unwrap().as_ref().map_or(0, Sender::len)
+    }
+
+    fn dropped(&self, alert: &Alert) {
+        let dropped = self.metrics.alerts_dropped.fetch_add(1, Ordering::Relaxed) + 1;
+        // log the first drop and then every 100 to avoid flooding our own logs
+        if dropped == 1 || dropped.is_multiple_of(100) {
+            warn!(
+                "Alert channel full, {} alerts dropped so far, dropped: {}",
+                dropped, alert.message
+            );
+        }
+    }
+}
+
+/// Forwards the alerts to the notifiers they are routed to, all of them for alerts
+/// without a route, until the alert channel closes.
+pub async fn fan_out(rx: Receiver<Alert>, txs: Vec<(&str, Sender<Alert>)>) {
+    while let Ok(alert) = rx.recv_async().await {
+        let routed = txs
+            .iter()
+            .filter(|(name, _)| alert.notify.is_empty() || alert.notify.iter().any(|n| n == name));
+        for (_, tx) in routed {
+            // a notifier that exited does not stop the others
+            if tx.send_async(alert.clone()).await.is_err() {
+                warn!("Notifier channel closed, dropped: {}", alert.message);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn config(capacity: usize, policy: &str) -> Config {
+        toml::from_str(&format!(
+            r#"
+            slack_webhook_url = ""
+            systemd_unit = ""
+            channel_capacity = {capacity}
+            overflow_policy = "{policy}"
+            "#
+        ))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_drop_new() {
+        let metrics = Arc::new(Metrics::default());
+        let (tx, rx) = channel(&config(2, "drop_new"), metrics.clone());
+        for i in 0..4 {
+            tx.send(Alert::new(i.to_string())).await.unwrap();
+        }
+        let messages = rx.drain().map(|a| a.message).collect::<Vec<_>>();
+        assert_eq!(messages, ["0", "1"]);
+        assert_eq!(metrics.alerts_dropped.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest() {
+        let metrics = Arc::new(Metrics::default());
+        let (tx, rx) = channel(&config(2, "drop_oldest"), metrics.clone());
+        for i in 0..4 {
+            tx.send(Alert::new(i.to_string())).await.unwrap();
+        }
+        let messages = rx.drain().map(|a| a.message).collect::<Vec<_>>();
+        assert_eq!(messages, ["2", "3"]);
+        assert_eq!(metrics.alerts_dropped.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_maintenance() {
+        let (tx, rx) = channel(&config(0, "block"), Arc::default());
+        tx.set_maintenance(true).await.unwrap();
+        tx.set_maintenance(true).await.unwrap();
+        tx.send(Alert::new("muted")).await.unwrap();
+        tx.set_maintenance(false).await.unwrap();
+        tx.send(Alert::new("sent")).await.unwrap();
+        let messages = rx.drain().map(|a| a.message).collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                "🚧 Entering maintenance mode, alerts are muted",
+                "✅ Leaving maintenance mode, alerts are sent again",
+                "sent"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_close() {
+        let (tx, rx) = channel(&config(0, "block"), Arc::default());
+        // e.g. a task that still holds a sender
+        let held = tx.clone();
+        tx.send(Alert::new("queued")).await.unwrap();
+        tx.close();
+        // the queued alert is received, then the channel is closed right away
+        assert_eq!(rx.recv_async().await.unwrap().message, "queued");
+        let closed = tokio::time::timeout(Duration::from_millis(100), rx.recv_async()).await;
+        assert!(matches!(closed, Ok(Err(_))));
+        // so the notifier flushing the queue exits once it is empty
+        let slack = crate::slack::Slack::new(Vec::new());
+        let flushed = tokio::time::timeout(Duration::from_millis(100), slack.start(rx)).await;
+        assert!(matches!(flushed, Ok(Ok(()))));
+        assert!(held.send(Alert::new("late")).await.is_err());
+        assert_eq!(held.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_fan_out() {
+        let (tx, rx) = flume::unbounded();
+        let (slack_tx, slack_rx) = flume::unbounded();
+        let (pushover_tx, pushover_rx) = flume::unbounded();
+        tx.send(Alert::new("everywhere")).unwrap();
+        tx.send(Alert {
+            notify: vec!["pushover".to_string()],
+            ..Alert::new("phone only")
+        })
+        .unwrap();
+        drop(tx);
+        fan_out(rx, vec![("slack", slack_tx), ("pushover", pushover_tx)]).await;
+
+        let messages = |rx: Receiver<Alert>| rx.drain().map(|a| a.message).collect::<Vec<_>>();
+        assert_eq!(messages(slack_rx), ["everywhere"]);
+        assert_eq!(messages(pushover_rx), ["everywhere", "phone only"]);
+    }
+}
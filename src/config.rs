@@ -1,30 +1,932 @@
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
+use std::path::Path;
+use std::time::Duration;
 
-use anyhow::{Context, Result};
-use log::info;
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Local, Utc};
+use log::{info, warn};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::error::ConfigError;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
-    pub slack_webhook_url: String,
+    // version of the config format, the current one when unset
+    #[serde(default)]
+    pub version: u32,
+    // webhooks every alert is posted to, a single string or a list
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub slack_webhook_url: Vec<String>,
+    // file containing the webhook url, e.g. a mounted secret, instead of the url itself
+    #[serde(default)]
+    pub slack_webhook_url_file: String,
+    // extra headers of the webhook requests, `${VAR}` in the values reads the environment
+    #[serde(default)]
+    pub webhook_headers: BTreeMap<String, String>,
+    // bot token to post with the Web API instead of the webhook, enables threading
+    #[serde(default)]
+    pub slack_token: String,
+    // channel the Web API posts to
+    #[serde(default)]
+    pub slack_channel: String,
+    // channels the Web API posts to per severity, slack_channel when unset
+    #[serde(default)]
+    pub slack_channels: SlackChannels,
+    // emoji reactions the Web API adds to the alerts per severity, e.g. "fire"
+    #[serde(default)]
+    pub slack_reactions: SlackReactions,
+    // seconds during which alerts of a rule are threaded under its first one, disabled when 0
+    #[serde(default)]
+    pub slack_thread_ttl: u64,
+    // post the recovery of a heartbeat as a reply to its miss, with the Web API
+    #[serde(default)]
+    pub slack_thread_heartbeats: bool,
+    // how the journal context of the alerts is attached, uploads need the Web API
+    #[serde(default)]
+    pub slack_context_upload: ContextUpload,
+    // add an Acknowledge button to the alerts posted with the Web API, disabled when unset
+    #[serde(default)]
+    pub slack_ack: Option<SlackAckConfig>,
+    #[serde(default)]
+    pub slack_identity: SlackIdentity,
+    #[serde(default)]
     pub systemd_unit: String,
+    // syslog identifiers to filter on, a single string or a list
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub syslog_identifier: Vec<String>,
+    // units whose entries are ignored, for monitoring all logs but a few chatty units
+    #[serde(default)]
+    pub exclude_units: Vec<String>,
+    // journal transports (_TRANSPORT) whose entries are matched, all when empty,
+    // and those whose entries are ignored, e.g. "kernel"
+    #[serde(default)]
+    pub transports: Vec<String>,
+    #[serde(default)]
+    pub exclude_transports: Vec<String>,
+    // boot to scope the journal to, e.g. "0" for the current boot or "-1" for the previous one
+    #[serde(default)]
+    pub boot: Option<String>,
+    // read the journal with timestamps and include them in the alerts
+    #[serde(default)]
+    pub include_timestamp: bool,
+    // read the journal with timestamps and ignore the lines logged before the start
+    #[serde(default)]
+    pub quiet_start: bool,
+    // timezone the timestamps in the alerts are shown in, UTC by default
+    #[serde(default)]
+    pub timezone: Timezone,
+    // read the journal as JSON and derive the severity of rules without one from PRIORITY
+    #[serde(default)]
+    pub severity_from_priority: bool,
+    // alert once for every rule matching a line instead of the first one only
+    #[serde(default)]
+    pub match_all: bool,
+    // coarse pattern passed to journalctl --grep, must not exclude any rule's matches
+    #[serde(default)]
+    pub prefilter: String,
+    // how the journal is read, journalctl or natively with libsystemd
+    #[serde(default)]
+    pub journal_source: JournalSource,
+    // journal directories read instead of the local journal, e.g. of remote journals,
+    // with one journalctl --directory each
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub journal_directory: Vec<String>,
     #[serde(default)]
     pub heartbeat_interval: u64,
+    // seconds after startup before heartbeats are evaluated
+    #[serde(default)]
+    pub startup_grace: u64,
+    // seconds consecutive identical lines are held back to be matched once, disabled when 0
+    #[serde(default)]
+    pub collapse_repeats_secs: u64,
+    // seconds after startup during which rule alerts are only counted, then summarized
+    #[serde(default)]
+    pub startup_suppress_secs: u64,
+    // random fraction of the heartbeat interval added or removed from each wait
+    #[serde(default)]
+    pub heartbeat_jitter: f64,
+    // seconds between sweeps of stale heartbeat state
+    #[serde(default)]
+    pub heartbeat_sweep_interval: u64,
+    // seconds during which heartbeat recoveries are batched in one message, disabled when 0
+    #[serde(default)]
+    pub recovery_batch_window: u64,
+    // seconds without any journal line before alerting, disabled when 0
+    #[serde(default)]
+    pub stream_idle_timeout: u64,
     #[serde(default)]
     pub alerts: Vec<AlertRule>,
     #[serde(default)]
     pub heartbeats: Vec<HeartbeatRule>,
+    // independent groups of units with their own rules, replacing the top level ones
+    #[serde(default)]
+    pub monitors: Vec<MonitorConfig>,
+    // maximum compiled size of each rule pattern in bytes, defaults to 1 MiB
+    #[serde(default)]
+    pub regex_size_limit: usize,
+    // lines are truncated to this many bytes before matching, disabled when 0
+    #[serde(default)]
+    pub max_line_length: usize,
+    // format of the message bodies, whose keys are added to the journal fields
+    #[serde(default)]
+    pub body_format: BodyFormat,
+    // lines matched per second by each monitor, the ones above are dropped, disabled when 0
+    #[serde(default)]
+    pub max_lines_per_sec: u64,
+    // ignore the empty and whitespace only lines instead of matching them
+    #[serde(default = "default_true")]
+    pub skip_blank_lines: bool,
+    // transformations applied to every alert message, after the rule ones
+    #[serde(default)]
+    pub transforms: Vec<TransformRule>,
+    // how long duplicates are suppressed, per severity
+    #[serde(default)]
+    pub suppression_ttl: SuppressionTtl,
+    // how failed sends are retried by the notifiers posting over HTTP
+    #[serde(default)]
+    pub send_retry: SendRetry,
+    // steps building the text duplicates are detected on from the message, the whole
+    // message when empty
+    #[serde(default)]
+    pub dedup_key: Vec<DedupStep>,
+    // maximum number of messages tracked by the suppression, unbounded when 0
+    #[serde(default)]
+    pub max_suppression_entries: usize,
+    // number of identical messages sent before the following ones are suppressed
+    #[serde(default)]
+    pub suppress_after: usize,
+    // suppress the duplicates once in front of all the notifiers instead of in each one
+    #[serde(default)]
+    pub shared_suppression: bool,
+    // alerts sent per day before the following ones are dropped until midnight,
+    // unlimited when 0
+    #[serde(default)]
+    pub max_alerts_per_day: usize,
+    // send the alerts of the always_notify rules over the daily budget
+    #[serde(default)]
+    pub budget_bypass_always_notify: bool,
+    // label shown before the messages, per severity
+    #[serde(default)]
+    pub severity_labels: SeverityLabels,
+    // push the alerts to phones with Pushover, disabled when unset
+    #[serde(default)]
+    pub pushover: Option<PushoverConfig>,
+    // write the alerts to the local syslog, disabled when unset
+    #[serde(default)]
+    pub syslog: Option<SyslogConfig>,
+    // publish the alerts to an MQTT broker, disabled when unset
+    #[serde(default)]
+    pub mqtt: Option<MqttConfig>,
+    // append the alerts to a local file, disabled when unset
+    #[serde(default)]
+    pub file_log: Option<FileLogConfig>,
+    // create Opsgenie alerts, closed on recovery, disabled when unset
+    #[serde(default)]
+    pub opsgenie: Option<OpsgenieConfig>,
+    // redis used to deduplicate alerts across instances, disabled when empty
+    #[serde(default)]
+    pub redis_url: String,
+    // seconds during which other instances suppress an alert already reported
+    #[serde(default)]
+    pub redis_dedup_window: u64,
+    // seconds between checks of the config file for changes to reload, disabled when 0
+    #[serde(default)]
+    pub auto_reload_secs: u64,
+    // mute all the alerts while processing the logs, e.g. during a migration
+    #[serde(default)]
+    pub maintenance: bool,
+    // seconds after which the process exits to be restarted by systemd, disabled when 0
+    #[serde(default)]
+    pub max_uptime_secs: u64,
+    // URL pinged while the journals are read, e.g. a healthchecks.io check, disabled when empty
+    #[serde(default)]
+    pub deadman_url: String,
+    // seconds between the pings of deadman_url
+    #[serde(default)]
+    pub deadman_interval: u64,
+    // path of the unix socket accepting control commands, disabled when empty
+    #[serde(default)]
+    pub control_socket: String,
+    // number of sent alerts the control socket lists with `recent`
+    #[serde(default)]
+    pub recent_alerts: usize,
+    // capacity of the alert channel, unbounded when 0
+    #[serde(default)]
+    pub channel_capacity: usize,
+    // what to do when the alert channel is full
+    #[serde(default)]
+    pub overflow_policy: OverflowPolicy,
+    // what to do when the notifier fails
+    #[serde(default)]
+    pub on_notifier_failure: FailurePolicy,
+    // what to do when a processor fails reading the journal
+    #[serde(default)]
+    pub on_processor_failure: FailurePolicy,
+
+    // send a message when the tool starts
+    #[serde(default = "default_true")]
+    pub notify_on_start: bool,
+    // number of alerts sent at the same time
+    #[serde(default)]
+    pub send_concurrency: usize,
+    // text wrapped around every message, outside of the rule prefixes
+    #[serde(default)]
+    pub message_prefix: String,
+    #[serde(default)]
+    pub message_suffix: String,
+    // use Slack formatting in messages, e.g. to highlight the matched text
+    #[serde(default)]
+    pub rich_formatting: bool,
+    // format of the tool's own logs
+    #[serde(default)]
+    pub log_format: LogFormat,
 
     // this are internal settings
     // this is the interval to print processed journal entries count
     #[serde(default)]
-    pub print_count_interval: u128,
+    pub print_count_interval: u64,
+    // number of queued alerts after which a backlog warning is logged
+    #[serde(default)]
+    pub backlog_warn_threshold: usize,
+    // capacity of the buffer journalctl output is read into, per monitor
+    #[serde(default)]
+    pub buffer_size_bytes: usize,
+    // keys of the config file that are not settings, e.g. typos, ignored
+    #[serde(skip)]
+    pub unknown_keys: Vec<String>,
+}
+
+/// A group of units followed by its own journalctl, with its own rules.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MonitorConfig {
+    // name used in the logs, defaults to `monitor-<index>`
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub systemd_unit: String,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub syslog_identifier: Vec<String>,
+    // the body format of the monitor, the top level one when unset
+    #[serde(default)]
+    pub body_format: Option<BodyFormat>,
+    // the line rate limit of the monitor, the top level one when unset
+    #[serde(default)]
+    pub max_lines_per_sec: Option<u64>,
+    // the transports of the monitor, the top level ones when empty
+    #[serde(default)]
+    pub transports: Vec<String>,
+    #[serde(default)]
+    pub exclude_transports: Vec<String>,
+    #[serde(default)]
+    pub alerts: Vec<AlertRule>,
+    #[serde(default)]
+    pub heartbeats: Vec<HeartbeatRule>,
+}
+
+/// Username and icons the Slack messages are posted with, unset fields keep the
+/// webhook defaults.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SlackIdentity {
+    pub username: String,
+    pub icon_emoji: String,
+    pub icon_url: String,
+    // icon used for heartbeat misses and recoveries instead of `icon_emoji`
+    pub heartbeat_icon_emoji: String,
+}
+
+/// Severity of an alert.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Critical,
+    #[default]
+    Warning,
+    Info,
+}
+
+impl Severity {
+    /// Maps a syslog priority to a severity: emerg to err are critical, warning is
+    /// a warning, and notice to debug are informational.
+    pub fn from_priority(priority: u8) -> Self {
+        match priority {
+            0..=3 => Severity::Critical,
+            4 => Severity::Warning,
+            _ => Severity::Info,
+        }
+    }
+}
+
+/// Seconds during which duplicates of an alert are suppressed, per severity.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SuppressionTtl {
+    pub critical: u64,
+    pub warning: u64,
+    pub info: u64,
+}
+
+impl Default for SuppressionTtl {
+    fn default() -> Self {
+        // critical alerts re-fire quickly, informational ones stay quiet longer
+        SuppressionTtl {
+            critical: 600,
+            warning: 3600,
+            info: 14400,
+        }
+    }
+}
+
+impl SuppressionTtl {
+    pub fn get(&self, severity: Severity) -> Duration {
+        Duration::from_secs(match severity {
+            Severity::Critical => self.critical,
+            Severity::Warning => self.warning,
+            Severity::Info => self.info,
+        })
+    }
+}
+
+/// A step of the pipeline building the text duplicates are detected on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DedupStep {
+    Lowercase,
+    // removes the digits, e.g. of ids and durations
+    StripDigits,
+    // keeps the first words
+    FirstWords(usize),
+    // keeps the first capture group of the pattern, or its match, when it matches
+    Capture(String),
+}
+
+/// How a failed send is retried, waiting `delay_ms` before the first retry and
+/// doubling the delay after each one.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SendRetry {
+    // attempts before giving up, including the first one
+    pub attempts: u32,
+    pub delay_ms: u64,
+}
+
+impl Default for SendRetry {
+    fn default() -> Self {
+        SendRetry {
+            attempts: 3,
+            delay_ms: 250,
+        }
+    }
+}
+
+impl SendRetry {
+    /// The delay before the first retry.
+    pub fn delay(&self) -> Duration {
+        Duration::from_millis(self.delay_ms)
+    }
 }
 
+/// Pushover credentials and which alerts are pushed. Critical alerts are sent with
+/// the emergency priority, repeated every `retry` seconds until acknowledged or
+/// `expire` seconds have passed.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PushoverConfig {
+    pub token: String,
+    pub user: String,
+    pub severities: Vec<Severity>,
+    pub retry: u64,
+    pub expire: u64,
+    // override the global send_retry and suppression_ttl
+    pub send_retry: Option<SendRetry>,
+    pub suppression_ttl: Option<SuppressionTtl>,
+}
+
+impl Default for PushoverConfig {
+    fn default() -> Self {
+        PushoverConfig {
+            token: String::new(),
+            user: String::new(),
+            severities: vec![Severity::Critical],
+            retry: 60,
+            expire: 3600,
+            send_retry: None,
+            suppression_ttl: None,
+        }
+    }
+}
+
+/// Syslog facility, identifier and the severities of the alerts written.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyslogConfig {
+    pub facility: String,
+    pub ident: String,
+    pub severities: Vec<Severity>,
+    // suppress duplicates for these windows, every alert is written when unset
+    pub suppression_ttl: Option<SuppressionTtl>,
+}
+
+impl Default for SyslogConfig {
+    fn default() -> Self {
+        SyslogConfig {
+            facility: "daemon".to_string(),
+            ident: "journal-alerts".to_string(),
+            severities: vec![Severity::Critical, Severity::Warning, Severity::Info],
+            suppression_ttl: None,
+        }
+    }
+}
+
+/// MQTT broker the alerts are published to, under `<topic>/<severity>`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    pub password: String,
+    pub tls: bool,
+    pub client_id: String,
+    pub topic: String,
+    pub severities: Vec<Severity>,
+    // suppress duplicates for these windows, every alert is published when unset
+    pub suppression_ttl: Option<SuppressionTtl>,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        MqttConfig {
+            host: String::new(),
+            port: 1883,
+            username: String::new(),
+            password: String::new(),
+            tls: false,
+            client_id: "journal-alerts".to_string(),
+            topic: "journal-alerts".to_string(),
+            severities: vec![Severity::Critical, Severity::Warning, Severity::Info],
+            suppression_ttl: None,
+        }
+    }
+}
+
+/// File the alerts are appended to, rotated once it reaches `max_size_bytes`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FileLogConfig {
+    pub path: String,
+    // size above which the file is rotated to `<path>.1`, never rotated when 0
+    pub max_size_bytes: u64,
+    // rotated files kept, `<path>.1` being the most recent
+    pub max_files: usize,
+    pub severities: Vec<Severity>,
+}
+
+impl Default for FileLogConfig {
+    fn default() -> Self {
+        FileLogConfig {
+            path: String::new(),
+            max_size_bytes: 0,
+            max_files: 5,
+            severities: vec![Severity::Critical, Severity::Warning, Severity::Info],
+        }
+    }
+}
+
+/// The endpoint Slack calls back when the Acknowledge button of an alert is clicked,
+/// the following duplicates of an acknowledged alert are suppressed for `window`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SlackAckConfig {
+    // address the interactivity endpoint listens on, behind a proxy terminating TLS
+    pub listen: String,
+    // signing secret of the Slack app, the requests without its signature are refused
+    pub signing_secret: String,
+    // seconds the duplicates of an acknowledged alert are suppressed
+    pub window: u64,
+}
+
+impl Default for SlackAckConfig {
+    fn default() -> Self {
+        SlackAckConfig {
+            listen: "127.0.0.1:3000".to_string(),
+            signing_secret: String::new(),
+            window: 3600,
+        }
+    }
+}
+
+/// Opsgenie API key and which alerts are created. The alerts of a rule or heartbeat
+/// share an alias, so Opsgenie counts the repeats of an open alert, and are closed
+/// when the rule is resolved or the heartbeat recovers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OpsgenieConfig {
+    pub api_key: String,
+    // base URL of the API, e.g. https://api.eu.opsgenie.com for the EU instance
+    pub api_url: String,
+    pub severities: Vec<Severity>,
+    // override the global send_retry
+    pub send_retry: Option<SendRetry>,
+}
+
+impl Default for OpsgenieConfig {
+    fn default() -> Self {
+        OpsgenieConfig {
+            api_key: String::new(),
+            api_url: "https://api.opsgenie.com".to_string(),
+            severities: vec![Severity::Critical],
+            send_retry: None,
+        }
+    }
+}
+
+/// Labels shown before the messages per severity, e.g. `🔴 CRIT`, empty ones are not shown.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SeverityLabels {
+    pub critical: String,
+    pub warning: String,
+    pub info: String,
+}
+
+impl SeverityLabels {
+    pub fn get(&self, severity: Severity) -> &str {
+        match severity {
+            Severity::Critical => &self.critical,
+            Severity::Warning => &self.warning,
+            Severity::Info => &self.info,
+        }
+    }
+}
+
+/// Slack channels the alerts are posted to per severity, empty ones use `slack_channel`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SlackChannels {
+    pub critical: String,
+    pub warning: String,
+    pub info: String,
+}
+
+impl SlackChannels {
+    pub fn get(&self, severity: Severity) -> Option<&str> {
+        let channel = match severity {
+            Severity::Critical => &self.critical,
+            Severity::Warning => &self.warning,
+            Severity::Info => &self.info,
+        };
+        (!channel.is_empty()).then_some(channel.as_str())
+    }
+}
+
+/// Emoji the alerts are reacted with per severity, by name with or without the
+/// colons, none when empty.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SlackReactions {
+    pub critical: String,
+    pub warning: String,
+    pub info: String,
+}
+
+impl SlackReactions {
+    pub fn get(&self, severity: Severity) -> Option<&str> {
+        let reaction = match severity {
+            Severity::Critical => &self.critical,
+            Severity::Warning => &self.warning,
+            Severity::Info => &self.info,
+        };
+        let reaction = reaction.trim_matches(':');
+        (!reaction.is_empty()).then_some(reaction)
+    }
+}
+
+/// Format of the logs emitted by the tool itself.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Human readable lines, the env_logger default.
+    #[default]
+    Plain,
+    /// One JSON object per line.
+    Json,
+}
+
+/// How the journal is read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JournalSource {
+    /// Follow the output of a journalctl process.
+    #[default]
+    Journalctl,
+    /// Read the journal files with libsystemd, needs the `native-journal` feature.
+    Native,
+}
+
+/// The timezone timestamps are shown in: `UTC`, `local` for the one of the host, or
+/// a named one such as `Europe/Berlin`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Timezone {
+    #[default]
+    Utc,
+    Local,
+    Named(chrono_tz::Tz),
+}
+
+impl Timezone {
+    /// Formats the time as ISO 8601 in the timezone, e.g. `2025-01-01T11:00:00+01:00`,
+    /// UTC times end with `Z`.
+    pub fn format(self, time: DateTime<Utc>) -> String {
+        const FORMAT: &str = "%Y-%m-%dT%H:%M:%S%:z";
+        match self {
+            Timezone::Utc => time.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+            Timezone::Local => time.with_timezone(&Local).format(FORMAT).to_string(),
+            Timezone::Named(tz) => time.with_timezone(&tz).format(FORMAT).to_string(),
+        }
+    }
+}
+
+impl TryFrom<String> for Timezone {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        match name.as_str() {
+            "UTC" | "utc" => Ok(Timezone::Utc),
+            "local" => Ok(Timezone::Local),
+            _ => name.parse().map(Timezone::Named).map_err(|_| {
+                format!(
+                    "unknown timezone '{name}', expected UTC, local or a name such as Europe/Berlin"
+                )
+            }),
+        }
+    }
+}
+
+impl From<Timezone> for String {
+    fn from(timezone: Timezone) -> Self {
+        match timezone {
+            Timezone::Utc => "UTC".to_string(),
+            Timezone::Local => "local".to_string(),
+            Timezone::Named(tz) => tz.name().to_string(),
+        }
+    }
+}
+
+/// What to do when a bounded alert channel is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Wait for the notifier to make room.
+    #[default]
+    Block,
+    /// Discard the oldest queued alert to make room for the new one.
+    DropOldest,
+    /// Discard the new alert.
+    DropNew,
+}
+
+/// The format of the message bodies, whose keys are added to the fields of the entries.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BodyFormat {
+    /// Plain text, no keys.
+    #[default]
+    Raw,
+    /// A JSON object, e.g. `{"level":"error","status":500}`.
+    Json,
+    /// Key value pairs, e.g. `level=error msg="disk full"`.
+    Logfmt,
+}
+
+/// The encoding of a capture group decoded by a rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    /// Standard or URL safe base64, the padding is optional.
+    Base64,
+    /// Hexadecimal digits, two per byte.
+    Hex,
+}
+
+/// The engine a rule pattern is compiled with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RegexEngine {
+    /// The `regex` crate, matching in linear time.
+    #[default]
+    Regex,
+    /// The `fancy-regex` crate, adding backreferences and lookaround at the cost of
+    /// backtracking, which can be slow on some patterns and lines.
+    Fancy,
+}
+
+/// What to do when the notifier or a processor fails. Fatal errors, such as an
+/// invalid config or a missing permission, always exit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailurePolicy {
+    /// Exit with the error, for systemd to restart the process.
+    #[default]
+    Exit,
+    /// Log the error and start the task again after a backoff. The alerts queued
+    /// meanwhile are kept, as is the heartbeat state of a processor.
+    Restart,
+}
+
+/// How the journal context of an alert is attached to its Slack message.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContextUpload {
+    /// A code block at the end of the message.
+    #[default]
+    Inline,
+    /// A text file uploaded in the thread of the message, with the Web API.
+    File,
+    /// A gzip compressed file uploaded in the thread of the message, with the Web API.
+    Gzip,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct AlertRule {
+    // name used to refer to the rule at runtime, defaults to `alert-<index>`
+    #[serde(default)]
+    pub name: String,
+    // may be left out with `all_of`, any of its patterns then matches
+    #[serde(default)]
     pub pattern: String,
     pub prefix: String,
+    // rules with a higher priority are matched first, ties resolve by config order
+    #[serde(default)]
+    pub priority: i32,
+    // severity of the alerts of this rule, defaults to warning
+    #[serde(default)]
+    pub severity: Option<Severity>,
+    // never suppress duplicates of this rule, can cause spam if misused
+    #[serde(default)]
+    pub always_notify: bool,
+    // number of recent journal lines attached to the alerts, 0 to disable
+    #[serde(default)]
+    pub context_lines: usize,
+    // transformations applied to the matched message of this rule
+    #[serde(default)]
+    pub transforms: Vec<TransformRule>,
+    // names of the notifiers receiving the alerts of this rule, all of them when empty
+    #[serde(default)]
+    pub notify: Vec<String>,
+    // what the alert means, shown under the message
+    #[serde(default)]
+    pub description: String,
+    // link to the runbook, shown under the message
+    #[serde(default)]
+    pub runbook_url: String,
+    // pattern of the line resolving the alert, sends a notice and clears its suppression
+    #[serde(default)]
+    pub resolve_pattern: String,
+    // text of the alerts instead of the matched line, with `{name}` placeholders for
+    // the captures and journal fields
+    #[serde(default)]
+    pub template: String,
+    // times of day during which duplicates are suppressed until the window ends
+    #[serde(default)]
+    pub suppression_windows: Vec<SuppressionWindow>,
+    // journal fields expected on the matching lines, the rule only alerts on the
+    // lines where one of them is missing or empty
+    #[serde(default)]
+    pub require_fields: Vec<String>,
+    // journal fields the matching lines must have with exactly these values, e.g. a
+    // PRIORITY telling the lines a service wrote to stderr
+    #[serde(default)]
+    pub match_fields: BTreeMap<String, String>,
+    // engine the pattern is compiled with, `fancy` for backreferences and lookaround
+    #[serde(default)]
+    pub engine: RegexEngine,
+    // alert only once the pattern matched on that many distinct hosts (`_HOSTNAME`)
+    // within `hosts_window` seconds, 300 by default
+    #[serde(default)]
+    pub min_hosts: Option<usize>,
+    #[serde(default)]
+    pub hosts_window: u64,
+    // patterns that must all match lines of the rule within `all_of_window` seconds,
+    // 5 by default, before it alerts
+    #[serde(default)]
+    pub all_of: Vec<String>,
+    #[serde(default)]
+    pub all_of_window: u64,
+    // fraction of the matches alerting, between 0 and 1, the others are only counted
+    #[serde(default)]
+    pub sample_rate: Option<f64>,
+    // decodes a capture group of the pattern, e.g. an encoded payload, before alerting
+    #[serde(default)]
+    pub decode: Option<Encoding>,
+    // capture group decoded, a number or a name, the first group by default
+    #[serde(default)]
+    pub decode_group: String,
+    // pattern the decoded group must match for the rule to alert, any text when empty
+    #[serde(default)]
+    pub decode_pattern: String,
+}
+
+impl AlertRule {
+    /// The required fields missing or empty in the fields of an entry.
+    pub fn missing_fields<'r>(&'r self, fields: &HashMap<String, String>) -> Vec<&'r str> {
+        self.require_fields
+            .iter()
+            .filter(|field| {
+                fields
+                    .get(*field)
+                    .is_none_or(|value| value.trim().is_empty())
+            })
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Whether the fields of an entry have all the values the rule matches on.
+    pub fn fields_match(&self, fields: &HashMap<String, String>) -> bool {
+        self.match_fields
+            .iter()
+            .all(|(field, value)| fields.get(field) == Some(value))
+    }
+
+    /// Time left in the suppression window the given seconds since midnight fall
+    /// in, the longest one if windows overlap.
+    pub fn suppression_window(&self, now: u32) -> Option<Duration> {
+        self.suppression_windows
+            .iter()
+            .filter_map(|window| window.remaining(now))
+            .max()
+    }
+}
+
+/// A local time of day window, `HH:MM` to `HH:MM`, that may span midnight.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SuppressionWindow {
+    pub start: String,
+    pub end: String,
+}
+
+impl SuppressionWindow {
+    /// Time left in the window at the given seconds since midnight, `None` outside it.
+    pub fn remaining(&self, now: u32) -> Option<Duration> {
+        let (start, end) = (time_of_day(&self.start).ok()?, time_of_day(&self.end).ok()?);
+        let left = if start <= end {
+            (start..end).contains(&now).then(|| end - now)
+        } else {
+            // spans midnight, e.g. 22:00 to 06:00
+            (now >= start || now < end).then(|| (end + DAY_SECS - now) % DAY_SECS)
+        }?;
+        Some(Duration::from_secs(left.into()))
+    }
+}
+
+const DAY_SECS: u32 = 86400;
+
+/// Whether the pattern of the rule has its `decode_group`, a number or a name. An
+/// invalid pattern is reported when the rules are compiled.
+fn has_group(rule: &AlertRule) -> bool {
+    let group = rule.decode_group.as_str();
+    let (len, names): (usize, Vec<String>) = match rule.engine {
+        RegexEngine::Regex => match regex::Regex::new(&rule.pattern) {
+            Ok(re) => (
+                re.captures_len(),
+                re.capture_names().flatten().map(String::from).collect(),
+            ),
+            Err(_) => return true,
+        },
+        RegexEngine::Fancy => match fancy_regex::Regex::new(&rule.pattern) {
+            Ok(re) => (
+                re.captures_len(),
+                re.capture_names().flatten().map(String::from).collect(),
+            ),
+            Err(_) => return true,
+        },
+    };
+    match group.parse::<usize>() {
+        Ok(i) => i > 0 && i < len,
+        Err(_) => names.iter().any(|name| name == group),
+    }
+}
+
+/// Parses a `HH:MM` time of day into seconds since midnight.
+fn time_of_day(time: &str) -> Result<u32, ConfigError> {
+    let parsed = time
+        .split_once(':')
+        .and_then(|(h, m)| Some((h.parse::<u32>().ok()?, m.parse::<u32>().ok()?)))
+        .filter(|(h, m)| *h < 24 && *m < 60 && time.len() == 5);
+    let (hours, minutes) = parsed.ok_or_else(|| {
+        ConfigError::Invalid(format!("Invalid time of day '{time}', expected HH:MM"))
+    })?;
+    Ok(hours * 3600 + minutes * 60)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransformRule {
+    // regex to find in the message
+    pub find: String,
+    // replacement template, can reference capture groups as `$1` or `${name}`
+    pub replace: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -32,42 +934,411 @@ pub struct HeartbeatRule {
     pub pattern: String,
     pub prefix: String,
     pub tolerance: u64,
+    #[serde(default)]
+    pub priority: i32,
+    // engine the pattern is compiled with, `fancy` for backreferences and lookaround
+    #[serde(default)]
+    pub engine: RegexEngine,
+    // overrides the global startup grace for this heartbeat
+    #[serde(default)]
+    pub startup_grace: Option<u64>,
+    // minimum number of matches expected within each tolerance window
+    #[serde(default)]
+    pub min_count: Option<usize>,
+    // consecutive overdue checks before alerting, 0 or 1 alert on the first one
+    #[serde(default)]
+    pub consecutive_misses: usize,
+    // learn the cadence of the matches, overdue this many standard deviations over
+    // their mean interval instead of after the tolerance
+    #[serde(default)]
+    pub cadence_sensitivity: Option<f64>,
+    // intervals between matches the cadence is learned from
+    #[serde(default = "default_cadence_samples")]
+    pub cadence_samples: usize,
+    // names of the alert rules whose suppressed duplicates are cleared on recovery
+    #[serde(default)]
+    pub reset_alerts: Vec<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_cadence_samples() -> usize {
+    DEFAULT_CADENCE_SAMPLES
 }
 
+/// Replaces the `${VAR}` references with the value of the variables, returns the
+/// name of the first one that is not set.
+fn expand_env(value: &str) -> Result<String, String> {
+    let mut out = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        let var = &rest[start + 2..start + len];
+        out.push_str(&rest[..start]);
+        out.push_str(&std::env::var(var).map_err(|_| var.to_string())?);
+        rest = &rest[start + len + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// The dotted path of a config key, e.g. `monitors[0].alerts[1].patern`.
+fn key_path(path: &serde_ignored::Path) -> String {
+    use serde_ignored::Path;
+    match path {
+        Path::Root => String::new(),
+        Path::Seq { parent, index } => format!("{}[{index}]", key_path(parent)),
+        Path::Map { parent, key } => match key_path(parent) {
+            parent if parent.is_empty() => key.clone(),
+            parent => format!("{parent}.{key}"),
+        },
+        Path::Some { parent }
+        | Path::NewtypeStruct { parent }
+        | Path::NewtypeVariant { parent } => key_path(parent),
+    }
+}
+
+/// Deserializes either a single string or a list of strings into a `Vec<String>`.
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(s) => vec![s],
+        OneOrMany::Many(v) => v,
+    })
+}
+
+// version of the config format written for this release
+const CONFIG_VERSION: u32 = 1;
 const DEFAULT_CONFIGS: [&str; 2] = ["config.toml", "/etc/journal-alerts/config.toml"];
 const DEFAULT_HEARTBEAT_INTERVAL: u64 = 30;
-const DEFAULT_PRINT_COUNT_INTERVAL: u128 = 50_000;
+const DEFAULT_HEARTBEAT_SWEEP_INTERVAL: u64 = 3600;
+const DEFAULT_HOSTS_WINDOW: u64 = 300;
+const DEFAULT_ALL_OF_WINDOW: u64 = 5;
+const DEFAULT_CADENCE_SAMPLES: usize = 20;
+const DEFAULT_DEADMAN_INTERVAL: u64 = 60;
+const DEFAULT_PRINT_COUNT_INTERVAL: u64 = 50_000;
+const DEFAULT_BACKLOG_WARN_THRESHOLD: usize = 1_000;
+const DEFAULT_BUFFER_SIZE_BYTES: usize = 8 * 1024 * 1024;
+const MIN_BUFFER_SIZE_BYTES: usize = 64 * 1024;
+const DEFAULT_REDIS_DEDUP_WINDOW: u64 = 300;
+const DEFAULT_SEND_CONCURRENCY: usize = 1;
+const DEFAULT_SUPPRESS_AFTER: usize = 1;
+const DEFAULT_RECENT_ALERTS: usize = 100;
+const PUSHOVER_MIN_RETRY: u64 = 30;
+const PUSHOVER_MAX_EXPIRE: u64 = 10800;
+const REDACTED: &str = "<redacted>";
+// values of the _TRANSPORT journal field
+const TRANSPORTS: [&str; 6] = ["audit", "driver", "syslog", "journal", "stdout", "kernel"];
 
 impl Config {
-    pub fn load(path: Option<String>) -> Result<Self> {
-        let path = match path {
-            Some(p) => p,
+    /// Returns the config file path, the given one or the first default found.
+    pub fn path(path: Option<String>) -> Result<String, ConfigError> {
+        match path {
+            Some(p) => Ok(p),
             None => DEFAULT_CONFIGS
                 .iter()
                 .find(|p| std::path::Path::new(p).exists())
                 .map(|s| s.to_string())
-                .ok_or_else(|| anyhow::anyhow!("No config file found"))?,
-        };
+                .ok_or(ConfigError::NotFound),
+        }
+    }
+
+    /// Parses the config, collecting the keys that are not settings instead of
+    /// silently ignoring them.
+    fn parse(data: &str) -> Result<Self, ConfigError> {
+        let deserializer = toml::Deserializer::parse(data).map_err(ConfigError::Toml)?;
+        let mut unknown_keys = Vec::new();
+        let mut config: Config = serde_ignored::deserialize(deserializer, |path| {
+            unknown_keys.push(key_path(&path));
+        })
+        .map_err(ConfigError::Toml)?;
+        config.unknown_keys = unknown_keys;
+        Ok(config)
+    }
+
+    pub fn load(path: Option<String>) -> Result<Self, ConfigError> {
+        let path = Self::path(path)?;
 
         info!("Loading config from: {path}");
 
-        let data = fs::read_to_string(&path)
-            .with_context(|| format!("Failed to read config file: {}", path))?;
+        let data = fs::read_to_string(&path).map_err(|source| ConfigError::Read {
+            what: "config file",
+            path: path.clone(),
+            source,
+        })?;
 
-        let mut config: Config =
-            toml::from_str(&data).with_context(|| "Invalid TOML in config file")?;
+        let mut config = Self::parse(&data)?;
+        for key in &config.unknown_keys {
+            warn!("Unknown config key '{key}' ignored, check it for typos");
+        }
+        if config.version > CONFIG_VERSION {
+            return Err(ConfigError::Invalid(format!(
+                "Config version {} is newer than the supported version {CONFIG_VERSION}, upgrade journal-alerts",
+                config.version
+            )));
+        }
+        config.version = CONFIG_VERSION;
+
+        // read the webhook from a secret file, it must not be set twice
+        if !config.slack_webhook_url_file.is_empty() {
+            if !config.slack_webhook_url.is_empty() {
+                return Err(ConfigError::Invalid(
+                    "Only one of slack_webhook_url and slack_webhook_url_file can be set"
+                        .to_string(),
+                ));
+            }
+            let path = &config.slack_webhook_url_file;
+            let url = fs::read_to_string(path)
+                .map_err(|source| ConfigError::Read {
+                    what: "slack_webhook_url_file",
+                    path: path.clone(),
+                    source,
+                })?
+                .trim()
+                .to_string();
+            config.slack_webhook_url = vec![url];
+            info!("Slack webhook url read from: {path}");
+        }
+        config.slack_webhook_url.retain(|url| !url.is_empty());
+
+        // secrets of the headers are read from the environment
+        for (name, value) in &mut config.webhook_headers {
+            *value = expand_env(value).map_err(|var| {
+                ConfigError::Invalid(format!(
+                    "Environment variable '{var}' of webhook header '{name}' is not set"
+                ))
+            })?;
+        }
+        config.header_map()?;
+
+        if !config.slack_token.is_empty() && config.slack_channel.is_empty() {
+            return Err(ConfigError::Invalid(
+                "slack_channel is required when slack_token is set".to_string(),
+            ));
+        }
+        let channels = &config.slack_channels;
+        let routed = [Severity::Critical, Severity::Warning, Severity::Info]
+            .into_iter()
+            .any(|severity| channels.get(severity).is_some());
+        if routed && config.slack_token.is_empty() {
+            return Err(ConfigError::Invalid(
+                "slack_channels needs slack_token, webhooks post to a fixed channel".to_string(),
+            ));
+        }
+        let reactions = &config.slack_reactions;
+        let reacted = [Severity::Critical, Severity::Warning, Severity::Info]
+            .into_iter()
+            .any(|severity| reactions.get(severity).is_some());
+        if reacted && config.slack_token.is_empty() {
+            return Err(ConfigError::Invalid(
+                "slack_reactions needs slack_token, webhooks do not return the posted message"
+                    .to_string(),
+            ));
+        }
+        if config.slack_thread_heartbeats && config.slack_token.is_empty() {
+            return Err(ConfigError::Invalid(
+                "slack_thread_heartbeats needs slack_token, webhook messages cannot be threaded"
+                    .to_string(),
+            ));
+        }
+        if let Some(ack) = &config.slack_ack {
+            if config.slack_token.is_empty() {
+                return Err(ConfigError::Invalid(
+                    "slack_ack needs slack_token, the button clicks are sent to a Slack app"
+                        .to_string(),
+                ));
+            }
+            if ack.signing_secret.is_empty() {
+                return Err(ConfigError::Invalid(
+                    "The signing_secret of slack_ack is required".to_string(),
+                ));
+            }
+            if ack.listen.parse::<std::net::SocketAddr>().is_err() {
+                return Err(ConfigError::Invalid(format!(
+                    "The listen address of slack_ack is not valid: {}",
+                    ack.listen
+                )));
+            }
+            if ack.window == 0 {
+                return Err(ConfigError::Invalid(
+                    "The window of slack_ack must be greater than 0".to_string(),
+                ));
+            }
+        }
 
-        if config.heartbeats.is_empty() && config.alerts.is_empty() {
-            return Err(anyhow::anyhow!(
-                "Config must contain at least one alert or heartbeat rule"
+        let retries = std::iter::once(&config.send_retry)
+            .chain(config.pushover.iter().filter_map(|p| p.send_retry.as_ref()))
+            .chain(config.opsgenie.iter().filter_map(|o| o.send_retry.as_ref()));
+        if retries.into_iter().any(|retry| retry.attempts == 0) {
+            return Err(ConfigError::Invalid(
+                "send_retry attempts must be at least 1".to_string(),
             ));
         }
 
-        info!(
-            "Config loaded: {} alert rules, {} heartbeat rules",
-            config.alerts.len(),
-            config.heartbeats.len()
-        );
+        // the limits of the Pushover emergency priority
+        if let Some(pushover) = &config.pushover {
+            if pushover.retry < PUSHOVER_MIN_RETRY {
+                return Err(ConfigError::Invalid(format!(
+                    "pushover retry must be at least {PUSHOVER_MIN_RETRY} seconds"
+                )));
+            }
+            if pushover.expire == 0 || pushover.expire > PUSHOVER_MAX_EXPIRE {
+                return Err(ConfigError::Invalid(format!(
+                    "pushover expire must be between 1 and {PUSHOVER_MAX_EXPIRE} seconds"
+                )));
+            }
+        }
+
+        if config.monitors.is_empty() {
+            validate_rules(
+                "Config",
+                &config.syslog_identifier,
+                &mut config.alerts,
+                &config.heartbeats,
+                "",
+            )?;
+            info!(
+                "Config loaded: {} alert rules, {} heartbeat rules",
+                config.alerts.len(),
+                config.heartbeats.len()
+            );
+        } else {
+            // top level rules would be silently ignored, make it an error
+            if !config.alerts.is_empty() || !config.heartbeats.is_empty() {
+                return Err(ConfigError::Invalid(
+                    "Rules must be defined in the monitors when monitors are configured"
+                        .to_string(),
+                ));
+            }
+            for (i, monitor) in config.monitors.iter_mut().enumerate() {
+                if monitor.name.is_empty() {
+                    monitor.name = format!("monitor-{i}");
+                }
+                // rule names are prefixed so that they are unique across monitors
+                validate_rules(
+                    &format!("Monitor '{}'", monitor.name),
+                    &monitor.syslog_identifier,
+                    &mut monitor.alerts,
+                    &monitor.heartbeats,
+                    &format!("{}-", monitor.name),
+                )?;
+                info!(
+                    "Monitor '{}' loaded: {} alert rules, {} heartbeat rules",
+                    monitor.name,
+                    monitor.alerts.len(),
+                    monitor.heartbeats.len()
+                );
+            }
+        }
+
+        for (name, monitor) in config.monitors() {
+            let owner = if config.monitors.is_empty() {
+                "Config".to_string()
+            } else {
+                format!("Monitor '{name}'")
+            };
+            validate_transports(&owner, &monitor.transports)?;
+            validate_transports(&owner, &monitor.exclude_transports)?;
+        }
+
+        // a missing directory would only fail once journalctl is started
+        if let Some(directory) = config
+            .journal_directory
+            .iter()
+            .find(|directory| !Path::new(directory).is_dir())
+        {
+            return Err(ConfigError::Invalid(format!(
+                "journal_directory '{directory}' is not an existing directory"
+            )));
+        }
+        crate::processor::check_native(&config)?;
+
+        if let Some(syslog) = &config.syslog {
+            crate::syslog::parse_facility(&syslog.facility)?;
+            // the alerts written to syslog end up in the journal, matching the rules again
+            let unfiltered = config.monitors().into_iter().any(|(_, monitor)| {
+                monitor.systemd_unit.is_empty()
+                    && monitor.syslog_identifier.is_empty()
+                    && monitor.exclude_units.is_empty()
+            });
+            if unfiltered {
+                warn!(
+                    "The syslog notifier writes the alerts to the journal read by an unfiltered monitor, exclude the unit of {} with exclude_units",
+                    syslog.ident
+                );
+            }
+        }
+
+        // the notifiers leave the suppression to the shared layer, their windows unused
+        if config.suppression_shared() {
+            let overridden = [
+                (
+                    "pushover",
+                    config.pushover.as_ref().map(|c| &c.suppression_ttl),
+                ),
+                ("syslog", config.syslog.as_ref().map(|c| &c.suppression_ttl)),
+                ("mqtt", config.mqtt.as_ref().map(|c| &c.suppression_ttl)),
+            ];
+            if let Some((notifier, _)) = overridden
+                .iter()
+                .find(|(_, ttl)| ttl.is_some_and(Option::is_some))
+            {
+                return Err(ConfigError::Invalid(format!(
+                    "The suppression_ttl of {notifier} is not used with shared_suppression or max_alerts_per_day, set the global one instead"
+                )));
+            }
+        }
+
+        if let Some(opsgenie) = &config.opsgenie
+            && reqwest::Url::parse(&opsgenie.api_url).is_err()
+        {
+            return Err(ConfigError::Invalid(format!(
+                "The api_url of opsgenie is not a valid URL: {}",
+                opsgenie.api_url
+            )));
+        }
+
+        if let Some(file_log) = &config.file_log
+            && file_log.path.trim().is_empty()
+        {
+            return Err(ConfigError::Invalid(
+                "The path of file_log must not be empty".to_string(),
+            ));
+        }
+
+        // rules can only be routed to the configured notifiers
+        let notifiers = config.notifiers();
+        let rules = config
+            .alerts
+            .iter()
+            .chain(config.monitors.iter().flat_map(|monitor| &monitor.alerts));
+        for rule in rules {
+            if let Some(name) = rule
+                .notify
+                .iter()
+                .find(|n| !notifiers.contains(&n.as_str()))
+            {
+                return Err(ConfigError::Invalid(format!(
+                    "Rule '{}' notifies '{}', configured notifiers are: {}",
+                    rule.name,
+                    name,
+                    notifiers.join(", ")
+                )));
+            }
+        }
 
         // heartbeat interval default
         if config.heartbeat_interval == 0 {
@@ -78,11 +1349,637 @@ impl Config {
             config.heartbeat_interval
         );
 
+        if !(0.0..=1.0).contains(&config.heartbeat_jitter) {
+            return Err(ConfigError::Invalid(
+                "heartbeat_jitter must be a fraction between 0 and 1".to_string(),
+            ));
+        }
+
+        // heartbeat sweep interval default
+        if config.heartbeat_sweep_interval == 0 {
+            config.heartbeat_sweep_interval = DEFAULT_HEARTBEAT_SWEEP_INTERVAL;
+        }
+        if config.deadman_interval == 0 {
+            config.deadman_interval = DEFAULT_DEADMAN_INTERVAL;
+        }
+
+        // shared deduplication window default
+        if config.redis_dedup_window == 0 {
+            config.redis_dedup_window = DEFAULT_REDIS_DEDUP_WINDOW;
+        }
+
+        // alerts are sent one at a time by default
+        if config.send_concurrency == 0 {
+            config.send_concurrency = DEFAULT_SEND_CONCURRENCY;
+        }
+
+        // only the first of identical messages is sent by default
+        if config.suppress_after == 0 {
+            config.suppress_after = DEFAULT_SUPPRESS_AFTER;
+        }
+
+        // alerts listed by the recent control command
+        if config.recent_alerts == 0 {
+            config.recent_alerts = DEFAULT_RECENT_ALERTS;
+        }
+
         // print count interval
         if config.print_count_interval == 0 {
             config.print_count_interval = DEFAULT_PRINT_COUNT_INTERVAL;
         }
 
+        // backlog warning threshold
+        if config.backlog_warn_threshold == 0 {
+            config.backlog_warn_threshold = DEFAULT_BACKLOG_WARN_THRESHOLD;
+        }
+
+        // read buffer size, small buffers would not absorb any burst
+        if config.buffer_size_bytes == 0 {
+            config.buffer_size_bytes = DEFAULT_BUFFER_SIZE_BYTES;
+        } else if config.buffer_size_bytes < MIN_BUFFER_SIZE_BYTES {
+            return Err(ConfigError::Invalid(format!(
+                "buffer_size_bytes must be at least {} bytes",
+                MIN_BUFFER_SIZE_BYTES
+            )));
+        }
+
         Ok(config)
     }
+
+    /// The webhook headers, checked to be valid header names and values.
+    pub fn header_map(&self) -> Result<HeaderMap, ConfigError> {
+        self.webhook_headers
+            .iter()
+            .map(|(name, value)| {
+                let invalid = || ConfigError::Invalid(format!("Invalid webhook header '{name}'"));
+                let name = HeaderName::try_from(name).map_err(|_| invalid())?;
+                let value = HeaderValue::try_from(value).map_err(|_| invalid())?;
+                Ok((name, value))
+            })
+            .collect()
+    }
+
+    /// Names of the configured notifiers, the rules can route their alerts to.
+    pub fn notifiers(&self) -> Vec<&'static str> {
+        let mut notifiers = vec!["slack"];
+        if self.pushover.is_some() {
+            notifiers.push("pushover");
+        }
+        if self.syslog.is_some() {
+            notifiers.push("syslog");
+        }
+        if self.mqtt.is_some() {
+            notifiers.push("mqtt");
+        }
+        if self.file_log.is_some() {
+            notifiers.push("file_log");
+        }
+        if self.opsgenie.is_some() {
+            notifiers.push("opsgenie");
+        }
+        notifiers
+    }
+
+    /// Renders the resolved config as TOML or JSON, optionally hiding the secrets.
+    pub fn render(&self, json: bool, redact: bool) -> Result<String, ConfigError> {
+        let mut config = self.clone();
+        if redact {
+            let notifiers = config
+                .pushover
+                .iter_mut()
+                .flat_map(|pushover| [&mut pushover.token, &mut pushover.user])
+                .chain(config.mqtt.iter_mut().map(|mqtt| &mut mqtt.password))
+                .chain(
+                    config
+                        .opsgenie
+                        .iter_mut()
+                        .map(|opsgenie| &mut opsgenie.api_key),
+                )
+                .chain(
+                    config
+                        .slack_ack
+                        .iter_mut()
+                        .map(|ack| &mut ack.signing_secret),
+                );
+            for secret in config
+                .slack_webhook_url
+                .iter_mut()
+                .chain([
+                    &mut config.slack_token,
+                    &mut config.redis_url,
+                    &mut config.deadman_url,
+                ])
+                .chain(config.webhook_headers.values_mut())
+                .chain(notifiers)
+            {
+                if !secret.is_empty() {
+                    *secret = REDACTED.to_string();
+                }
+            }
+        }
+        if json {
+            serde_json::to_string_pretty(&config).map_err(ConfigError::Json)
+        } else {
+            toml::to_string(&config).map_err(ConfigError::TomlSerialize)
+        }
+    }
+
+    /// Whether the duplicates are suppressed once in front of all the notifiers, also
+    /// with a daily budget so that the suppressed duplicates do not spend it.
+    pub fn suppression_shared(&self) -> bool {
+        self.shared_suppression || self.max_alerts_per_day > 0
+    }
+
+    /// Splits the config into one config per monitor, with the monitor's units and
+    /// rules. Without monitors the config itself is the only one.
+    pub fn monitors(&self) -> Vec<(String, Config)> {
+        if self.monitors.is_empty() {
+            return vec![("default".to_string(), self.clone())];
+        }
+        self.monitors
+            .iter()
+            .map(|monitor| {
+                let config = Config {
+                    systemd_unit: monitor.systemd_unit.clone(),
+                    syslog_identifier: monitor.syslog_identifier.clone(),
+                    body_format: monitor.body_format.unwrap_or(self.body_format),
+                    max_lines_per_sec: monitor.max_lines_per_sec.unwrap_or(self.max_lines_per_sec),
+                    transports: or_global(&monitor.transports, &self.transports),
+                    exclude_transports: or_global(
+                        &monitor.exclude_transports,
+                        &self.exclude_transports,
+                    ),
+                    alerts: monitor.alerts.clone(),
+                    heartbeats: monitor.heartbeats.clone(),
+                    monitors: Vec::new(),
+                    ..self.clone()
+                };
+                (monitor.name.clone(), config)
+            })
+            .collect()
+    }
+}
+
+fn or_global(monitor: &[String], global: &[String]) -> Vec<String> {
+    if monitor.is_empty() { global } else { monitor }.to_vec()
+}
+
+/// Checks that the transports are ones journald records in `_TRANSPORT`.
+fn validate_transports(owner: &str, transports: &[String]) -> Result<(), ConfigError> {
+    match transports
+        .iter()
+        .find(|transport| !TRANSPORTS.contains(&transport.as_str()))
+    {
+        Some(transport) => Err(ConfigError::Invalid(format!(
+            "{owner} has an unknown transport '{transport}', expected one of {}",
+            TRANSPORTS.join(", ")
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Checks that a set of rules is usable and names the unnamed alert rules.
+fn validate_rules(
+    owner: &str,
+    syslog_identifier: &[String],
+    alerts: &mut [AlertRule],
+    heartbeats: &[HeartbeatRule],
+    name_prefix: &str,
+) -> Result<(), ConfigError> {
+    if heartbeats.is_empty() && alerts.is_empty() {
+        return Err(ConfigError::Invalid(format!(
+            "{owner} must contain at least one alert or heartbeat rule"
+        )));
+    }
+
+    if syslog_identifier.iter().any(|id| id.trim().is_empty()) {
+        return Err(ConfigError::Invalid(
+            "syslog_identifier entries must not be empty".to_string(),
+        ));
+    }
+
+    // rule names default to their position
+    for (i, rule) in alerts.iter_mut().enumerate() {
+        if rule.name.is_empty() {
+            rule.name = format!("{name_prefix}alert-{i}");
+        }
+        if rule
+            .require_fields
+            .iter()
+            .any(|field| field.trim().is_empty())
+        {
+            return Err(ConfigError::Invalid(format!(
+                "require_fields entries of rule '{}' must not be empty",
+                rule.name
+            )));
+        }
+        if rule.min_hosts == Some(0) {
+            return Err(ConfigError::Invalid(format!(
+                "min_hosts of rule '{}' must be greater than 0",
+                rule.name
+            )));
+        }
+        if rule.hosts_window == 0 {
+            rule.hosts_window = DEFAULT_HOSTS_WINDOW;
+        }
+        if !rule.all_of.is_empty() {
+            if rule.engine == RegexEngine::Fancy {
+                return Err(ConfigError::Invalid(format!(
+                    "all_of of rule '{}' is not supported with the fancy engine",
+                    rule.name
+                )));
+            }
+            // the rule sees the lines matching any of the patterns
+            if rule.pattern.is_empty() {
+                rule.pattern = rule
+                    .all_of
+                    .iter()
+                    .map(|pattern| format!("(?:{pattern})"))
+                    .collect::<Vec<_>>()
+                    .join("|");
+            }
+            if rule.all_of_window == 0 {
+                rule.all_of_window = DEFAULT_ALL_OF_WINDOW;
+            }
+        } else if rule.pattern.is_empty() {
+            return Err(ConfigError::Invalid(format!(
+                "Rule '{}' must have a pattern or all_of",
+                rule.name
+            )));
+        }
+        if rule.decode.is_some() {
+            if rule.decode_group.is_empty() {
+                rule.decode_group = "1".to_string();
+            }
+            if !has_group(rule) {
+                return Err(ConfigError::Invalid(format!(
+                    "The pattern of rule '{}' has no capture group '{}' to decode",
+                    rule.name, rule.decode_group
+                )));
+            }
+        } else if !rule.decode_group.is_empty() || !rule.decode_pattern.is_empty() {
+            return Err(ConfigError::Invalid(format!(
+                "decode_group and decode_pattern of rule '{}' need decode",
+                rule.name
+            )));
+        }
+        if rule
+            .sample_rate
+            .is_some_and(|rate| !(rate > 0.0 && rate <= 1.0))
+        {
+            return Err(ConfigError::Invalid(format!(
+                "sample_rate of rule '{}' must be a fraction above 0 and at most 1",
+                rule.name
+            )));
+        }
+        for window in &rule.suppression_windows {
+            let (start, end) = (time_of_day(&window.start)?, time_of_day(&window.end)?);
+            if start == end {
+                return Err(ConfigError::Invalid(format!(
+                    "Suppression window of rule '{}' must not be empty",
+                    rule.name
+                )));
+            }
+        }
+    }
+
+    // heartbeats clear the suppression of alert rules of the same set
+    for heartbeat in heartbeats {
+        if heartbeat
+            .cadence_sensitivity
+            .is_some_and(|sensitivity| !(sensitivity.is_finite() && sensitivity > 0.0))
+        {
+            return Err(ConfigError::Invalid(format!(
+                "cadence_sensitivity of heartbeat '{}' must be a positive number",
+                heartbeat.pattern
+            )));
+        }
+        // a deviation needs at least two intervals, the samples are unused without a cadence
+        if heartbeat.cadence_sensitivity.is_some() && heartbeat.cadence_samples < 2 {
+            return Err(ConfigError::Invalid(format!(
+                "cadence_samples of heartbeat '{}' must be at least 2",
+                heartbeat.pattern
+            )));
+        }
+        if let Some(name) = heartbeat
+            .reset_alerts
+            .iter()
+            .find(|name| !alerts.iter().any(|rule| &rule.name == *name))
+        {
+            return Err(ConfigError::Invalid(format!(
+                "Heartbeat '{}' resets unknown alert rule '{}'",
+                heartbeat.pattern, name
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_monitors() {
+        let config: Config = toml::from_str(
+            r#"
+            slack_webhook_url = ""
+            rich_formatting = true
+            [[monitors]]
+            name = "db"
+            systemd_unit = "postgresql.service"
+            [[monitors.alerts]]
+            pattern = "FATAL"
+            prefix = "🔴 "
+            [[monitors]]
+            name = "web"
+            systemd_unit = "nginx.service"
+            syslog_identifier = "nginx"
+            [[monitors.heartbeats]]
+            pattern = "health ok"
+            prefix = "Missing "
+            tolerance = 60
+            "#,
+        )
+        .unwrap();
+        let monitors = config.monitors();
+        assert_eq!(monitors.len(), 2);
+        let (name, db) = &monitors[0];
+        assert_eq!(name, "db");
+        assert_eq!(db.systemd_unit, "postgresql.service");
+        assert_eq!((db.alerts.len(), db.heartbeats.len()), (1, 0));
+        // the global settings are shared
+        assert!(db.rich_formatting);
+        let (name, web) = &monitors[1];
+        assert_eq!(name, "web");
+        assert_eq!(web.syslog_identifier, ["nginx"]);
+        assert_eq!((web.alerts.len(), web.heartbeats.len()), (0, 1));
+
+        // without monitors the config is used as is
+        let config: Config = toml::from_str(
+            r#"
+            slack_webhook_url = ""
+            systemd_unit = "myservice.service"
+            "#,
+        )
+        .unwrap();
+        let monitors = config.monitors();
+        assert_eq!(monitors.len(), 1);
+        assert_eq!(monitors[0].1.systemd_unit, "myservice.service");
+    }
+
+    #[test]
+    fn test_render() {
+        let config: Config = toml::from_str(
+            r#"
+            slack_webhook_url = "https://hooks.slack.com/services/SECRET"
+            systemd_unit = "myservice.service"
+            [slack_ack]
+            signing_secret = "SECRET"
+            [[alerts]]
+            pattern = "error"
+            prefix = "🔴 "
+            "#,
+        )
+        .unwrap();
+        // the rendered config loads back to the same config
+        let rendered: Config = toml::from_str(&config.render(false, false).unwrap()).unwrap();
+        assert_eq!(rendered.slack_webhook_url, config.slack_webhook_url);
+        assert_eq!(rendered.alerts[0].pattern, "error");
+
+        let rendered = config.render(true, true).unwrap();
+        assert!(!rendered.contains("SECRET"));
+        let rendered: serde_json::Value = serde_json::from_str(&rendered).unwrap();
+        assert_eq!(rendered["slack_webhook_url"], serde_json::json!([REDACTED]));
+        // unset secrets are left empty
+        assert_eq!(rendered["redis_url"], "");
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        let config = Config::parse(
+            r#"
+            slack_webhook_url = ""
+            systemd_unit = ""
+            heartbeat_intervall = 30
+            [pushover]
+            tokn = "app"
+            [[alerts]]
+            pattern = "error"
+            prefix = ""
+            patern = "eror"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.unknown_keys,
+            ["alerts[0].patern", "heartbeat_intervall", "pushover.tokn"]
+        );
+    }
+
+    #[test]
+    fn test_reset_alerts() {
+        let config: Config = toml::from_str(
+            r#"
+            slack_webhook_url = ""
+            systemd_unit = ""
+            [[alerts]]
+            pattern = "timeout"
+            prefix = ""
+            [[heartbeats]]
+            pattern = "ready"
+            prefix = ""
+            tolerance = 60
+            reset_alerts = ["alert-0"]
+            "#,
+        )
+        .unwrap();
+        let (mut alerts, mut heartbeats) = (config.alerts, config.heartbeats);
+        // the default names are set before the links are checked
+        assert!(validate_rules("config", &[], &mut alerts, &heartbeats, "").is_ok());
+        heartbeats[0].reset_alerts.push("db".to_string());
+        let err = validate_rules("config", &[], &mut alerts, &heartbeats, "").unwrap_err();
+        assert!(err.to_string().contains("unknown alert rule 'db'"), "{err}");
+    }
+
+    #[test]
+    fn test_cadence_sensitivity() {
+        let config: Config = toml::from_str(
+            r#"
+            slack_webhook_url = ""
+            systemd_unit = ""
+            [[heartbeats]]
+            pattern = "ready"
+            prefix = ""
+            tolerance = 60
+            cadence_sensitivity = 3
+            "#,
+        )
+        .unwrap();
+        let mut heartbeats = config.heartbeats;
+        assert_eq!(heartbeats[0].cadence_samples, DEFAULT_CADENCE_SAMPLES);
+        assert!(validate_rules("config", &[], &mut [], &heartbeats, "").is_ok());
+        heartbeats[0].cadence_sensitivity = Some(-1.0);
+        let err = validate_rules("config", &[], &mut [], &heartbeats, "").unwrap_err();
+        assert!(
+            err.to_string().contains("must be a positive number"),
+            "{err}"
+        );
+        // the cadence is learned from at least two intervals
+        heartbeats[0].cadence_sensitivity = Some(3.0);
+        heartbeats[0].cadence_samples = 1;
+        assert!(validate_rules("config", &[], &mut [], &heartbeats, "").is_err());
+        heartbeats[0].cadence_sensitivity = None;
+        assert!(validate_rules("config", &[], &mut [], &heartbeats, "").is_ok());
+    }
+
+    #[test]
+    fn test_all_of() {
+        let mut alerts = vec![AlertRule {
+            all_of: vec!["(?i)election".to_string(), "timeout".to_string()],
+            ..Default::default()
+        }];
+        assert!(validate_rules("config", &[], &mut alerts, &[], "").is_ok());
+        // the rule matches the lines of any of the patterns
+        assert_eq!(alerts[0].pattern, "(?:(?i)election)|(?:timeout)");
+        assert_eq!(alerts[0].all_of_window, DEFAULT_ALL_OF_WINDOW);
+        alerts[0].all_of.clear();
+        alerts[0].pattern.clear();
+        let err = validate_rules("config", &[], &mut alerts, &[], "").unwrap_err();
+        assert!(err.to_string().contains("must have a pattern"), "{err}");
+    }
+
+    #[test]
+    fn test_decode() {
+        let mut alerts = vec![AlertRule {
+            pattern: r"payload=(?P<data>\S+) (\d+)".to_string(),
+            decode: Some(Encoding::Base64),
+            ..Default::default()
+        }];
+        assert!(validate_rules("config", &[], &mut alerts, &[], "").is_ok());
+        assert_eq!(alerts[0].decode_group, "1");
+        for (group, valid) in [
+            ("data", true),
+            ("2", true),
+            ("3", false),
+            ("0", false),
+            ("x", false),
+        ] {
+            alerts[0].decode_group = group.to_string();
+            assert_eq!(
+                validate_rules("config", &[], &mut alerts, &[], "").is_ok(),
+                valid,
+                "{group}"
+            );
+        }
+        alerts[0].decode = None;
+        let err = validate_rules("config", &[], &mut alerts, &[], "").unwrap_err();
+        assert!(err.to_string().contains("need decode"), "{err}");
+    }
+
+    #[test]
+    fn test_webhook_headers() {
+        // cargo sets the package variables when running the tests
+        assert_eq!(
+            expand_env("Bearer ${CARGO_PKG_NAME}-${CARGO_PKG_NAME}").unwrap(),
+            "Bearer journal-alerts-journal-alerts"
+        );
+        assert_eq!(expand_env("no ${reference").unwrap(), "no ${reference");
+        assert_eq!(
+            expand_env("${JOURNAL_ALERTS_TEST_UNSET}").unwrap_err(),
+            "JOURNAL_ALERTS_TEST_UNSET"
+        );
+
+        let mut config: Config = toml::from_str(
+            r#"
+            slack_webhook_url = ""
+            systemd_unit = ""
+            webhook_headers = { Authorization = "Bearer secret", X-Source = "journal-alerts" }
+            "#,
+        )
+        .unwrap();
+        let headers = config.header_map().unwrap();
+        assert_eq!(headers["x-source"], "journal-alerts");
+        config
+            .webhook_headers
+            .insert("X-Bad".to_string(), "line\nbreak".to_string());
+        assert!(config.header_map().is_err());
+    }
+
+    #[test]
+    fn test_timezone() {
+        let timezone = |toml: &str| {
+            toml::from_str::<Config>(&format!("slack_webhook_url = \"\"\n{toml}"))
+                .map(|config| config.timezone)
+        };
+        let time = DateTime::from_timestamp(1_735_725_600, 0).unwrap();
+        assert_eq!(timezone("").unwrap().format(time), "2025-01-01T10:00:00Z");
+        let named = timezone(r#"timezone = "America/New_York""#).unwrap();
+        assert_eq!(named.format(time), "2025-01-01T05:00:00-05:00");
+        assert_eq!(String::from(named), "America/New_York");
+        assert_eq!(timezone(r#"timezone = "local""#).unwrap(), Timezone::Local);
+        let error = timezone(r#"timezone = "Mars/Olympus""#).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("unknown timezone 'Mars/Olympus'")
+        );
+    }
+
+    #[test]
+    fn test_webhook_url_file() {
+        let dir = std::env::temp_dir();
+        let secret = dir.join("journal-alerts-test-webhook");
+        fs::write(&secret, "https://hooks.slack.com/services/SECRET\n").unwrap();
+        let rules = "systemd_unit = \"\"\n[[alerts]]\npattern = \"error\"\nprefix = \"\"\n";
+        let path = dir.join("journal-alerts-test-webhook.toml");
+        let load = |webhook: &str| {
+            fs::write(&path, format!("{webhook}\n{rules}")).unwrap();
+            Config::load(Some(path.to_str().unwrap().to_string()))
+        };
+
+        let file = format!("slack_webhook_url_file = {:?}", secret.to_str().unwrap());
+        let config = load(&file).unwrap();
+        assert_eq!(
+            config.slack_webhook_url,
+            ["https://hooks.slack.com/services/SECRET"]
+        );
+        // setting both is ambiguous
+        assert!(matches!(
+            load(&format!("{file}\nslack_webhook_url = \"https://x\"")),
+            Err(ConfigError::Invalid(_))
+        ));
+        fs::remove_file(&secret).unwrap();
+        assert!(matches!(
+            load(&file),
+            Err(ConfigError::Read {
+                what: "slack_webhook_url_file",
+                ..
+            })
+        ));
+        assert!(matches!(load("[[alerts"), Err(ConfigError::Toml(_))));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_suppression_window() {
+        let window = |start: &str, end: &str| SuppressionWindow {
+            start: start.to_string(),
+            end: end.to_string(),
+        };
+        let rule = AlertRule {
+            suppression_windows: vec![window("22:00", "06:00"), window("12:00", "12:30")],
+            ..Default::default()
+        };
+        let at = |h: u32, m: u32| rule.suppression_window(h * 3600 + m * 60);
+        assert_eq!(at(23, 0), Some(Duration::from_secs(7 * 3600)));
+        assert_eq!(at(5, 59), Some(Duration::from_secs(60)));
+        assert_eq!(at(12, 10), Some(Duration::from_secs(20 * 60)));
+        assert_eq!(at(6, 0), None);
+        assert_eq!(at(12, 30), None);
+
+        assert_eq!(time_of_day("06:30").unwrap(), 6 * 3600 + 30 * 60);
+        assert!(time_of_day("24:00").is_err());
+        assert!(time_of_day("6:30").is_err());
+    }
 }
@@ -0,0 +1,73 @@
+use std::process::Stdio;
+use std::time::Duration;
+
+use tokio::process::Command;
+
+use crate::error::ProcessError;
+
+/// Upper bound of the context sent in the message, Slack truncates long messages anyway.
+pub const MAX_CONTEXT_BYTES: usize = 3000;
+/// Upper bound of the context uploaded as a file.
+pub const MAX_UPLOAD_BYTES: usize = 256 * 1024;
+// journalctl should answer quickly, do not hold up the notifier
+const CONTEXT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Fetches the last `lines` journal lines for the filter, e.g. the monitored unit.
+///
+/// The output is bounded to the most recent `max_bytes`, an empty journal yields an
+/// empty string.
+pub async fn recent_lines(
+    filter: &[String],
+    lines: usize,
+    max_bytes: usize,
+) -> Result<String, ProcessError> {
+    let output = Command::new("journalctl")
+        .args(filter)
+        .args(["--lines", &lines.to_string(), "--output=cat", "--no-pager"])
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(CONTEXT_TIMEOUT, output)
+        .await
+        .map_err(|_| ProcessError::Timeout(CONTEXT_TIMEOUT))?
+        .map_err(ProcessError::Spawn)?;
+    if !output.status.success() {
+        return Err(ProcessError::Exited {
+            status: output.status,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+    let context = String::from_utf8_lossy(&output.stdout);
+    Ok(tail(context.trim_end(), max_bytes).to_string())
+}
+
+/// Returns the end of the text, at most `max` bytes, starting on a line when possible.
+pub fn tail(text: &str, max: usize) -> &str {
+    if text.len() <= max {
+        return text;
+    }
+    let mut start = text.len() - max;
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    let text = &text[start..];
+    // drop the partial first line, unless it is the only one
+    match text.find('\n') {
+        Some(pos) if pos + 1 < text.len() => &text[pos + 1..],
+        _ => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tail() {
+        assert_eq!(tail("one\ntwo", 100), "one\ntwo");
+        assert_eq!(tail("one\ntwo\nthree", 9), "three");
+        assert_eq!(tail("a single long line", 6), "g line");
+        // never split a multi byte character
+        assert_eq!(tail("ééé", 3), "é");
+    }
+}
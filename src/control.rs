@@ -0,0 +1,306 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+use dashmap::DashMap;
+use log::{error, info, warn};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+
+use crate::budget::{self, AlertBudget};
+use crate::error::{CommandError, ServerError};
+use crate::metrics::Metrics;
+use crate::recent::RecentAlerts;
+use crate::suppression::Suppressions;
+
+/// Runtime silences for alert rules, keyed by rule name.
+#[derive(Default)]
+pub struct Silences {
+    // Map of rule name to the instant the silence expires
+    entries: DashMap<String, Instant>,
+}
+
+impl Silences {
+    /// Silences the rule for the given duration, replacing any existing silence.
+    pub fn silence(&self, rule: &str, duration: Duration) {
+        self.entries
+            .insert(rule.to_string(), Instant::now() + duration);
+    }
+
+    /// Removes the silence for the rule, returns false if it was not silenced.
+    pub fn unsilence(&self, rule: &str) -> bool {
+        self.entries.remove(rule).is_some()
+    }
+
+    /// Checks if the rule is currently silenced, expired silences are removed.
+    pub fn is_silenced(&self, rule: &str) -> bool {
+        let now = Instant::now();
+        self.entries
+            .remove_if(rule, |_, until| *until <= now)
+            .is_none()
+            && self.entries.contains_key(rule)
+    }
+
+    /// Lists the active silences with their remaining time, sorted by rule name.
+    pub fn list(&self) -> Vec<(String, Duration)> {
+        let now = Instant::now();
+        self.entries.retain(|_, until| *until > now);
+        let mut silences = self
+            .entries
+            .iter()
+            .map(|e| (e.key().clone(), e.value().saturating_duration_since(now)))
+            .collect::<Vec<_>>();
+        silences.sort();
+        silences
+    }
+}
+
+/// Parses a duration such as `90`, `90s`, `30m`, `2h` or `1d`.
+pub fn parse_duration(value: &str) -> Result<Duration, CommandError> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(pos) => value.split_at(pos),
+        None => (value, "s"),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| CommandError::Duration(value.to_string()))?;
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return Err(CommandError::DurationUnit(value.to_string())),
+    };
+    Ok(Duration::from_secs(number * multiplier))
+}
+
+/// Formats a duration for humans with the units of `parse_duration`, e.g.
+/// `2h 3m 4s`, to the second.
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let parts = [
+        (secs / 86400, "d"),
+        (secs / 3600 % 24, "h"),
+        (secs / 60 % 60, "m"),
+        (secs % 60, "s"),
+    ]
+    .into_iter()
+    .filter(|(value, _)| *value > 0)
+    .map(|(value, unit)| format!("{value}{unit}"))
+    .collect::<Vec<_>>();
+    if parts.is_empty() {
+        "0s".to_string()
+    } else {
+        parts.join(" ")
+    }
+}
+
+/// The runtime state the control commands operate on.
+#[derive(Clone, Default)]
+pub struct ControlContext {
+    pub silences: Arc<Silences>,
+    pub metrics: Arc<Metrics>,
+    pub suppressions: Arc<Suppressions>,
+    pub recent: Arc<RecentAlerts>,
+    pub budget: Arc<AlertBudget>,
+}
+
+/// A control server that accepts line based commands on a unix socket.
+pub struct ControlServer {
+    listener: UnixListener,
+    context: ControlContext,
+}
+
+impl ControlServer {
+    pub fn bind(path: &str, context: ControlContext) -> Result<Self, ServerError> {
+        // remove a stale socket left over by a previous run
+        if std::path::Path::new(path).exists() {
+            std::fs::remove_file(path).map_err(|source| ServerError::Stale {
+                path: path.to_string(),
+                source,
+            })?;
+        }
+        let listener = UnixListener::bind(path).map_err(|source| ServerError::Bind {
+            what: "control socket",
+            address: path.to_string(),
+            source,
+        })?;
+        info!("Control socket listening on: {path}");
+        Ok(ControlServer { listener, context })
+    }
+
+    pub async fn start(&self) -> Result<(), ServerError> {
+        loop {
+            let (stream, _) =
+                self.listener
+                    .accept()
+                    .await
+                    .map_err(|source| ServerError::Accept {
+                        what: "control",
+                        source,
+                    })?;
+            let context = self.context.clone();
+            tokio::spawn(async move {
+                if let Err(e) = handle_connection(stream, &context).await {
+                    warn!("Control connection error: {}", e);
+                }
+            });
+        }
+    }
+}
+
+async fn handle_connection(stream: UnixStream, context: &ControlContext) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        let response = match execute(&line, context) {
+            Ok(response) => response,
+            Err(e) => {
+                error!("Control command '{}' failed: {}", line, e);
+                format!("error: {e}")
+            }
+        };
+        writer.write_all(response.as_bytes()).await?;
+        writer.write_all(b"\n").await?;
+    }
+    Ok(())
+}
+
+/// Executes a single control command and returns the response text.
+fn execute(line: &str, context: &ControlContext) -> Result<String, CommandError> {
+    let silences = &context.silences;
+    let parts = line.split_whitespace().collect::<Vec<_>>();
+    match parts.as_slice() {
+        ["silence", rule, duration] => {
+            let duration = parse_duration(duration)?;
+            silences.silence(rule, duration);
+            let duration = format_duration(duration);
+            info!("Rule '{}' silenced for {}", rule, duration);
+            Ok(format!("ok: '{rule}' silenced for {duration}"))
+        }
+        ["unsilence", rule] => {
+            if silences.unsilence(rule) {
+                info!("Rule '{}' unsilenced", rule);
+                Ok(format!("ok: '{rule}' unsilenced"))
+            } else {
+                Ok(format!("ok: '{rule}' was not silenced"))
+            }
+        }
+        ["silences"] => {
+            let silences = silences.list();
+            if silences.is_empty() {
+                return Ok("no active silences".to_string());
+            }
+            Ok(silences
+                .iter()
+                .map(|(rule, remaining)| format!("{rule} {}", format_duration(*remaining)))
+                .collect::<Vec<_>>()
+                .join("\n"))
+        }
+        ["reset-suppression"] => {
+            let removed = context.suppressions.reset(None);
+            Ok(format!("ok: {removed} suppressed messages reset"))
+        }
+        ["reset-suppression", rule] => {
+            let removed = context.suppressions.reset(Some(rule));
+            Ok(format!(
+                "ok: {removed} suppressed messages reset for '{rule}'"
+            ))
+        }
+        ["metrics"] => Ok(context.metrics.render().trim_end().to_string()),
+        ["budget"] => {
+            let now = Local::now();
+            let Some(spending) = context.budget.spending(now.date_naive()) else {
+                return Ok("no alert budget".to_string());
+            };
+            Ok(format!(
+                "{} of {} alerts left today, {} dropped, resets in {}",
+                spending.remaining,
+                spending.max,
+                spending.dropped,
+                format_duration(budget::until_midnight(now))
+            ))
+        }
+        ["recent"] => Ok(recent(context, usize::MAX)),
+        ["recent", limit] => {
+            let limit = limit
+                .parse()
+                .map_err(|_| CommandError::Count(limit.to_string()))?;
+            Ok(recent(context, limit))
+        }
+        _ => Err(CommandError::Unknown),
+    }
+}
+
+/// Lists the last alerts sent, oldest first, with the duplicates suppressed since.
+fn recent(context: &ControlContext, limit: usize) -> String {
+    let recent = context.recent.list(limit);
+    if recent.is_empty() {
+        return "no recent alerts".to_string();
+    }
+    recent
+        .iter()
+        .map(|recent| {
+            format!(
+                "{} ago [{}] {} ({} suppressed)",
+                format_duration(recent.sent.elapsed()),
+                recent.rule.as_deref().unwrap_or("-"),
+                recent.message,
+                recent.suppressed
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("30m").unwrap(), Duration::from_secs(1800));
+        assert_eq!(parse_duration("2h").unwrap(), Duration::from_secs(7200));
+        assert_eq!(parse_duration("1d").unwrap(), Duration::from_secs(86400));
+        assert!(matches!(
+            parse_duration("1w"),
+            Err(CommandError::DurationUnit(value)) if value == "1w"
+        ));
+        assert!(matches!(
+            parse_duration("m"),
+            Err(CommandError::Duration(value)) if value == "m"
+        ));
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::ZERO), "0s");
+        assert_eq!(format_duration(Duration::from_millis(59_900)), "59s");
+        assert_eq!(format_duration(Duration::from_secs(3600)), "1h");
+        assert_eq!(format_duration(Duration::from_secs(7384)), "2h 3m 4s");
+        assert_eq!(format_duration(Duration::from_secs(90061)), "1d 1h 1m 1s");
+    }
+
+    #[test]
+    fn test_silences() {
+        let context = ControlContext::default();
+        let silences = &context.silences;
+        execute("silence disk 1h", &context).unwrap();
+        assert!(silences.is_silenced("disk"));
+        assert!(!silences.is_silenced("other"));
+        assert_eq!(silences.list().len(), 1);
+
+        execute("unsilence disk", &context).unwrap();
+        assert!(!silences.is_silenced("disk"));
+
+        // expired silences are dropped
+        silences.silence("disk", Duration::ZERO);
+        assert!(!silences.is_silenced("disk"));
+        assert!(silences.list().is_empty());
+
+        assert!(execute("bogus", &context).is_err());
+    }
+}
@@ -0,0 +1,55 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use tokio::time::sleep;
+
+use crate::metrics::Metrics;
+
+/// Pings `url` every `interval` while every monitor reads its journal, so that an
+/// external monitor such as healthchecks.io alerts when the pings stop: the tool is
+/// down, or one of its journal readers is.
+pub async fn run(url: String, interval: Duration, metrics: Arc<Metrics>) {
+    info!("Pinging the deadman URL every {:?}", interval);
+    let client = reqwest::Client::new();
+    loop {
+        // the first ping waits for the journal readers to start
+        sleep(interval).await;
+        if !metrics.journals_up() {
+            warn!("A journal reader is down, skipping the deadman ping");
+            continue;
+        }
+        match client.get(&url).timeout(interval).send().await {
+            Ok(res) if res.status().is_success() => debug!("Deadman ping sent"),
+            Ok(res) => warn!("Deadman ping failed, status: {}", res.status()),
+            Err(e) => warn!("Deadman ping failed: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::Running;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn test_run() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/ping", listener.local_addr().unwrap());
+        let metrics = Arc::new(Metrics::default());
+        Metrics::set(&metrics.monitors, 1);
+        let deadman = tokio::spawn(run(url, Duration::from_millis(20), metrics.clone()));
+
+        // no ping while the journal reader is down
+        let accept = tokio::time::timeout(Duration::from_millis(200), listener.accept());
+        assert!(accept.await.is_err());
+        let _running = Running::new(&metrics.journal_readers);
+        let (mut stream, _) = listener.accept().await.unwrap();
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        deadman.abort();
+    }
+}
@@ -0,0 +1,85 @@
+use log::{debug, info};
+use redis::aio::ConnectionManager;
+
+use crate::error::NotifierError;
+
+const KEY_PREFIX: &str = "journal-alerts:dedup:";
+
+/// Deduplication of alerts shared between instances through Redis.
+///
+/// The first instance reporting a (normalized) message within the window claims it,
+/// the other instances suppress their copy.
+#[derive(Clone)]
+pub struct SharedDedup {
+    connection: ConnectionManager,
+    window: u64,
+    hostname: String,
+}
+
+impl SharedDedup {
+    pub async fn connect(redis_url: &str, window: u64) -> Result<Self, NotifierError> {
+        let client = redis::Client::open(redis_url).map_err(|source| NotifierError::Redis {
+            what: "Invalid redis_url",
+            source,
+        })?;
+        let connection =
+            ConnectionManager::new(client)
+                .await
+                .map_err(|source| NotifierError::Redis {
+                    what: "Failed to connect to Redis",
+                    source,
+                })?;
+        let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname")
+            .map(|h| h.trim().to_string())
+            .unwrap_or_default();
+        info!("Shared deduplication enabled, window {} seconds", window);
+        Ok(SharedDedup {
+            connection,
+            window,
+            hostname,
+        })
+    }
+
+    /// Claims the message for this host, returns false if another host already reported it.
+    pub async fn claim(&self, message: &str) -> Result<bool, NotifierError> {
+        let key = format!("{KEY_PREFIX}{}", normalize(message, &self.hostname));
+        let claimed: Option<String> = redis::cmd("SET")
+            .arg(&key)
+            .arg(&self.hostname)
+            .arg("NX")
+            .arg("EX")
+            .arg(self.window)
+            .query_async(&mut self.connection.clone())
+            .await
+            .map_err(|source| NotifierError::Redis {
+                what: "Redis SET failed",
+                source,
+            })?;
+        debug!("Shared dedup claim for '{}': {}", key, claimed.is_some());
+        Ok(claimed.is_some())
+    }
+}
+
+/// Normalizes a message so the same alert from different hosts yields the same key.
+fn normalize(message: &str, hostname: &str) -> String {
+    let message = if hostname.is_empty() {
+        message.to_string()
+    } else {
+        message.replace(hostname, "<host>")
+    };
+    message.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(
+            normalize("db  timeout on web-1 (web-1.local)", "web-1"),
+            "db timeout on <host> (<host>.local)"
+        );
+        assert_eq!(normalize("db timeout", ""), "db timeout");
+    }
+}
@@ -0,0 +1,238 @@
+use std::process::ExitStatus;
+use std::time::Duration;
+
+use thiserror::Error;
+
+/// Failures loading or validating the config.
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("No config file found")]
+    NotFound,
+    #[error("Failed to read {what}: {path}")]
+    Read {
+        what: &'static str,
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("Invalid TOML in config file")]
+    Toml(#[source] toml::de::Error),
+    #[error("Failed to serialize config to JSON")]
+    Json(#[source] serde_json::Error),
+    #[error("Failed to serialize config to TOML")]
+    TomlSerialize(#[source] toml::ser::Error),
+    // a setting or rule that is not usable, with the reason
+    #[error("{0}")]
+    Invalid(String),
+}
+
+/// Failures compiling the patterns of the rules.
+#[derive(Debug, Error)]
+pub enum MatcherError {
+    #[error("Invalid regex pattern '{pattern}' of rule {rule}: {source}")]
+    Pattern {
+        pattern: String,
+        // the rule name quoted, or its index
+        rule: String,
+        source: regex::Error,
+    },
+    #[error("Invalid fancy regex pattern '{pattern}' of rule {rule}: {source}")]
+    FancyPattern {
+        pattern: String,
+        rule: String,
+        source: Box<fancy_regex::Error>,
+    },
+    #[error("Invalid transform pattern '{pattern}': {source}")]
+    Transform {
+        pattern: String,
+        source: regex::Error,
+    },
+    #[error("Invalid resolve_pattern of rule '{rule}'")]
+    Resolve { rule: String, source: regex::Error },
+    #[error("Invalid decode_pattern of rule '{rule}'")]
+    Decode { rule: String, source: regex::Error },
+    #[error("Invalid template of rule '{rule}': {reason}")]
+    Template { rule: String, reason: String },
+    #[error("Invalid dedup_key capture pattern '{pattern}': {source}")]
+    DedupKey {
+        pattern: String,
+        source: regex::Error,
+    },
+}
+
+/// Failures running journalctl.
+#[derive(Debug, Error)]
+pub enum ProcessError {
+    #[error("Failed to spawn journalctl process")]
+    Spawn(#[source] std::io::Error),
+    #[error("Failed to capture {0} of journalctl")]
+    Capture(&'static str),
+    // exited before reading anything, e.g. a missing permission
+    #[error("journalctl exited with {status}: {stderr}{}", permission_hint(.stderr))]
+    Exited { status: ExitStatus, stderr: String },
+    #[error("Failed to read the journal")]
+    Journal(#[source] std::io::Error),
+    #[error("Failed to wait for journalctl")]
+    Wait(#[source] std::io::Error),
+    #[error("journalctl exited with {0}")]
+    Failed(ExitStatus),
+    #[error("journalctl did not answer within {0:?}")]
+    Timeout(Duration),
+    #[error("Processor task failed")]
+    Task(#[from] tokio::task::JoinError),
+}
+
+impl ProcessError {
+    /// Whether starting the processor again would fail the same way, e.g. without the
+    /// permission to read the journal or without journalctl at all.
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            ProcessError::Spawn(e) => matches!(
+                e.kind(),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::PermissionDenied
+            ),
+            ProcessError::Exited { .. } => true,
+            ProcessError::Capture(_)
+            | ProcessError::Journal(_)
+            | ProcessError::Wait(_)
+            | ProcessError::Failed(_)
+            | ProcessError::Timeout(_)
+            | ProcessError::Task(_) => false,
+        }
+    }
+}
+
+/// Formats the error followed by its causes, e.g. `Invalid TOML in config file: ...`.
+pub fn report(error: &dyn std::error::Error) -> String {
+    let mut report = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        report = format!("{report}: {cause}");
+        source = cause.source();
+    }
+    report
+}
+
+/// A hint when journalctl lacks the permission to read the journal.
+fn permission_hint(stderr: &str) -> &'static str {
+    if stderr.to_lowercase().contains("permission") {
+        "\nhint: the user running journal-alerts must be root or in the 'systemd-journal' group"
+    } else {
+        ""
+    }
+}
+
+/// Failures delivering an alert.
+#[derive(Debug, Error)]
+pub enum NotifierError {
+    #[error("HTTP client error {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("Failed to send alert to {notifier}. Status: {status}")]
+    Status {
+        notifier: &'static str,
+        status: reqwest::StatusCode,
+    },
+    #[error("Failed to send alert to {notifier} after {attempts} attempts. {last}")]
+    Retries {
+        notifier: &'static str,
+        attempts: u32,
+        // the error of the last attempt
+        last: String,
+    },
+    #[error("Failed to send alert to all the webhooks")]
+    AllWebhooks,
+    #[error("Invalid {notifier} response")]
+    Response {
+        notifier: &'static str,
+        source: reqwest::Error,
+    },
+    #[error("{notifier} API error: {details}")]
+    Api {
+        notifier: &'static str,
+        details: String,
+    },
+    #[error("Send task failed")]
+    Task(#[from] tokio::task::JoinError),
+    #[error("Send permits closed")]
+    Permits(#[from] tokio::sync::AcquireError),
+    #[error("The alert channel is closed")]
+    Closed,
+    #[error("{what}")]
+    Redis {
+        what: &'static str,
+        source: redis::RedisError,
+    },
+    #[error("{0}")]
+    Syslog(String),
+    // the notifiers created again after a failure, from an invalid config
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error(transparent)]
+    Matcher(#[from] MatcherError),
+}
+
+impl NotifierError {
+    /// Whether starting the notifiers again would fail the same way, a problem in the
+    /// config or its rules.
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, NotifierError::Config(_) | NotifierError::Matcher(_))
+    }
+}
+
+/// Failures of the control socket and of the Slack interactivity endpoint.
+#[derive(Debug, Error)]
+pub enum ServerError {
+    #[error("Failed to remove stale control socket: {path}")]
+    Stale {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("Failed to bind {what}: {address}")]
+    Bind {
+        what: &'static str,
+        address: String,
+        source: std::io::Error,
+    },
+    #[error("Failed to accept {what} connection")]
+    Accept {
+        what: &'static str,
+        source: std::io::Error,
+    },
+}
+
+/// Failures of a control command, answered to the client.
+#[derive(Debug, Error)]
+pub enum CommandError {
+    #[error("Invalid duration '{0}'")]
+    Duration(String),
+    #[error("Invalid duration unit in '{0}'")]
+    DurationUnit(String),
+    #[error("Invalid number of alerts '{0}'")]
+    Count(String),
+    #[error(
+        "unknown command, expected: silence <rule> <duration> | unsilence <rule> | silences | reset-suppression [rule] | metrics | budget | recent [count]"
+    )]
+    Unknown,
+}
+
+/// Failures replaying a log file or previewing the journal.
+#[derive(Debug, Error)]
+pub enum ReplayError {
+    #[error("Failed to open {path}")]
+    Open {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("Failed to read {path}")]
+    Read {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("Failed to read the lines")]
+    Lines(#[source] std::io::Error),
+    #[error("Failed to write the matches")]
+    Write(#[source] std::io::Error),
+    #[error(transparent)]
+    Matcher(#[from] MatcherError),
+    #[error(transparent)]
+    Process(#[from] ProcessError),
+}
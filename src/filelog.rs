@@ -0,0 +1,171 @@
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+
+use chrono::Utc;
+use flume::Receiver;
+use log::{error, info};
+
+use crate::alert::Alert;
+use crate::config::{FileLogConfig, Severity, Timezone};
+use crate::error::NotifierError;
+
+/// Appends every alert to a local file as one line, an audit trail of the alert
+/// stream independent of the tool's own logs.
+pub struct FileLog {
+    config: FileLogConfig,
+    // timezone of the time the lines start with
+    timezone: Timezone,
+}
+
+impl FileLog {
+    pub fn new(config: FileLogConfig) -> Self {
+        FileLog {
+            config,
+            timezone: Timezone::default(),
+        }
+    }
+
+    pub fn with_timezone(mut self, timezone: Timezone) -> Self {
+        self.timezone = timezone;
+        self
+    }
+
+    pub async fn start(&self, rx: Receiver<Alert>) -> Result<(), NotifierError> {
+        info!(
+            "File log notifier started, appending to {}.",
+            self.config.path
+        );
+        // opened on the first alert, and again after a failed write
+        let mut file = None;
+        loop {
+            let Ok(alert) = rx.recv_async().await else {
+                info!("File log notifier receiver channel closed, exiting.");
+                break;
+            };
+            // alerts routed here explicitly are written whatever their severity
+            if alert.notify.is_empty() && !self.config.severities.contains(&alert.severity) {
+                continue;
+            }
+            let line = line(&alert, &self.timezone.format(Utc::now()));
+            if let Err(e) = self.append(&mut file, &line) {
+                error!("Error writing alert to {}: {}", self.config.path, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends the line, rotating the file first if it would grow past the maximum
+    /// size. A file moved or removed from under us, e.g. by logrotate, is created again.
+    fn append(&self, file: &mut Option<File>, line: &str) -> std::io::Result<()> {
+        let path = Path::new(&self.config.path);
+        let size = match std::fs::metadata(path) {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                *file = None;
+                0
+            }
+            Err(e) => return Err(e),
+        };
+        let max_size = self.config.max_size_bytes;
+        if max_size > 0 && size > 0 && size + line.len() as u64 > max_size {
+            *file = None;
+            self.rotate()?;
+        }
+        // left closed on error, to be opened again with the next alert
+        let mut open = match file.take() {
+            Some(open) => open,
+            None => OpenOptions::new().create(true).append(true).open(path)?,
+        };
+        open.write_all(line.as_bytes())?;
+        *file = Some(open);
+        Ok(())
+    }
+
+    /// Shifts the rotated files by one, dropping the oldest, and moves the file to `<path>.1`.
+    fn rotate(&self) -> std::io::Result<()> {
+        let path = &self.config.path;
+        if self.config.max_files == 0 {
+            return std::fs::remove_file(path);
+        }
+        for i in (1..self.config.max_files).rev() {
+            match std::fs::rename(format!("{path}.{i}"), format!("{path}.{}", i + 1)) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        std::fs::rename(path, format!("{path}.1"))
+    }
+}
+
+/// The line of an alert, with the time it was written, as
+/// `<time> <severity> [<rule>] <text>`, newlines in the text are escaped.
+fn line(alert: &Alert, now: &str) -> String {
+    let severity = match alert.severity {
+        Severity::Critical => "critical",
+        Severity::Warning => "warning",
+        Severity::Info => "info",
+    };
+    format!(
+        "{} {} [{}] {}\n",
+        now,
+        severity,
+        alert.rule.as_deref().unwrap_or("-"),
+        alert.text().replace('\n', "\\n")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+
+    #[test]
+    fn test_append() {
+        let dir = std::env::temp_dir().join("journal-alerts-test-filelog");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("alerts.log").to_str().unwrap().to_string();
+        let file_log = FileLog::new(FileLogConfig {
+            path: path.clone(),
+            max_size_bytes: 30,
+            max_files: 2,
+            ..FileLogConfig::default()
+        });
+        let mut file = None;
+        let read = |path: &str| std::fs::read_to_string(path).unwrap();
+
+        // each line fits alone, the file is rotated before every append
+        for line in [
+            "first line\n",
+            "second line\n",
+            "third line\n",
+            "fourth line\n",
+        ] {
+            file_log.append(&mut file, line).unwrap();
+            file_log.append(&mut file, line).unwrap();
+        }
+        assert_eq!(read(&path), "fourth line\nfourth line\n");
+        assert_eq!(read(&format!("{path}.1")), "third line\nthird line\n");
+        assert_eq!(read(&format!("{path}.2")), "second line\nsecond line\n");
+        assert!(!Path::new(&format!("{path}.3")).exists());
+
+        // removed from under us, the file is created again
+        std::fs::remove_file(&path).unwrap();
+        file_log.append(&mut file, "fifth line\n").unwrap();
+        assert_eq!(read(&path), "fifth line\n");
+
+        let alert = Alert {
+            rule: Some("disk".to_string()),
+            severity: Severity::Critical,
+            ..Alert::new("disk full\nRunbook: https://wiki")
+        };
+        let now = DateTime::parse_from_rfc3339("2026-01-02T03:04:05+00:00").unwrap();
+        let timezone = Timezone::try_from("Europe/Berlin".to_string()).unwrap();
+        assert_eq!(
+            line(&alert, &timezone.format(now.to_utc())),
+            "2026-01-02T04:04:05+01:00 critical [disk] disk full\\nRunbook: https://wiki\n"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,391 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use hmac::{Hmac, KeyInit, Mac};
+use log::{error, info, warn};
+use sha2::Sha256;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::config::SlackAckConfig;
+use crate::control::format_duration;
+use crate::error::ServerError;
+use crate::processor::decode_hex;
+use crate::slack::ACK_ACTION_ID;
+use crate::suppression::Suppressions;
+
+// seconds after which a signed request is refused, as a possible replay
+const MAX_SIGNATURE_AGE: u64 = 300;
+// limit of a request, the interactivity payloads include the clicked message
+const MAX_REQUEST_LEN: u64 = 1 << 20;
+// time a client has to send its request, Slack sends it at once
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Answers the Slack interactivity requests sent when the Acknowledge button of an
+/// alert is clicked, suppressing the duplicates of the alert for the window.
+pub struct InteractivityServer {
+    listener: TcpListener,
+    acknowledger: Acknowledger,
+}
+
+/// Records the acknowledgments in the suppression entries.
+#[derive(Clone)]
+struct Acknowledger {
+    signing_secret: String,
+    window: Duration,
+    suppressions: Arc<Suppressions>,
+    client: reqwest::Client,
+}
+
+/// A request read from the connection, the header names in lowercase.
+struct Request {
+    method: String,
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+/// The message to replace the acknowledged alert with, at the URL of the click.
+struct Reply {
+    response_url: String,
+    text: String,
+}
+
+impl InteractivityServer {
+    pub async fn bind(
+        config: &SlackAckConfig,
+        suppressions: Arc<Suppressions>,
+    ) -> Result<Self, ServerError> {
+        let listener =
+            TcpListener::bind(&config.listen)
+                .await
+                .map_err(|source| ServerError::Bind {
+                    what: "interactivity endpoint",
+                    address: config.listen.clone(),
+                    source,
+                })?;
+        info!(
+            "Slack interactivity endpoint listening on: {}",
+            config.listen
+        );
+        let acknowledger = Acknowledger {
+            signing_secret: config.signing_secret.clone(),
+            window: Duration::from_secs(config.window),
+            suppressions,
+            client: reqwest::Client::new(),
+        };
+        Ok(InteractivityServer {
+            listener,
+            acknowledger,
+        })
+    }
+
+    pub async fn start(&self) -> Result<(), ServerError> {
+        loop {
+            let (stream, _) =
+                self.listener
+                    .accept()
+                    .await
+                    .map_err(|source| ServerError::Accept {
+                        what: "interactivity",
+                        source,
+                    })?;
+            let acknowledger = self.acknowledger.clone();
+            tokio::spawn(async move {
+                if let Err(e) = handle_connection(stream, &acknowledger).await {
+                    warn!("Interactivity connection error: {}", e);
+                }
+            });
+        }
+    }
+}
+
+async fn handle_connection(stream: TcpStream, acknowledger: &Acknowledger) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let request = tokio::time::timeout(
+        REQUEST_TIMEOUT,
+        read_request(BufReader::new(reader.take(MAX_REQUEST_LEN))),
+    )
+    .await
+    .unwrap_or(Err("408 Request Timeout"));
+    let now = chrono::Utc::now().timestamp().max(0) as u64;
+    let status = match request.and_then(|request| acknowledger.handle(&request, now)) {
+        Ok(reply) => {
+            // Slack expects the answer within 3 seconds, the message is replaced after
+            if let Some(reply) = reply {
+                let client = acknowledger.client.clone();
+                tokio::spawn(async move { replace_message(&client, reply).await });
+            }
+            "200 OK"
+        }
+        Err(status) => status,
+    };
+    writer
+        .write_all(
+            format!("HTTP/1.1 {status}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+                .as_bytes(),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Reads the request line, the headers and the body of its content-length. Returns
+/// the status of a request that cannot be read, the body is bounded before it is read.
+async fn read_request<R: tokio::io::AsyncBufRead + Unpin>(
+    mut reader: R,
+) -> Result<Request, &'static str> {
+    let mut line = String::new();
+    reader.read_line(&mut line).await.map_err(bad_request)?;
+    let method = line
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    let mut headers = HashMap::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await.map_err(bad_request)? == 0 {
+            return Err("400 Bad Request");
+        }
+        let Some((name, value)) = line.trim_end().split_once(':') else {
+            break;
+        };
+        headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+    }
+    let len = headers
+        .get("content-length")
+        .map(|len| len.parse::<u64>())
+        .transpose()
+        .map_err(|_| "400 Bad Request")?
+        .unwrap_or_default();
+    if len > MAX_REQUEST_LEN {
+        warn!("Refused an interactivity request of {} bytes", len);
+        return Err("413 Payload Too Large");
+    }
+    let mut body = vec![0; len as usize];
+    reader.read_exact(&mut body).await.map_err(bad_request)?;
+    Ok(Request {
+        method,
+        headers,
+        body,
+    })
+}
+
+fn bad_request(e: std::io::Error) -> &'static str {
+    warn!("Failed to read interactivity request: {}", e);
+    "400 Bad Request"
+}
+
+impl Acknowledger {
+    /// Acknowledges the alert of a signed click on its button, the other interactions
+    /// are ignored. Returns the status of a refused request.
+    fn handle(&self, request: &Request, now: u64) -> Result<Option<Reply>, &'static str> {
+        if request.method != "POST" {
+            return Err("405 Method Not Allowed");
+        }
+        let header = |name: &str| request.headers.get(name).map(String::as_str);
+        let signed = verify(
+            &self.signing_secret,
+            header("x-slack-request-timestamp").unwrap_or_default(),
+            header("x-slack-signature").unwrap_or_default(),
+            &request.body,
+            now,
+        );
+        if !signed {
+            warn!("Refused an interactivity request without a valid Slack signature");
+            return Err("401 Unauthorized");
+        }
+        let payload = form_urlencoded::parse(&request.body)
+            .find(|(name, _)| name == "payload")
+            .and_then(|(_, payload)| serde_json::from_str::<serde_json::Value>(&payload).ok())
+            .ok_or("400 Bad Request")?;
+        let Some(action) = payload["actions"].as_array().and_then(|actions| {
+            actions
+                .iter()
+                .find(|action| action["action_id"] == ACK_ACTION_ID)
+        }) else {
+            return Ok(None);
+        };
+        let key = action["value"]
+            .as_str()
+            .and_then(|key| key.parse().ok())
+            .ok_or("400 Bad Request")?;
+        let user = &payload["user"];
+        let by = [&user["username"], &user["name"], &user["id"]]
+            .into_iter()
+            .find_map(|name| name.as_str())
+            .unwrap_or("someone");
+        let sample = self.suppressions.acknowledge(key, by, self.window);
+        let window = format_duration(self.window);
+        info!("Alert acknowledged by {} for {}: {}", by, window, sample);
+
+        // the button is dropped from the message, the acknowledgment shown instead
+        let mention = user["id"]
+            .as_str()
+            .map_or_else(|| by.to_string(), |id| format!("<@{id}>"));
+        let text = format!(
+            "{}\n✅ Acknowledged by {mention}, repeats suppressed for {window}",
+            payload["message"]["text"].as_str().unwrap_or_default()
+        );
+        Ok(payload["response_url"].as_str().map(|response_url| Reply {
+            response_url: response_url.to_string(),
+            text,
+        }))
+    }
+}
+
+/// Checks the signature of a Slack request, the HMAC-SHA256 of `v0:{timestamp}:{body}`
+/// with the signing secret, signed at most 5 minutes from now.
+fn verify(secret: &str, timestamp: &str, signature: &str, body: &[u8], now: u64) -> bool {
+    let Ok(signed_at) = timestamp.parse::<u64>() else {
+        return false;
+    };
+    if signed_at.abs_diff(now) > MAX_SIGNATURE_AGE {
+        return false;
+    }
+    let Some(tag) = signature.strip_prefix("v0=").and_then(decode_hex) else {
+        return false;
+    };
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret.as_bytes()) else {
+        return false;
+    };
+    mac.update(format!("v0:{timestamp}:").as_bytes());
+    mac.update(body);
+    mac.verify_slice(&tag).is_ok()
+}
+
+/// Replaces the clicked message through the response URL of the click.
+async fn replace_message(client: &reqwest::Client, reply: Reply) {
+    let payload = serde_json::json!({ "replace_original": true, "text": reply.text });
+    let res = client
+        .post(&reply.response_url)
+        .timeout(Duration::from_secs(10))
+        .json(&payload)
+        .send()
+        .await;
+    match res {
+        Ok(res) if res.status().is_success() => {}
+        Ok(res) => error!(
+            "Failed to update the acknowledged alert, status: {}",
+            res.status()
+        ),
+        Err(e) => error!("Failed to update the acknowledged alert: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alert::{Alert, AlertKind};
+    use crate::suppression::Suppressor;
+
+    #[test]
+    fn test_verify() {
+        // the example of the Slack documentation
+        let secret = "8f742231b10e8888abcd99yyyzzz85a5";
+        let body = b"token=xyzz0WbapA4vBCDEFasx0q6G&team_id=T1DC2JH3J&team_domain=testteamnow&channel_id=G8PSS9T3V&channel_name=foobar&user_id=U2CERLKJA&user_name=roadrunner&command=%2Fwebhook-collect&text=&response_url=https%3A%2F%2Fhooks.slack.com%2Fcommands%2FT1DC2JH3J%2F397700885554%2F96rGlfmibIGlgcZRskXaIFfN&trigger_id=398738663015.47445629121.803a0bc887a14d10d2c447fce8b6703c";
+        let signature = "v0=a2114d57b48eac39b9ad189dd8316235a7b4a8d21a10bd27519666489c69b503";
+        let now = 1531420618;
+        assert!(verify(secret, "1531420618", signature, body, now));
+        assert!(!verify(
+            secret,
+            "1531420618",
+            signature,
+            b"token=other",
+            now
+        ));
+        assert!(!verify("other", "1531420618", signature, body, now));
+        assert!(!verify(secret, "1531420619", signature, body, now));
+        // an old request is refused even with a valid signature
+        assert!(!verify(secret, "1531420618", signature, body, now + 301));
+        assert!(!verify(secret, "1531420618", "a2114d57", body, now));
+    }
+
+    #[tokio::test]
+    async fn test_read_request() {
+        let request = read_request(&b"POST /slack HTTP/1.1\r\nContent-Length: 4\r\n\r\nbody"[..])
+            .await
+            .unwrap();
+        assert_eq!(
+            (request.method.as_str(), &request.body[..]),
+            ("POST", &b"body"[..])
+        );
+        // a large body is refused before it is allocated
+        let oversized = read_request(
+            &b"POST /slack HTTP/1.1\r\nContent-Length: 18446744073709551615\r\n\r\n"[..],
+        )
+        .await;
+        assert_eq!(oversized.err(), Some("413 Payload Too Large"));
+        let truncated =
+            read_request(&b"POST /slack HTTP/1.1\r\nContent-Length: 9\r\n\r\nbody"[..]).await;
+        assert_eq!(truncated.err(), Some("400 Bad Request"));
+    }
+
+    #[tokio::test]
+    async fn test_acknowledge() {
+        let suppressor = Suppressor::default();
+        let acknowledger = Acknowledger {
+            signing_secret: "secret".to_string(),
+            window: Duration::from_secs(3600),
+            suppressions: suppressor.suppressions(),
+            client: reqwest::Client::new(),
+        };
+        let alert = |message: &str| Alert {
+            kind: AlertKind::Rule,
+            always_notify: true,
+            ..Alert::new(message)
+        };
+        assert!(suppressor.admit(&alert("disk full")).await);
+
+        let payload = serde_json::json!({
+            "type": "block_actions",
+            "user": { "id": "U01", "username": "ada" },
+            "actions": [{
+                "action_id": ACK_ACTION_ID,
+                "value": crate::suppression::key("disk full").to_string(),
+            }],
+            "message": { "text": "disk full" },
+            "response_url": "https://hooks.slack.com/actions/T01/1/abc",
+        });
+        let body = form_urlencoded::Serializer::new(String::new())
+            .append_pair("payload", &payload.to_string())
+            .finish()
+            .into_bytes();
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"secret").unwrap();
+        mac.update(b"v0:1700000000:");
+        mac.update(&body);
+        let signature = mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>();
+        let request = |signature: &str| Request {
+            method: "POST".to_string(),
+            headers: HashMap::from([
+                (
+                    "x-slack-request-timestamp".to_string(),
+                    "1700000000".to_string(),
+                ),
+                ("x-slack-signature".to_string(), format!("v0={signature}")),
+            ]),
+            body: body.clone(),
+        };
+        assert_eq!(
+            acknowledger.handle(&request("00"), 1700000000).err(),
+            Some("401 Unauthorized")
+        );
+        let reply = acknowledger
+            .handle(&request(&signature), 1700000000)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            reply.text,
+            "disk full\n✅ Acknowledged by <@U01>, repeats suppressed for 1h"
+        );
+
+        // the acknowledged alert is suppressed, critical included, the others are not
+        assert!(!suppressor.admit(&alert("disk full")).await);
+        assert!(suppressor.admit(&alert("db down")).await);
+    }
+}
@@ -0,0 +1,112 @@
+use std::cmp::Reverse;
+
+use crate::config::{AlertRule, Config};
+use crate::error::MatcherError;
+use crate::processor::{MatchLimits, Pattern};
+
+/// Finds the alert rules that an earlier rule likely shadows: only the first matching
+/// rule alerts, so a broad early rule swallows the lines of a specific later one.
+/// Returns one warning per shadowed rule, for all the monitors.
+pub fn lint(config: &Config) -> Result<Vec<String>, MatcherError> {
+    let mut warnings = Vec::new();
+    for (monitor, config) in config.monitors() {
+        for (shadowing, shadowed) in shadowed(&config.alerts)? {
+            warnings.push(format!(
+                "monitor '{}': rule '{}' is shadowed by rule '{}' matched before it",
+                monitor, config.alerts[shadowed].name, config.alerts[shadowing].name
+            ));
+        }
+    }
+    Ok(warnings)
+}
+
+/// Returns the (shadowing, shadowed) pairs of rule indices. The check is heuristic:
+/// a rule is shadowed when an earlier one matches its pattern, taken as an example
+/// line, or a line written from it.
+fn shadowed(rules: &[AlertRule]) -> Result<Vec<(usize, usize)>, MatcherError> {
+    // the order the matcher tries the rules in
+    let mut order = (0..rules.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| Reverse(rules[i].priority));
+    let patterns = rules
+        .iter()
+        .enumerate()
+        .map(|(i, rule)| Pattern::new(rule, i, MatchLimits::default()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut pairs = Vec::new();
+    for (pos, &later) in order.iter().enumerate() {
+        let pattern = &rules[later].pattern;
+        let samples = [pattern.clone(), sample(pattern)];
+        let shadowing = order[..pos]
+            .iter()
+            .find(|&&earlier| samples.iter().any(|s| patterns[earlier].is_match(s)));
+        if let Some(&earlier) = shadowing {
+            pairs.push((earlier, later));
+        }
+    }
+    Ok(pairs)
+}
+
+/// Writes a line the pattern would likely match: the flags, anchors, groups and
+/// quantifiers are dropped, character classes replaced by their first character.
+fn sample(pattern: &str) -> String {
+    // leading flags, e.g. `(?i)`
+    let pattern = pattern
+        .strip_prefix("(?")
+        .and_then(|rest| rest.split_once(')'))
+        .filter(|(flags, _)| flags.chars().all(|c| c.is_ascii_alphabetic() || c == '-'))
+        .map_or(pattern, |(_, rest)| rest);
+    let mut out = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('d') => out.push('0'),
+                Some('s') => out.push(' '),
+                Some('w') => out.push('a'),
+                Some(c) => out.push(c),
+                None => {}
+            },
+            '[' => {
+                // negated classes are left out, their characters are unknown
+                let class = chars.by_ref().take_while(|&c| c != ']').collect::<String>();
+                out.extend(class.chars().next().filter(|&c| c != '^'));
+            }
+            '{' => {
+                chars.by_ref().take_while(|&c| c != '}').for_each(drop);
+            }
+            '.' => out.push('a'),
+            '^' | '$' | '*' | '+' | '?' | '(' | ')' => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shadowed() {
+        let rule = |name: &str, pattern: &str, priority: i32| AlertRule {
+            name: name.to_string(),
+            pattern: pattern.to_string(),
+            priority,
+            ..Default::default()
+        };
+        let rules = [
+            rule("error", "(?i)error", 0),
+            rule("disk", r"^disk error: \w+ full$", 0),
+            rule("memory", "memory", 0),
+            rule("oom", "[Oo]ut of memory", 0),
+        ];
+        assert_eq!(shadowed(&rules).unwrap(), [(0, 1), (2, 3)]);
+        assert_eq!(sample(r"^disk error: \w+ full$"), "disk error: a full");
+        assert_eq!(sample(r"(?i)[^x]y{2,3}\d+"), "y0");
+
+        // a higher priority matches the specific rule first
+        let rules = [rule("error", "(?i)error", 0), rule("disk", "disk error", 1)];
+        assert!(shadowed(&rules).unwrap().is_empty());
+    }
+}
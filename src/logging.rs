@@ -0,0 +1,48 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::config::LogFormat;
+
+const LOG_FORMAT_ENV: &str = "LOG_ALERT_LOG_FORMAT";
+
+// whether the logs are emitted as JSON, switchable after the config is loaded
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Initializes the logger, the format is taken from `LOG_ALERT_LOG_FORMAT` if set.
+pub fn init() {
+    if let Ok(format) = std::env::var(LOG_FORMAT_ENV) {
+        JSON.store(format.eq_ignore_ascii_case("json"), Ordering::Relaxed);
+    }
+
+    env_logger::Builder::from_default_env()
+        .format(|buf, record| {
+            if JSON.load(Ordering::Relaxed) {
+                let entry = serde_json::json!({
+                    "timestamp": buf.timestamp().to_string(),
+                    "level": record.level().as_str(),
+                    "target": record.target(),
+                    "message": record.args().to_string(),
+                });
+                writeln!(buf, "{entry}")
+            } else {
+                // same layout as the env_logger default format
+                let style = buf.default_level_style(record.level());
+                writeln!(
+                    buf,
+                    "[{} {style}{:<5}{style:#} {}] {}",
+                    buf.timestamp(),
+                    record.level(),
+                    record.target(),
+                    record.args()
+                )
+            }
+        })
+        .init();
+}
+
+/// Applies the format from the config, unless it was set by the environment.
+pub fn set_format(format: LogFormat) {
+    if std::env::var(LOG_FORMAT_ENV).is_err() {
+        JSON.store(format == LogFormat::Json, Ordering::Relaxed);
+    }
+}
@@ -1,14 +1,54 @@
+mod alert;
+mod backoff;
+mod budget;
+mod channel;
 mod config;
+mod context;
+mod control;
+mod deadman;
+mod dedup;
+mod error;
+mod filelog;
+mod interactivity;
+mod lint;
+mod logging;
+mod metrics;
+mod mqtt;
+mod notifier;
+mod opsgenie;
 mod processor;
+mod pushover;
+mod recent;
+mod reload;
+mod replay;
 mod slack;
+mod suppression;
+mod syslog;
+
+use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Result;
 use config::*;
-use log::info;
+use log::{error, info, warn};
 use tokio::select;
 
-use self::processor::JournalProcessor;
+use self::alert::{Alert, AlertKind};
+use self::backoff::Backoff;
+use self::budget::AlertBudget;
+use self::control::{ControlContext, ControlServer};
+use self::dedup::SharedDedup;
+use self::error::NotifierError;
+use self::interactivity::InteractivityServer;
+use self::processor::Warmup;
+use self::reload::SharedState;
 use self::slack::Slack;
+use self::suppression::{DedupKey, Suppressions};
+
+// how long the pending alerts are given to be sent on exit
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(30);
+// wait before starting a failed notifier again, doubled on each consecutive failure
+const NOTIFIER_RESTART_DELAY: Duration = Duration::from_secs(5);
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -26,29 +66,304 @@ async fn main() -> Result<()> {
         return Ok(());
     }
 
-    env_logger::init();
+    logging::init();
     info!("Starting {binary_name}...");
     info!(
         "{} version {} (git commit {})",
         binary_name, version, git_hash
     );
     // load configuration
-    let config_path = std::env::var("LOG_ALERT_CONFIG").ok();
-    let config = Config::load(config_path)?;
+    let config_path = Config::path(std::env::var("LOG_ALERT_CONFIG").ok())?;
+    let config = Config::load(Some(config_path.clone()))?;
+    logging::set_format(config.log_format);
 
-    // prepare communication channel
-    let (tx, rx) = flume::unbounded::<String>();
+    // print the resolved config and exit
+    if args.len() > 1 && args[1] == "--print-config" {
+        let flags = &args[2..];
+        let json = flags.iter().any(|a| a == "--json");
+        let redact = flags.iter().any(|a| a == "--redact");
+        println!("{}", config.render(json, redact)?);
+        return Ok(());
+    }
+
+    // report the config keys that are not settings and exit, failing if there are any
+    if args.len() > 1 && args[1] == "--check" {
+        for key in &config.unknown_keys {
+            println!("warning: unknown key '{key}'");
+        }
+        if !config.unknown_keys.is_empty() {
+            return Err(anyhow::anyhow!(
+                "{} unknown config keys found",
+                config.unknown_keys.len()
+            ));
+        }
+        println!("Config is valid");
+        return Ok(());
+    }
+
+    // report the rules shadowed by earlier ones and exit, failing if there are any
+    if args.len() > 1 && args[1] == "--lint" {
+        let warnings = lint::lint(&config)?;
+        for warning in &warnings {
+            println!("warning: {warning}");
+        }
+        if !warnings.is_empty() {
+            return Err(anyhow::anyhow!("{} shadowed rules found", warnings.len()));
+        }
+        println!("No shadowed rules found");
+        return Ok(());
+    }
+
+    // replay a log file against the rules instead of following the journal
+    if args.len() > 1 && args[1] == "--replay" {
+        let path = args
+            .get(2)
+            .ok_or_else(|| anyhow::anyhow!("Usage: {binary_name} --replay <file>"))?;
+        return Ok(replay::replay(&config, path)?);
+    }
+
+    // match a pattern against the recent journal without the configured rules
+    if args.len() > 1 && args[1] == "--preview" {
+        let pattern = args.get(2).ok_or_else(|| {
+            anyhow::anyhow!("Usage: {binary_name} --preview <pattern> [--since <time>]")
+        })?;
+        let since = match args.get(3).map(String::as_str) {
+            Some("--since") => args
+                .get(4)
+                .ok_or_else(|| anyhow::anyhow!("--since needs a time, e.g. -2h"))?,
+            _ => replay::DEFAULT_PREVIEW_SINCE,
+        };
+        return Ok(replay::preview(&config, pattern, since)?);
+    }
+
+    // follow the journal but print the alerts instead of sending them
+    let test_mode = args.len() > 1 && args[1] == "--test";
+    if test_mode {
+        info!("Test mode, alerts are printed to stdout and not sent");
+    }
+
+    // the runtime state shared by the processors and the notifier
+    let state = SharedState {
+        warmup: Arc::new(Warmup::new(Duration::from_secs(
+            config.startup_suppress_secs,
+        ))),
+        ..SharedState::default()
+    };
     // setup notifier and journal processor
-    let slack = Slack::new(config.slack_webhook_url.clone());
-    let processor = JournalProcessor::new(&config)?;
+    let mut slack = Slack::new(config.slack_webhook_url.clone())
+        .with_webhook_headers(config.header_map()?)
+        .with_suppressions(
+            Suppressions::new(config.max_suppression_entries, state.metrics.clone())
+                .with_dedup_key(DedupKey::new(&config.dedup_key)?),
+        )
+        .with_suppression_ttl(config.suppression_ttl.clone())
+        .with_send_retry(config.send_retry)
+        .with_suppress_after(config.suppress_after)
+        .with_recent_alerts(config.recent_alerts)
+        .with_identity(config.slack_identity.clone())
+        .with_severity_labels(config.severity_labels.clone())
+        .with_message_wrap(config.message_prefix.clone(), config.message_suffix.clone())
+        .with_send_concurrency(config.send_concurrency);
+    if !config.slack_token.is_empty() {
+        slack = slack
+            .with_api(
+                config.slack_token.clone(),
+                config.slack_channel.clone(),
+                Duration::from_secs(config.slack_thread_ttl),
+            )
+            .with_severity_channels(config.slack_channels.clone())
+            .with_severity_reactions(config.slack_reactions.clone())
+            .with_context_upload(config.slack_context_upload);
+        if config.slack_thread_heartbeats {
+            slack = slack.with_heartbeat_threads();
+        }
+        if config.slack_ack.is_some() {
+            slack = slack.with_ack_button();
+        }
+    }
+    if !config.redis_url.is_empty() && !test_mode {
+        let shared_dedup =
+            SharedDedup::connect(&config.redis_url, config.redis_dedup_window).await?;
+        slack = slack.with_shared_dedup(shared_dedup);
+    }
+    if config.suppression_shared() {
+        slack = slack.with_shared_suppression();
+    }
+    // the notifiers besides Slack, not in test mode
+    let notifiers = if test_mode {
+        Vec::new()
+    } else {
+        notifier::notifiers(&config)?
+    };
+    // one processor per monitor, the runtime state is shared
+    let processors = reload::processors(&config, &state)?;
+    // prepare communication channel
+    let (tx, rx) = channel::channel(&config, state.metrics.clone());
+    // reload the rules on SIGHUP and when the config file changes
+    let (reload_tx, reload_rx) = flume::unbounded();
+    tokio::spawn(reload::watch_sighup(reload_tx.clone()));
+    if config.auto_reload_secs > 0 {
+        let interval = Duration::from_secs(config.auto_reload_secs);
+        tokio::spawn(reload::watch_mtime(
+            config_path.clone(),
+            interval,
+            reload_tx.clone(),
+        ));
+    }
+    // the daily alert budget, spent in front of the notifiers
+    let budget = Arc::new(AlertBudget::new(
+        config.max_alerts_per_day,
+        config.budget_bypass_always_notify,
+    ));
+    // setup the optional control socket
+    let control = if config.control_socket.is_empty() || test_mode {
+        None
+    } else {
+        let context = ControlContext {
+            silences: state.silences.clone(),
+            metrics: state.metrics.clone(),
+            suppressions: slack.suppressions(),
+            recent: slack.recent_alerts(),
+            budget: budget.clone(),
+        };
+        Some(ControlServer::bind(&config.control_socket, context)?)
+    };
+    // setup the optional endpoint of the Acknowledge button clicks
+    let interactivity = match &config.slack_ack {
+        Some(ack) if !test_mode => {
+            Some(InteractivityServer::bind(ack, slack.suppressions()).await?)
+        }
+        _ => None,
+    };
 
     // signal startup complete
-    tx.send(format!("{binary_name} has started"))?;
+    if config.notify_on_start {
+        tx.send(Alert::new(format!("{binary_name} has started")))
+            .await?;
+    }
+    // mute the alerts from the start in maintenance mode
+    tx.set_maintenance(config.maintenance).await?;
+
+    // summarize the alerts held back once the startup window is over
+    let summary = state.warmup.until().map(|until| {
+        let warmup = state.warmup.clone();
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep_until(until.into()).await;
+            if let Some(summary) = warmup.summary() {
+                tx.send(summary)
+                    .await
+                    .inspect_err(|e| error!("Failed to send startup summary: {}", e))
+                    .ok();
+            }
+        })
+    });
+
+    // ping the deadman URL while the journals are read
+    if !config.deadman_url.is_empty() && !test_mode {
+        tokio::spawn(deadman::run(
+            config.deadman_url.clone(),
+            Duration::from_secs(config.deadman_interval),
+            state.metrics.clone(),
+        ));
+    }
+
+    // exit after the maximum uptime, for systemd to restart the process
+    let max_uptime = Duration::from_secs(config.max_uptime_secs);
+    if !max_uptime.is_zero() {
+        let at = chrono::Local::now() + max_uptime;
+        info!(
+            "Restart scheduled after {} of uptime, at {}",
+            control::format_duration(max_uptime),
+            at.format("%Y-%m-%d %H:%M:%S")
+        );
+    }
 
-    // start both tasks
+    // start both tasks, the channel is closed with `closer` once they stop
+    let closer = tx.clone();
+    let (config, slack) = (&config, &slack);
+    // the notifiers are created again when restarted after a failure
+    let mut notifiers = Some(notifiers);
+    let notifier = notifier::supervise(
+        rx,
+        config.on_notifier_failure,
+        Backoff::new(NOTIFIER_RESTART_DELAY),
+        move |rx| {
+            let notifiers = notifiers.take();
+            let budget = budget.clone();
+            async move {
+                let notifiers = match notifiers {
+                    Some(notifiers) => notifiers,
+                    None if test_mode => Vec::new(),
+                    None => notifier::notifiers(config)?,
+                };
+                if test_mode {
+                    print_alerts(rx).await
+                } else if notifiers.is_empty() && !config.suppression_shared() {
+                    slack.start(rx).await
+                } else {
+                    notifier::run(config, rx, slack, notifiers, budget).await
+                }
+            }
+        },
+    );
+    tokio::pin!(notifier);
     select! {
-        res = slack.start(rx) => res?,
-        res = processor.start(tx) => res?,
+        res = &mut notifier => return Ok(res?),
+        res = reload::run(config, &config_path, processors, state, tx, reload_rx) => res?,
+        res = async {
+            match &control {
+                Some(control) => control.start().await,
+                None => std::future::pending().await,
+            }
+        } => res?,
+        res = async {
+            match &interactivity {
+                Some(interactivity) => interactivity.start().await,
+                None => std::future::pending().await,
+            }
+        } => res?,
+        _ = async {
+            if max_uptime.is_zero() {
+                std::future::pending().await
+            } else {
+                tokio::time::sleep(max_uptime).await
+            }
+        } => info!("Maximum uptime of {} reached, exiting for a restart", control::format_duration(max_uptime)),
+    }
+
+    // the processors are stopped, the notifier sends the queued alerts and exits
+    if let Some(summary) = summary {
+        summary.abort();
+    }
+    closer.close();
+    info!("Sending the pending alerts before exiting");
+    match tokio::time::timeout(FLUSH_TIMEOUT, notifier).await {
+        Ok(res) => Ok(res?),
+        Err(_) => {
+            warn!(
+                "Pending alerts not sent within {:?}, exiting anyway",
+                FLUSH_TIMEOUT
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Prints the alerts with the rule that produced them, in place of the notifier.
+async fn print_alerts(rx: flume::Receiver<Alert>) -> Result<(), NotifierError> {
+    while let Ok(alert) = rx.recv_async().await {
+        let kind = match alert.kind {
+            AlertKind::Rule => "alert",
+            AlertKind::Heartbeat => "heartbeat",
+            AlertKind::Recovery => "recovery",
+            AlertKind::System => "system",
+        };
+        println!(
+            "[{kind}] [{}] {}",
+            alert.rule.as_deref().unwrap_or("-"),
+            alert.text()
+        );
     }
     Ok(())
 }
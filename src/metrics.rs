@@ -0,0 +1,252 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use dashmap::DashMap;
+
+/// Series of a metric per rule, keyed by the monitor, the rule index in the monitor and
+/// its name, or the pattern for heartbeats that have no name.
+pub type PerRule = DashMap<(String, usize, String), u64>;
+
+/// Counts a running task in a gauge until dropped, including when the task is aborted.
+pub struct Running<'a>(&'a AtomicU64);
+
+impl<'a> Running<'a> {
+    pub fn new(gauge: &'a AtomicU64) -> Self {
+        gauge.fetch_add(1, Ordering::Relaxed);
+        Running(gauge)
+    }
+}
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Process wide counters and gauges, rendered in the Prometheus text format.
+#[derive(Default)]
+pub struct Metrics {
+    // number of journal lines read
+    pub lines_processed: AtomicU64,
+    // number of lines dropped by the `max_lines_per_sec` guard
+    pub lines_dropped: AtomicU64,
+    // number of lines matching an alert rule
+    pub alerts_matched: AtomicU64,
+    // bytes read from journalctl but not yet processed
+    pub read_buffer_bytes: AtomicU64,
+    // messages queued for the notifier
+    pub channel_depth: AtomicU64,
+    // alerts discarded because the channel was full
+    pub alerts_dropped: AtomicU64,
+    // messages tracked by the duplicate suppression
+    pub suppression_entries: AtomicU64,
+    // suppression entries evicted to stay within the maximum
+    pub suppression_evictions: AtomicU64,
+    // monitors of the running config
+    pub monitors: AtomicU64,
+    // journal readers currently running, journalctl or native, one per monitor when healthy
+    pub journal_readers: AtomicU64,
+    // lines matching each alert rule
+    pub rule_matches: PerRule,
+    // alerted misses of each heartbeat
+    pub heartbeat_misses: PerRule,
+    // recoveries of each heartbeat
+    pub heartbeat_recoveries: PerRule,
+    // 1 while the heartbeat is overdue, 0 otherwise
+    pub heartbeat_overdue: PerRule,
+}
+
+impl Metrics {
+    pub fn inc(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set(gauge: &AtomicU64, value: u64) {
+        gauge.store(value, Ordering::Relaxed);
+    }
+
+    pub fn inc_rule(counter: &PerRule, monitor: &str, index: usize, name: &str) {
+        *counter
+            .entry((monitor.to_string(), index, name.to_string()))
+            .or_default() += 1;
+    }
+
+    pub fn set_rule(gauge: &PerRule, monitor: &str, index: usize, name: &str, value: u64) {
+        gauge.insert((monitor.to_string(), index, name.to_string()), value);
+    }
+
+    /// Forgets the series of every rule, when a reload replaces the rules.
+    pub fn clear_rules(&self) {
+        self.rule_matches.clear();
+        self.heartbeat_misses.clear();
+        self.heartbeat_recoveries.clear();
+        self.heartbeat_overdue.clear();
+    }
+
+    /// Whether every monitor is reading its journal.
+    pub fn journals_up(&self) -> bool {
+        let monitors = self.monitors.load(Ordering::Relaxed);
+        monitors > 0 && self.journal_readers.load(Ordering::Relaxed) >= monitors
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let metrics = [
+            (
+                "journal_alerts_lines_processed_total",
+                "counter",
+                "Journal lines read",
+                &self.lines_processed,
+            ),
+            (
+                "journal_alerts_lines_dropped_total",
+                "counter",
+                "Journal lines dropped above max_lines_per_sec",
+                &self.lines_dropped,
+            ),
+            (
+                "journal_alerts_alerts_matched_total",
+                "counter",
+                "Journal lines matching an alert rule",
+                &self.alerts_matched,
+            ),
+            (
+                "journal_alerts_alerts_dropped_total",
+                "counter",
+                "Alerts discarded because the channel was full",
+                &self.alerts_dropped,
+            ),
+            (
+                "journal_alerts_suppression_evictions_total",
+                "counter",
+                "Suppression entries evicted to stay within the maximum",
+                &self.suppression_evictions,
+            ),
+            (
+                "journal_alerts_read_buffer_bytes",
+                "gauge",
+                "Bytes read from journalctl but not yet processed",
+                &self.read_buffer_bytes,
+            ),
+            (
+                "journal_alerts_channel_depth",
+                "gauge",
+                "Messages queued for the notifier",
+                &self.channel_depth,
+            ),
+            (
+                "journal_alerts_suppression_entries",
+                "gauge",
+                "Messages tracked by the duplicate suppression",
+                &self.suppression_entries,
+            ),
+            (
+                "journal_alerts_monitors",
+                "gauge",
+                "Monitors of the running config",
+                &self.monitors,
+            ),
+            (
+                "journal_alerts_journal_readers",
+                "gauge",
+                "Journal readers currently running",
+                &self.journal_readers,
+            ),
+        ];
+        for (name, kind, help, value) in metrics {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} {kind}");
+            let _ = writeln!(out, "{name} {}", value.load(Ordering::Relaxed));
+        }
+
+        let per_rule = [
+            (
+                "journal_alerts_rule_matches_total",
+                "counter",
+                "Journal lines matching the alert rule",
+                "rule",
+                &self.rule_matches,
+            ),
+            (
+                "journal_alerts_heartbeat_misses_total",
+                "counter",
+                "Alerted misses of the heartbeat",
+                "pattern",
+                &self.heartbeat_misses,
+            ),
+            (
+                "journal_alerts_heartbeat_recoveries_total",
+                "counter",
+                "Recoveries of the heartbeat",
+                "pattern",
+                &self.heartbeat_recoveries,
+            ),
+            (
+                "journal_alerts_heartbeat_overdue",
+                "gauge",
+                "Whether the heartbeat is currently overdue",
+                "pattern",
+                &self.heartbeat_overdue,
+            ),
+        ];
+        for (name, kind, help, label, series) in per_rule {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} {kind}");
+            // sorted so that the output is stable between scrapes
+            let mut series = series
+                .iter()
+                .map(|e| (e.key().clone(), *e.value()))
+                .collect::<Vec<_>>();
+            series.sort();
+            for ((monitor, index, value_label), value) in series {
+                let _ = writeln!(
+                    out,
+                    "{name}{{monitor=\"{}\",{label}=\"{}\",index=\"{index}\"}} {value}",
+                    escape(&monitor),
+                    escape(&value_label)
+                );
+            }
+        }
+        out
+    }
+}
+
+/// Escapes a label value, patterns often contain backslashes and quotes.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_per_rule() {
+        let metrics = Metrics::default();
+        Metrics::inc_rule(&metrics.rule_matches, "app", 1, "disk");
+        Metrics::inc_rule(&metrics.rule_matches, "app", 1, "disk");
+        Metrics::inc_rule(&metrics.rule_matches, "app", 0, "oom");
+        // the same rule index in another monitor is a series of its own
+        Metrics::inc_rule(&metrics.rule_matches, "db", 0, "oom");
+        Metrics::set_rule(&metrics.heartbeat_overdue, "app", 0, r#"ok "\d+""#, 1);
+        let rendered = metrics.render();
+        assert!(rendered.contains(
+            "journal_alerts_rule_matches_total{monitor=\"app\",rule=\"oom\",index=\"0\"} 1\n\
+             journal_alerts_rule_matches_total{monitor=\"app\",rule=\"disk\",index=\"1\"} 2\n\
+             journal_alerts_rule_matches_total{monitor=\"db\",rule=\"oom\",index=\"0\"} 1\n"
+        ));
+        assert!(rendered.contains(
+            r#"journal_alerts_heartbeat_overdue{monitor="app",pattern="ok \"\\d+\"",index="0"} 1"#
+        ));
+
+        // a reload forgets the series of the replaced rules
+        metrics.clear_rules();
+        let rendered = metrics.render();
+        assert!(!rendered.contains("monitor="));
+        assert!(rendered.contains("# TYPE journal_alerts_rule_matches_total counter\n"));
+    }
+}
@@ -0,0 +1,184 @@
+use std::time::Duration;
+
+use flume::Receiver;
+use log::{debug, error, info, warn};
+use rumqttc::{AsyncClient, Event, MqttOptions, QoS, Transport};
+use tokio::time::sleep;
+
+use crate::alert::{Alert, AlertKind};
+use crate::config::{MqttConfig, Severity};
+use crate::error::NotifierError;
+use crate::suppression::{DedupKey, Suppressions, Suppressor};
+
+// requests queued for the broker while it is unreachable, newer alerts are dropped
+const QUEUE_CAPACITY: usize = 100;
+// delay before connecting again after the broker connection failed
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Publishes the alerts to an MQTT broker, e.g. for home automation.
+pub struct Mqtt {
+    config: MqttConfig,
+    suppressor: Option<Suppressor>,
+}
+
+impl Mqtt {
+    pub fn new(config: MqttConfig) -> Self {
+        Mqtt {
+            suppressor: config
+                .suppression_ttl
+                .clone()
+                .map(|ttl| Suppressor::default().with_suppression_ttl(ttl)),
+            config,
+        }
+    }
+
+    /// Detects the duplicates on the text built by the `dedup_key` steps.
+    pub fn with_dedup_key(mut self, dedup_key: DedupKey) -> Self {
+        self.suppressor = self.suppressor.map(|suppressor| {
+            suppressor.with_suppressions(Suppressions::default().with_dedup_key(dedup_key))
+        });
+        self
+    }
+
+    pub async fn start(&self, rx: Receiver<Alert>) -> Result<(), NotifierError> {
+        info!("MQTT notifier started.");
+        let client = (!self.config.host.is_empty()).then(|| self.connect());
+        loop {
+            let Ok(alert) = rx.recv_async().await else {
+                info!("MQTT notifier receiver channel closed, exiting.");
+                break;
+            };
+            // alerts routed here explicitly are published whatever their severity
+            if alert.notify.is_empty() && !self.config.severities.contains(&alert.severity) {
+                continue;
+            }
+            // duplicates are only suppressed when the block sets their windows
+            if let Some(suppressor) = &self.suppressor {
+                let admitted = suppressor.admit(&alert).await;
+                suppressor.suppressions().sweep();
+                if !admitted {
+                    continue;
+                }
+            }
+            let Some(client) = &client else {
+                info!("{}", alert.text());
+                continue;
+            };
+            // never wait on a broker that is down, the queue is bounded
+            let (topic, qos) = (self.topic(alert.severity), qos(alert.severity));
+            if let Err(e) = client.try_publish(topic, qos, false, payload(&alert).to_string()) {
+                error!("Error publishing alert to MQTT: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts the connection to the broker, reconnecting whenever it fails.
+    fn connect(&self) -> AsyncClient {
+        let config = &self.config;
+        let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+        options.set_keep_alive(Duration::from_secs(30));
+        if !config.username.is_empty() {
+            options.set_credentials(&config.username, &config.password);
+        }
+        if config.tls {
+            options.set_transport(Transport::tls_with_default_config());
+        }
+        let (client, mut eventloop) = AsyncClient::new(options, QUEUE_CAPACITY);
+        tokio::spawn(async move {
+            loop {
+                // polling drives the connection, it reconnects on the next poll after an error
+                match eventloop.poll().await {
+                    Ok(Event::Incoming(event)) => debug!("MQTT event: {:?}", event),
+                    Ok(Event::Outgoing(_)) => {}
+                    Err(e) => {
+                        warn!("MQTT connection error, reconnecting: {}", e);
+                        sleep(RECONNECT_DELAY).await;
+                    }
+                }
+            }
+        });
+        client
+    }
+
+    /// The topic of the severity, e.g. `journal-alerts/critical`.
+    fn topic(&self, severity: Severity) -> String {
+        let severity = match severity {
+            Severity::Critical => "critical",
+            Severity::Warning => "warning",
+            Severity::Info => "info",
+        };
+        format!("{}/{}", self.config.topic, severity)
+    }
+}
+
+/// Critical alerts are delivered at least once, the others at most once.
+fn qos(severity: Severity) -> QoS {
+    match severity {
+        Severity::Critical => QoS::AtLeastOnce,
+        Severity::Warning | Severity::Info => QoS::AtMostOnce,
+    }
+}
+
+fn payload(alert: &Alert) -> serde_json::Value {
+    let kind = match alert.kind {
+        AlertKind::Rule => "alert",
+        AlertKind::Heartbeat => "heartbeat",
+        AlertKind::Recovery => "recovery",
+        AlertKind::System => "system",
+    };
+    serde_json::json!({
+        "message": alert.message,
+        "rule": alert.rule,
+        "severity": alert.severity,
+        "kind": kind,
+        "timestamp": alert.timestamp,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SuppressionTtl;
+
+    #[test]
+    fn test_payload() {
+        let mqtt = Mqtt::new(MqttConfig::default());
+        assert_eq!(mqtt.topic(Severity::Critical), "journal-alerts/critical");
+        assert_eq!(qos(Severity::Critical), QoS::AtLeastOnce);
+        let alert = Alert {
+            rule: Some("disk".to_string()),
+            kind: AlertKind::Rule,
+            ..Alert::new("disk full")
+        };
+        assert_eq!(
+            payload(&alert),
+            serde_json::json!({
+                "message": "disk full",
+                "rule": "disk",
+                "severity": "info",
+                "kind": "alert",
+                "timestamp": null,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_start_suppresses_duplicates() {
+        let config = MqttConfig {
+            suppression_ttl: Some(SuppressionTtl::default()),
+            ..MqttConfig::default()
+        };
+        let mqtt = Mqtt::new(config);
+        let (tx, rx) = flume::unbounded();
+        tx.send(Alert::new("disk full")).unwrap();
+        tx.send(Alert::new("disk full")).unwrap();
+        drop(tx);
+        mqtt.start(rx).await.unwrap();
+        // the suppressor saw the duplicate, this is its third occurrence
+        let suppressions = mqtt.suppressor.as_ref().unwrap().suppressions();
+        assert_eq!(suppressions.duplicate("disk full"), Some(3));
+        // without windows nothing is suppressed
+        assert!(Mqtt::new(MqttConfig::default()).suppressor.is_none());
+    }
+}
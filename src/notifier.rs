@@ -0,0 +1,414 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+
+use chrono::Local;
+use flume::{Receiver, Sender};
+use log::{error, warn};
+use tokio::task::JoinSet;
+use tokio::time::sleep;
+
+use crate::alert::Alert;
+use crate::backoff::Backoff;
+use crate::budget::AlertBudget;
+use crate::channel;
+use crate::config::{Config, FailurePolicy, SendRetry};
+use crate::control::format_duration;
+use crate::error::{self, NotifierError};
+use crate::filelog::FileLog;
+use crate::mqtt::Mqtt;
+use crate::opsgenie::Opsgenie;
+use crate::pushover::Pushover;
+use crate::slack::Slack;
+use crate::suppression::{DedupKey, Suppressor};
+use crate::syslog::Syslog;
+
+/// A notifier running next to Slack, fed the alerts routed to it.
+pub enum Notifier {
+    Pushover(Pushover),
+    Syslog(Syslog),
+    Mqtt(Mqtt),
+    FileLog(FileLog),
+    Opsgenie(Opsgenie),
+}
+
+impl Notifier {
+    /// The name the rules route their alerts to the notifier with.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Notifier::Pushover(_) => "pushover",
+            Notifier::Syslog(_) => "syslog",
+            Notifier::Mqtt(_) => "mqtt",
+            Notifier::FileLog(_) => "file_log",
+            Notifier::Opsgenie(_) => "opsgenie",
+        }
+    }
+
+    pub async fn start(&self, rx: Receiver<Alert>) -> Result<(), NotifierError> {
+        match self {
+            Notifier::Pushover(pushover) => pushover.start(rx).await,
+            Notifier::Syslog(syslog) => syslog.start(rx).await,
+            Notifier::Mqtt(mqtt) => mqtt.start(rx).await,
+            Notifier::FileLog(file_log) => file_log.start(rx).await,
+            Notifier::Opsgenie(opsgenie) => opsgenie.start(rx).await,
+        }
+    }
+}
+
+/// Creates the notifiers configured besides Slack. The settings of a notifier's
+/// block take precedence over the global ones.
+pub fn notifiers(config: &Config) -> Result<Vec<Notifier>, NotifierError> {
+    let mut notifiers = Vec::new();
+    let dedup_key = DedupKey::new(&config.dedup_key)?;
+    if let Some(pushover) = &config.pushover {
+        let suppression_ttl = pushover
+            .suppression_ttl
+            .clone()
+            .unwrap_or_else(|| config.suppression_ttl.clone());
+        let mut pushover = Pushover::new(pushover.clone())
+            .with_suppression_ttl(suppression_ttl)
+            .with_send_retry(pushover.send_retry.unwrap_or(config.send_retry))
+            .with_dedup_key(dedup_key.clone());
+        if config.suppression_shared() {
+            pushover = pushover.with_shared_suppression();
+        }
+        notifiers.push(Notifier::Pushover(pushover));
+    }
+    if let Some(syslog) = &config.syslog {
+        let syslog = Syslog::new(syslog.clone())?.with_dedup_key(dedup_key.clone());
+        notifiers.push(Notifier::Syslog(syslog));
+    }
+    if let Some(mqtt) = &config.mqtt {
+        let mqtt = Mqtt::new(mqtt.clone()).with_dedup_key(dedup_key);
+        notifiers.push(Notifier::Mqtt(mqtt));
+    }
+    if let Some(file_log) = &config.file_log {
+        let file_log = FileLog::new(file_log.clone()).with_timezone(config.timezone);
+        notifiers.push(Notifier::FileLog(file_log));
+    }
+    if let Some(opsgenie) = &config.opsgenie {
+        let opsgenie = Opsgenie::new(opsgenie.clone())
+            .with_send_retry(opsgenie.send_retry.unwrap_or(config.send_retry));
+        notifiers.push(Notifier::Opsgenie(opsgenie));
+    }
+    Ok(notifiers)
+}
+
+/// The outcome of a failed send attempt.
+pub enum Attempt {
+    /// A transient failure, e.g. a network error or rate limiting, with its reason.
+    Retry(String),
+    /// A failure that sending again would not fix, e.g. a rejected payload.
+    Fatal(NotifierError),
+}
+
+/// Sends until an attempt succeeds or fails for good, up to the attempts of the
+/// retry settings and doubling the delay between them. Returns the answer of the
+/// successful attempt.
+pub async fn send_with_retry<T, F, Fut>(
+    notifier: &'static str,
+    retry: &SendRetry,
+    mut send: F,
+) -> Result<T, NotifierError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Attempt>>,
+{
+    let (mut attempt, mut delay) = (1, retry.delay());
+    loop {
+        let error = match send().await {
+            Ok(answer) => return Ok(answer),
+            Err(Attempt::Fatal(e)) => return Err(e),
+            Err(Attempt::Retry(error)) => error,
+        };
+        if attempt >= retry.attempts {
+            return Err(NotifierError::Retries {
+                notifier,
+                attempts: attempt,
+                last: error,
+            });
+        }
+        warn!("Failed to send alert to {notifier}, retrying in {delay:?}. {error}");
+        sleep(delay).await;
+        (attempt, delay) = (attempt + 1, delay * 2);
+    }
+}
+
+/// Runs Slack and the other notifiers, each one getting the alerts routed to it.
+/// A full notifier holds up the others, so that the overflow policy still applies.
+/// With `shared_suppression` or a daily budget the duplicates are suppressed before the
+/// routing, with the suppression state of Slack, and before the budget is spent.
+pub async fn run(
+    config: &Config,
+    rx: Receiver<Alert>,
+    slack: &Slack,
+    notifiers: Vec<Notifier>,
+    budget: Arc<AlertBudget>,
+) -> Result<(), NotifierError> {
+    let notifier_channel = || match config.channel_capacity {
+        0 => flume::unbounded(),
+        capacity => flume::bounded(capacity),
+    };
+    let (rx, suppress) = if config.suppression_shared() {
+        let (tx, admitted) = notifier_channel();
+        (admitted, Some(suppress(rx, slack.suppressor(), tx)))
+    } else {
+        (rx, None)
+    };
+    let (rx, spend) = if config.max_alerts_per_day > 0 {
+        let (tx, admitted) = notifier_channel();
+        (admitted, Some(spend(rx, budget, tx)))
+    } else {
+        (rx, None)
+    };
+    let (slack_tx, slack_rx) = notifier_channel();
+    let mut routes = vec![("slack", slack_tx)];
+    let mut tasks = JoinSet::new();
+    for notifier in notifiers {
+        let (tx, rx) = notifier_channel();
+        routes.push((notifier.name(), tx));
+        tasks.spawn(async move { notifier.start(rx).await });
+    }
+    let pipeline = async {
+        let (_, _, _, res) = tokio::join!(
+            async {
+                if let Some(suppress) = suppress {
+                    suppress.await;
+                }
+            },
+            async {
+                if let Some(spend) = spend {
+                    spend.await;
+                }
+            },
+            channel::fan_out(rx, routes),
+            slack.start(slack_rx)
+        );
+        res
+    };
+    until_failed(pipeline, tasks).await
+}
+
+/// Runs the pipeline to the end, then waits for the notifiers, which exit once the fan
+/// out closed their channels. A notifier failing meanwhile ends the run with its error
+/// right away, for the notifiers to be restarted.
+async fn until_failed(
+    pipeline: impl Future<Output = Result<(), NotifierError>>,
+    mut tasks: JoinSet<Result<(), NotifierError>>,
+) -> Result<(), NotifierError> {
+    tokio::pin!(pipeline);
+    loop {
+        tokio::select! {
+            res = &mut pipeline => break res?,
+            Some(res) = tasks.join_next() => res??,
+        }
+    }
+    while let Some(res) = tasks.join_next().await {
+        res??;
+    }
+    Ok(())
+}
+
+/// Passes on the alerts that are not suppressed duplicates, until either side closes.
+async fn suppress(rx: Receiver<Alert>, suppressor: Suppressor, tx: Sender<Alert>) {
+    while let Ok(alert) = rx.recv_async().await {
+        if suppressor.admit(&alert).await && tx.send_async(alert).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Passes on the alerts within the daily budget, and the notice of the spent budget.
+async fn spend(rx: Receiver<Alert>, budget: Arc<AlertBudget>, tx: Sender<Alert>) {
+    while let Ok(alert) = rx.recv_async().await {
+        let Some(alert) = budget.admit(alert, Local::now().date_naive()) else {
+            continue;
+        };
+        if tx.send_async(alert).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Runs the notifier started by `start` until the alert channel closes. When it fails
+/// and the policy is to restart, it is started again after the backoff, unless the
+/// error is fatal.
+pub async fn supervise<F, Fut>(
+    rx: Receiver<Alert>,
+    policy: FailurePolicy,
+    mut backoff: Backoff,
+    mut start: F,
+) -> Result<(), NotifierError>
+where
+    F: FnMut(Receiver<Alert>) -> Fut,
+    Fut: Future<Output = Result<(), NotifierError>>,
+{
+    loop {
+        let started = Instant::now();
+        // the alerts queued meanwhile stay in the channel, `rx` keeps it open
+        match start(rx.clone()).await {
+            Err(e)
+                if policy == FailurePolicy::Restart && !e.is_fatal() && !rx.is_disconnected() =>
+            {
+                let delay = backoff.delay(started.elapsed());
+                error!(
+                    "Notifier failed, restarting in {}: {}",
+                    format_duration(delay),
+                    error::report(&e)
+                );
+                sleep(delay).await;
+            }
+            res => return res,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Duration;
+
+    use super::*;
+    use crate::alert::AlertKind;
+    use crate::error::ConfigError;
+
+    #[tokio::test]
+    async fn test_send_with_retry() {
+        let retry = SendRetry {
+            attempts: 3,
+            delay_ms: 0,
+        };
+        let attempts = AtomicU32::new(0);
+        let send = |fatal: bool| {
+            let attempts = &attempts;
+            move || async move {
+                attempts.fetch_add(1, Ordering::Relaxed);
+                if fatal {
+                    Err::<(), _>(Attempt::Fatal(NotifierError::AllWebhooks))
+                } else {
+                    Err(Attempt::Retry("Status: 503".to_string()))
+                }
+            }
+        };
+        let error = send_with_retry("test", &retry, send(false)).await;
+        assert!(matches!(
+            error,
+            Err(NotifierError::Retries { attempts: 3, .. })
+        ));
+        assert_eq!(attempts.swap(0, Ordering::Relaxed), 3);
+
+        // failures that sending again would not fix are not retried
+        let error = send_with_retry("test", &retry, send(true)).await;
+        assert!(matches!(error, Err(NotifierError::AllWebhooks)));
+        assert_eq!(attempts.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_supervise() {
+        let starts = AtomicU32::new(0);
+        let start = |rx: Receiver<Alert>| {
+            let starts = &starts;
+            async move {
+                if starts.fetch_add(1, Ordering::Relaxed) == 0 {
+                    return Err(NotifierError::Closed);
+                }
+                // delivers the queued alert and stops
+                let alert = rx.recv_async().await.map_err(|_| NotifierError::Closed)?;
+                assert_eq!(alert.message, "queued");
+                Ok(())
+            }
+        };
+        let (tx, rx) = flume::unbounded();
+        tx.send(Alert::new("queued")).unwrap();
+        let backoff = || Backoff::new(Duration::ZERO);
+        let res = supervise(rx.clone(), FailurePolicy::Exit, backoff(), start).await;
+        assert!(res.is_err());
+
+        // restarted, the queued alert is still delivered
+        starts.store(0, Ordering::Relaxed);
+        let res = supervise(rx.clone(), FailurePolicy::Restart, backoff(), start).await;
+        assert!(res.is_ok());
+        assert_eq!(starts.load(Ordering::Relaxed), 2);
+
+        // not restarted once the channel is closed
+        starts.store(0, Ordering::Relaxed);
+        drop(tx);
+        let res = supervise(rx, FailurePolicy::Restart, backoff(), start).await;
+        assert!(res.is_err());
+
+        // nor on a fatal error
+        let (_tx, rx) = flume::unbounded();
+        starts.store(0, Ordering::Relaxed);
+        let res = supervise(rx, FailurePolicy::Restart, backoff(), |_| async {
+            starts.fetch_add(1, Ordering::Relaxed);
+            Err(ConfigError::Invalid("bad token".to_string()).into())
+        })
+        .await;
+        assert!(res.is_err());
+        assert_eq!(starts.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_until_failed() {
+        // a failing notifier ends the run while the pipeline still runs
+        let mut tasks = JoinSet::new();
+        tasks.spawn(async { Ok(()) });
+        tasks.spawn(async { Err(NotifierError::Closed) });
+        let res = until_failed(std::future::pending(), tasks);
+        let res = tokio::time::timeout(Duration::from_secs(1), res).await;
+        assert!(matches!(res, Ok(Err(NotifierError::Closed))), "{res:?}");
+
+        // as does a panicking one
+        let mut tasks = JoinSet::new();
+        tasks.spawn(async { panic!("notifier panicked") });
+        let res = until_failed(std::future::pending(), tasks).await;
+        assert!(matches!(res, Err(NotifierError::Task(_))), "{res:?}");
+
+        // otherwise it waits for the notifiers after the pipeline
+        let mut tasks = JoinSet::new();
+        tasks.spawn(async {
+            sleep(Duration::from_millis(50)).await;
+            Err(NotifierError::AllWebhooks)
+        });
+        let res = until_failed(async { Ok(()) }, tasks).await;
+        assert!(matches!(res, Err(NotifierError::AllWebhooks)), "{res:?}");
+    }
+
+    #[tokio::test]
+    async fn test_shared_suppression() {
+        let (tx, rx) = flume::unbounded();
+        for message in ["disk full", "disk full", "db down"] {
+            tx.send(Alert::new(message)).unwrap();
+        }
+        drop(tx);
+        let (admitted_tx, admitted) = flume::unbounded();
+        suppress(rx, Suppressor::default(), admitted_tx).await;
+        // the duplicate is suppressed for all the notifiers the alerts are routed to
+        let messages = admitted.drain().map(|a| a.message).collect::<Vec<_>>();
+        assert_eq!(messages, ["disk full", "db down"]);
+    }
+
+    #[tokio::test]
+    async fn test_budget_after_suppression() {
+        let config: Config =
+            toml::from_str("slack_webhook_url = \"\"\nsystemd_unit = \"\"\nmax_alerts_per_day = 2")
+                .unwrap();
+        let slack = Slack::new(Vec::new()).with_shared_suppression();
+        let budget = Arc::new(AlertBudget::new(config.max_alerts_per_day, false));
+        let (tx, rx) = flume::unbounded();
+        for message in ["disk full", "disk full", "disk full", "db down"] {
+            tx.send(Alert {
+                kind: AlertKind::Rule,
+                ..Alert::new(message)
+            })
+            .unwrap();
+        }
+        drop(tx);
+        run(&config, rx, &slack, Vec::new(), budget.clone())
+            .await
+            .unwrap();
+        // the suppressed duplicates do not spend the budget
+        let spending = budget.spending(Local::now().date_naive()).unwrap();
+        assert_eq!((spending.remaining, spending.dropped), (0, 0));
+    }
+}
@@ -0,0 +1,235 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use flume::Receiver;
+use log::{debug, error, info};
+use reqwest::Url;
+use reqwest::header::AUTHORIZATION;
+
+use crate::alert::{Alert, AlertKind};
+use crate::config::{OpsgenieConfig, SendRetry, Severity};
+use crate::error::NotifierError;
+use crate::notifier::{self, Attempt};
+
+// source shown on the Opsgenie alerts
+const SOURCE: &str = "journal-alerts";
+// limits of the Opsgenie alert fields, in characters
+const MAX_MESSAGE_LEN: usize = 130;
+const MAX_DESCRIPTION_LEN: usize = 15000;
+
+/// Creates Opsgenie alerts for the alerts of the configured severities, and closes
+/// them when their rule is resolved or their heartbeat recovers.
+pub struct Opsgenie {
+    config: OpsgenieConfig,
+    client: reqwest::Client,
+    send_retry: SendRetry,
+}
+
+impl Opsgenie {
+    pub fn new(config: OpsgenieConfig) -> Self {
+        Opsgenie {
+            config,
+            client: reqwest::Client::new(),
+            send_retry: SendRetry::default(),
+        }
+    }
+
+    /// Sets how the failed requests are retried.
+    pub fn with_send_retry(mut self, send_retry: SendRetry) -> Self {
+        self.send_retry = send_retry;
+        self
+    }
+
+    pub async fn start(&self, rx: Receiver<Alert>) -> Result<(), NotifierError> {
+        info!("Opsgenie notifier started.");
+        // aliases of the alerts created and not closed yet
+        let mut open = HashSet::new();
+        loop {
+            let Ok(alert) = rx.recv_async().await else {
+                info!("Opsgenie notifier receiver channel closed, exiting.");
+                break;
+            };
+            // a recovery closes the alert it pairs with, and those of the linked rules
+            if alert.kind == AlertKind::Recovery {
+                let aliases = alert
+                    .thread_key
+                    .iter()
+                    .cloned()
+                    .chain(alert.resets().map(rule_alias));
+                for alias in aliases {
+                    if !open.remove(&alias) {
+                        continue;
+                    }
+                    if let Err(e) = self.close(&alias).await {
+                        error!("Error closing Opsgenie alert {}: {}", alias, e);
+                    }
+                }
+                continue;
+            }
+            // alerts routed here explicitly are created whatever their severity
+            if alert.notify.is_empty() && !self.config.severities.contains(&alert.severity) {
+                continue;
+            }
+            let alias = alias(&alert);
+            match self.create(&alert, alias.as_deref()).await {
+                Ok(()) => open.extend(alias),
+                Err(e) => error!("Error sending alert to Opsgenie: {}", e),
+            }
+        }
+        Ok(())
+    }
+
+    async fn create(&self, alert: &Alert, alias: Option<&str>) -> Result<(), NotifierError> {
+        if self.config.api_key.is_empty() {
+            info!("{}", alert.text());
+            return Ok(());
+        }
+        let url = self.url(&["v2", "alerts"])?;
+        self.post(url, payload(alert, alias)).await
+    }
+
+    async fn close(&self, alias: &str) -> Result<(), NotifierError> {
+        if self.config.api_key.is_empty() {
+            debug!("Closing Opsgenie alert {}", alias);
+            return Ok(());
+        }
+        let mut url = self.url(&["v2", "alerts", alias, "close"])?;
+        url.query_pairs_mut().append_pair("identifierType", "alias");
+        let payload = serde_json::json!({ "source": SOURCE, "note": "Recovered" });
+        self.post(url, payload).await
+    }
+
+    /// The URL of the API endpoint, the segments are percent-encoded.
+    fn url(&self, segments: &[&str]) -> Result<Url, NotifierError> {
+        let invalid = || NotifierError::Api {
+            notifier: "Opsgenie",
+            details: format!("invalid API URL {}", self.config.api_url),
+        };
+        let mut url = Url::parse(&self.config.api_url).map_err(|_| invalid())?;
+        url.path_segments_mut()
+            .map_err(|()| invalid())?
+            .pop_if_empty()
+            .extend(segments);
+        Ok(url)
+    }
+
+    async fn post(&self, url: Url, payload: serde_json::Value) -> Result<(), NotifierError> {
+        notifier::send_with_retry("Opsgenie", &self.send_retry, || async {
+            let res = self
+                .client
+                .post(url.clone())
+                .timeout(Duration::from_secs(10))
+                .header(AUTHORIZATION, format!("GenieKey {}", self.config.api_key))
+                .json(&payload)
+                .send()
+                .await
+                .map_err(|e| Attempt::Retry(format!("HTTP client error {e}")))?;
+            let status = res.status();
+            if status.is_success() {
+                return Ok(());
+            }
+            if status.is_server_error() || status.as_u16() == 429 {
+                return Err(Attempt::Retry(format!("Status: {status}")));
+            }
+            // a rejected request, the message says why
+            let body: serde_json::Value = res.json().await.map_err(|source| {
+                Attempt::Fatal(NotifierError::Response {
+                    notifier: "Opsgenie",
+                    source,
+                })
+            })?;
+            Err(Attempt::Fatal(NotifierError::Api {
+                notifier: "Opsgenie",
+                details: format!("status {}, message: {}", status, body["message"]),
+            }))
+        })
+        .await
+    }
+}
+
+/// The alias an alert is created with, shared by the alerts of a heartbeat or a rule,
+/// none for the other alerts.
+fn alias(alert: &Alert) -> Option<String> {
+    alert
+        .thread_key
+        .clone()
+        .or_else(|| alert.rule.as_deref().map(rule_alias))
+}
+
+fn rule_alias(rule: &str) -> String {
+    format!("rule:{rule}")
+}
+
+/// The alert creation request: the first line as the message, the whole text as the
+/// description, both within the Opsgenie limits.
+fn payload(alert: &Alert, alias: Option<&str>) -> serde_json::Value {
+    let text = alert.text();
+    let message = text.lines().next().unwrap_or_default();
+    let mut payload = serde_json::json!({
+        "message": message.chars().take(MAX_MESSAGE_LEN).collect::<String>(),
+        "description": text.chars().take(MAX_DESCRIPTION_LEN).collect::<String>(),
+        "priority": priority(alert.severity),
+        "source": SOURCE,
+    });
+    if let Some(alias) = alias {
+        payload["alias"] = serde_json::Value::from(alias);
+    }
+    payload
+}
+
+/// Maps the severity to an Opsgenie priority, from P1 (critical) to P5 (informational).
+fn priority(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Critical => "P1",
+        Severity::Warning => "P3",
+        Severity::Info => "P5",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload() {
+        let alert = Alert {
+            rule: Some("disk".to_string()),
+            severity: Severity::Critical,
+            ..Alert::new("disk full\nRunbook: https://wiki")
+        };
+        let rule = alias(&alert);
+        assert_eq!(rule.as_deref(), Some("rule:disk"));
+        assert_eq!(
+            payload(&alert, rule.as_deref()),
+            serde_json::json!({
+                "message": "disk full",
+                "description": "disk full\nRunbook: https://wiki",
+                "priority": "P1",
+                "source": "journal-alerts",
+                "alias": "rule:disk",
+            })
+        );
+
+        // a heartbeat miss and its recovery share the alias
+        let missed = Alert {
+            kind: AlertKind::Heartbeat,
+            thread_key: Some("heartbeat:health ok".to_string()),
+            ..Alert::new("Heartbeat missed")
+        };
+        assert_eq!(alias(&missed).as_deref(), Some("heartbeat:health ok"));
+        let opsgenie = Opsgenie::new(OpsgenieConfig {
+            api_url: "https://api.eu.opsgenie.com/".to_string(),
+            ..Default::default()
+        });
+        assert_eq!(
+            opsgenie
+                .url(&["v2", "alerts", "heartbeat:health ok/1", "close"])
+                .unwrap()
+                .as_str(),
+            "https://api.eu.opsgenie.com/v2/alerts/heartbeat:health%20ok%2F1/close"
+        );
+        let payload = payload(&Alert::new("started"), None);
+        assert_eq!(payload["priority"], "P5");
+        assert!(payload.get("alias").is_none());
+    }
+}
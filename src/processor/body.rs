@@ -0,0 +1,136 @@
+use crate::config::BodyFormat;
+
+/// Parses a structured message body into its keys and values, `None` for a raw body
+/// or one that is not in the format.
+pub fn fields(format: BodyFormat, message: &str) -> Option<Vec<(String, String)>> {
+    match format {
+        BodyFormat::Raw => None,
+        BodyFormat::Json => json(message),
+        BodyFormat::Logfmt => logfmt(message),
+    }
+}
+
+/// The keys of a JSON object, nested objects flattened as `outer.inner`.
+fn json(message: &str) -> Option<Vec<(String, String)>> {
+    let serde_json::Value::Object(object) = serde_json::from_str(message.trim()).ok()? else {
+        return None;
+    };
+    let mut fields = Vec::new();
+    flatten("", object, &mut fields);
+    Some(fields)
+}
+
+fn flatten(
+    prefix: &str,
+    object: serde_json::Map<String, serde_json::Value>,
+    fields: &mut Vec<(String, String)>,
+) {
+    for (key, value) in object {
+        let key = format!("{prefix}{key}");
+        match value {
+            serde_json::Value::Null => {}
+            serde_json::Value::String(value) => fields.push((key, value)),
+            serde_json::Value::Object(object) => flatten(&format!("{key}."), object, fields),
+            // numbers, booleans and arrays as written in JSON
+            value => fields.push((key, value.to_string())),
+        }
+    }
+}
+
+/// The pairs of a logfmt line such as `level=error msg="disk full" retry`, a bare key
+/// is `true`. Lines with a token that is not a pair or a key, or without any pair, are
+/// not logfmt.
+fn logfmt(message: &str) -> Option<Vec<(String, String)>> {
+    let mut fields = Vec::new();
+    let mut pairs = 0;
+    let mut chars = message.trim().chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            break;
+        }
+        let mut key = String::new();
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != '=') {
+            key.push(c);
+        }
+        let valid = |c: char| c.is_ascii_alphanumeric() || "_-./".contains(c);
+        if key.is_empty() || !key.chars().all(valid) {
+            return None;
+        }
+        if chars.next_if_eq(&'=').is_none() {
+            fields.push((key, "true".to_string()));
+            continue;
+        }
+        let mut value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next()? {
+                    '"' => break,
+                    '\\' => match chars.next()? {
+                        'n' => value.push('\n'),
+                        't' => value.push('\t'),
+                        c => value.push(c),
+                    },
+                    c => value.push(c),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                value.push(c);
+            }
+        }
+        fields.push((key, value));
+        pairs += 1;
+    }
+    (pairs > 0).then_some(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fields() {
+        let pairs = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            fields(
+                BodyFormat::Logfmt,
+                r#"level=error msg="disk \"data\" full" retry path=/var/lib"#
+            ),
+            Some(pairs(&[
+                ("level", "error"),
+                ("msg", "disk \"data\" full"),
+                ("retry", "true"),
+                ("path", "/var/lib"),
+            ]))
+        );
+        // plain text falls back to raw matching
+        assert_eq!(fields(BodyFormat::Logfmt, "error: disk full"), None);
+        assert_eq!(fields(BodyFormat::Logfmt, "starting up"), None);
+        assert_eq!(fields(BodyFormat::Logfmt, r#"msg="unterminated"#), None);
+
+        let mut json = fields(
+            BodyFormat::Json,
+            r#"{"status":500,"ok":false,"http":{"method":"GET"},"tags":["a"],"trace":null}"#,
+        )
+        .unwrap();
+        json.sort();
+        assert_eq!(
+            json,
+            pairs(&[
+                ("http.method", "GET"),
+                ("ok", "false"),
+                ("status", "500"),
+                ("tags", r#"["a"]"#),
+            ])
+        );
+        assert_eq!(fields(BodyFormat::Json, "[1, 2]"), None);
+        assert_eq!(fields(BodyFormat::Json, "status=500"), None);
+        assert_eq!(fields(BodyFormat::Raw, "status=500"), None);
+    }
+}
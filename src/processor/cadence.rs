@@ -0,0 +1,95 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// a perfectly regular cadence still allows some jitter, as a fraction of its mean
+const MIN_DEVIATION: f64 = 0.1;
+
+/// The cadence of a heartbeat learned from the intervals between its last matches.
+#[derive(Debug, Clone)]
+pub(super) struct Cadence {
+    // intervals between the last matches, the oldest first
+    intervals: VecDeque<Duration>,
+    // intervals learned from, the cadence is used once they are all seen
+    samples: usize,
+    last: Option<Instant>,
+}
+
+/// The typical interval between the matches of a heartbeat.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct Learned {
+    pub mean: Duration,
+    pub deviation: Duration,
+}
+
+impl Cadence {
+    pub fn new(samples: usize) -> Self {
+        Cadence {
+            intervals: VecDeque::with_capacity(samples),
+            samples,
+            last: None,
+        }
+    }
+
+    /// Records a match, learning the interval since the previous one.
+    pub fn record(&mut self, at: Instant) {
+        if let Some(last) = self.last.replace(at) {
+            if self.intervals.len() == self.samples {
+                self.intervals.pop_front();
+            }
+            self.intervals.push_back(at.saturating_duration_since(last));
+        }
+    }
+
+    /// The mean and standard deviation of the intervals, once enough are learned.
+    pub fn learned(&self) -> Option<Learned> {
+        if self.intervals.len() < self.samples {
+            return None;
+        }
+        let secs = self.intervals.iter().map(Duration::as_secs_f64);
+        let mean = secs.clone().sum::<f64>() / self.samples as f64;
+        let variance = secs.map(|s| (s - mean).powi(2)).sum::<f64>() / self.samples as f64;
+        Some(Learned {
+            mean: Duration::from_secs_f64(mean),
+            deviation: Duration::from_secs_f64(variance.sqrt()),
+        })
+    }
+}
+
+impl Learned {
+    /// How long after a match the heartbeat is overdue: `sensitivity` standard
+    /// deviations over the mean interval.
+    pub fn deadline(&self, sensitivity: f64) -> Duration {
+        let deviation = self.deviation.max(self.mean.mul_f64(MIN_DEVIATION));
+        self.mean + deviation.mul_f64(sensitivity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cadence() {
+        let start = Instant::now();
+        let secs = Duration::from_secs;
+        let mut cadence = Cadence::new(4);
+        // the first match only starts the first interval
+        for at in [0, 40, 110, 150] {
+            cadence.record(start + secs(at));
+        }
+        assert_eq!(cadence.learned(), None);
+        cadence.record(start + secs(220));
+        let learned = cadence.learned().unwrap();
+        assert_eq!(learned.mean, secs(55));
+        assert_eq!(learned.deviation, secs(15));
+        assert_eq!(learned.deadline(3.0), secs(55 + 3 * 15));
+
+        // the oldest intervals are forgotten, a regular cadence keeps some leeway
+        for at in [280, 340, 400, 460] {
+            cadence.record(start + secs(at));
+        }
+        let learned = cadence.learned().unwrap();
+        assert_eq!((learned.mean, learned.deviation), (secs(60), secs(0)));
+        assert_eq!(learned.deadline(3.0), secs(60 + 18));
+    }
+}
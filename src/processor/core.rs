@@ -1,31 +1,206 @@
+use std::collections::{HashMap, VecDeque};
+use std::ops::Range;
 use std::process::Stdio;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
-use super::matcher::Matcher;
-use crate::config::{Config, HeartbeatRule};
-use anyhow::{Context, Result};
+use super::body;
+use super::cadence::Cadence;
+use super::decode::{Decoded, Decoder};
+use super::matcher::{MatchLimits, Matcher};
+use super::native;
+use super::parse::{JournalEntry, OutputFormat, parse_timestamp};
+use super::template::Template;
+use super::transform::Transformer;
+use super::warmup::Warmup;
+use crate::alert::{Alert, AlertKind};
+use crate::channel::AlertSender;
+use crate::config::{Config, HeartbeatRule, JournalSource, Severity};
+use crate::control::{Silences, format_duration};
+use crate::error::{MatcherError, ProcessError};
+use crate::metrics::{Metrics, Running};
+use crate::slack;
+use chrono::{DateTime, Local, SubsecRound, Timelike, Utc};
 use dashmap::DashMap;
-use flume::Sender;
 use log::{debug, error, info, warn};
-use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::process::Command;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, ChildStdout, Command};
 use tokio::spawn;
+use tokio::task::JoinHandle;
 use tokio::time::sleep;
 
+/// Matches of a heartbeat counted over a tolerance window.
+#[derive(Debug, Clone)]
+struct WindowCount {
+    // start of the current window
+    start: Instant,
+    // matches in the current window
+    count: usize,
+    // matches in the last complete window
+    last: Option<usize>,
+}
+
+impl WindowCount {
+    fn new(start: Instant) -> Self {
+        WindowCount {
+            start,
+            count: 0,
+            last: None,
+        }
+    }
+
+    /// Closes the window if it elapsed, returns the count of the last complete window.
+    fn roll(&mut self, now: Instant, window: Duration) -> Option<usize> {
+        if now.saturating_duration_since(self.start) >= window {
+            self.last = Some(self.count);
+            self.count = 0;
+            self.start = now;
+        }
+        self.last
+    }
+}
+
+/// Counters of the journal entries read by a processor.
+#[derive(Debug, Default)]
+struct ReadStats {
+    processed: u64,
+    matched: u64,
+    // whether the notifier backlog is above the warning threshold
+    backlogged: bool,
+    // consecutive identical entries held back, when collapsing them
+    run: Option<Run>,
+    // lines left to match this second, with `max_lines_per_sec`
+    budget: Option<LineBudget>,
+}
+
+/// A token bucket of lines, refilled at the rate and holding one second of them.
+#[derive(Debug)]
+struct LineBudget {
+    rate: f64,
+    tokens: f64,
+    refilled: Instant,
+    // lines dropped since the flood started, while one is going on
+    flood: Option<u64>,
+}
+
+impl LineBudget {
+    fn new(rate: u64, now: Instant) -> Self {
+        LineBudget {
+            rate: rate as f64,
+            tokens: rate as f64,
+            refilled: now,
+            flood: None,
+        }
+    }
+
+    /// Takes a line from the budget, returns whether it is matched.
+    fn take(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.refilled = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    /// Whether the budget was refilled, the rate having stayed under the limit for a second.
+    fn full(&self) -> bool {
+        self.tokens + 1.0 >= self.rate
+    }
+}
+
+/// Consecutive identical entries, matched once when the run ends.
+#[derive(Debug)]
+struct Run {
+    entry: JournalEntry,
+    count: usize,
+    // the run is matched at the latest when it is this old
+    deadline: Instant,
+}
+
+/// Background tasks of a processor, aborted when it stops, e.g. on a config reload.
+struct Tasks(Vec<JoinHandle<()>>);
+
+impl Drop for Tasks {
+    fn drop(&mut self) {
+        for task in &self.0 {
+            task.abort();
+        }
+    }
+}
+
+/// A log line matched by an alert rule, before it is turned into an alert.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchEvent {
+    // index of the rule in the config alerts
+    pub rule_index: usize,
+    pub rule_name: String,
+    pub severity: Severity,
+    // the matched line, truncated to the maximum line length
+    pub line: String,
+    // byte range of the match in the line
+    pub span: Range<usize>,
+}
+
 pub struct JournalProcessor {
     config: Config,
     // Map of heartbeat index to (last seen time, message)
     heartbeat_updates: Arc<DashMap<usize, (Instant, String)>>,
     // Map of heartbeat index to (last seen time, missed count)
     heartbeat_misses: Arc<DashMap<usize, (Instant, usize)>>,
+    // Map of heartbeat index to matches counted in the current window, for `min_count` rules
+    heartbeat_counts: Arc<DashMap<usize, WindowCount>>,
+    // Map of heartbeat index to the cadence learned of its matches, for `cadence_sensitivity` rules
+    heartbeat_cadences: Arc<DashMap<usize, Cadence>>,
+    // Time the last line was read from the journal
+    last_line: Arc<Mutex<Instant>>,
+    // Time the processor was created, used for the heartbeats startup grace
+    started: Instant,
+    // Wall clock time the processor was created, to the second, for `quiet_start`
+    started_at: DateTime<Utc>,
     // Compiled matchers
     matcher_alerts: Matcher,
     matcher_heartbeats: Matcher,
+    // Compiled message transformations, per alert rule and global
+    transforms_alerts: Vec<Transformer>,
+    transforms_global: Transformer,
+    // Alert rules muted at runtime
+    silences: Arc<Silences>,
+    // Processing metrics
+    metrics: Arc<Metrics>,
+    // name of the monitor, labels the per rule metrics
+    monitor: String,
+    // rule alerts held back right after startup
+    warmup: Arc<Warmup>,
+    // journalctl filters of the monitored entries, per journal directory, used to fetch
+    // alert context
+    journal_filters: Vec<Vec<String>>,
+    // format of the journalctl output
+    output: OutputFormat,
+    // Compiled resolve patterns, per alert rule
+    resolve_patterns: Vec<Option<regex::Regex>>,
+    // message templates of the alert rules, by rule index
+    templates: Vec<Option<Template>>,
+    // Map of alert rule index to the time its alert was opened, for rules with a resolve pattern
+    open_alerts: DashMap<usize, Instant>,
+    // Map of alert rule index to the hosts it matched on and when, for `min_hosts` rules
+    host_matches: DashMap<usize, HashMap<String, Instant>>,
+    // Map of alert rule index to the matches since its last sampled alert, for `sample_rate` rules
+    sample_counts: DashMap<usize, usize>,
+    // compiled `all_of` patterns, per alert rule
+    all_of_patterns: Vec<Vec<regex::Regex>>,
+    // Map of alert rule index to the last line and time each of its `all_of` patterns matched
+    all_of_matches: DashMap<usize, Vec<Option<(Instant, String)>>>,
+    // decoders of the capture groups, per alert rule
+    decoders: Vec<Option<Decoder>>,
+    // lines read instead of the journal, fed by the tests
+    lines: Option<flume::Receiver<String>>,
 }
 
 impl JournalProcessor {
-    pub fn new(config: &Config) -> Result<Self> {
+    pub fn new(config: &Config) -> Result<Self, MatcherError> {
         // Initialize heartbeat states with current time
         let heartbeat_updates = Arc::new(
             config
@@ -36,31 +211,104 @@ impl JournalProcessor {
                 .collect::<DashMap<usize, (Instant, String)>>(),
         );
 
-        // Compile matchers for alerts
-        let matcher_alerts = Matcher::new(
-            config
-                .alerts
-                .iter()
-                .map(|r| r.pattern.clone())
-                .collect::<Vec<String>>()
-                .as_slice(),
-        )?;
-        // Compile matchers for heartbeats
-        let matcher_heartbeats = Matcher::new(
-            config
-                .heartbeats
-                .iter()
-                .map(|r| r.pattern.clone())
-                .collect::<Vec<String>>()
-                .as_slice(),
-        )?;
+        // Compile matchers for alerts and heartbeats
+        let limits = MatchLimits::from_config(config);
+        let matcher_alerts = Matcher::new(&config.alerts, limits)?;
+        let matcher_heartbeats = Matcher::new(&config.heartbeats, limits)?;
+        // Compile message transformations
+        let transforms_alerts = config
+            .alerts
+            .iter()
+            .map(|r| Transformer::new(&r.transforms))
+            .collect::<Result<Vec<Transformer>, MatcherError>>()?;
+        let transforms_global = Transformer::new(&config.transforms)?;
+        let resolve_patterns = config
+            .alerts
+            .iter()
+            .map(|rule| {
+                (!rule.resolve_pattern.is_empty())
+                    .then(|| {
+                        regex::RegexBuilder::new(&rule.resolve_pattern)
+                            .size_limit(limits.size_limit)
+                            .build()
+                            .map_err(|source| MatcherError::Resolve {
+                                rule: rule.name.clone(),
+                                source,
+                            })
+                    })
+                    .transpose()
+            })
+            .collect::<Result<Vec<_>, MatcherError>>()?;
+        let all_of_patterns = config
+            .alerts
+            .iter()
+            .map(|rule| {
+                rule.all_of
+                    .iter()
+                    .map(|pattern| {
+                        regex::RegexBuilder::new(pattern)
+                            .size_limit(limits.size_limit)
+                            .build()
+                            .map_err(|source| MatcherError::Pattern {
+                                pattern: pattern.clone(),
+                                rule: format!("'{}'", rule.name),
+                                source,
+                            })
+                    })
+                    .collect::<Result<Vec<_>, MatcherError>>()
+            })
+            .collect::<Result<Vec<_>, MatcherError>>()?;
+        let decoders = config
+            .alerts
+            .iter()
+            .map(|rule| Decoder::new(rule, limits))
+            .collect::<Result<Vec<_>, MatcherError>>()?;
+        let templates = config
+            .alerts
+            .iter()
+            .map(|rule| {
+                (!rule.template.is_empty())
+                    .then(|| Template::new(&rule.name, &rule.template))
+                    .transpose()
+            })
+            .collect::<Result<Vec<_>, MatcherError>>()?;
 
         let jp = JournalProcessor {
             config: config.clone(),
             heartbeat_updates,
             heartbeat_misses: Arc::new(DashMap::new()),
+            heartbeat_counts: Arc::new(DashMap::new()),
+            heartbeat_cadences: Arc::new(DashMap::new()),
+            last_line: Arc::new(Mutex::new(Instant::now())),
+            started: Instant::now(),
+            started_at: Utc::now().trunc_subsecs(0),
             matcher_alerts,
             matcher_heartbeats,
+            transforms_alerts,
+            transforms_global,
+            silences: Arc::new(Silences::default()),
+            metrics: Arc::new(Metrics::default()),
+            monitor: "default".to_string(),
+            warmup: Arc::new(Warmup::default()),
+            journal_filters: directory_args(config)
+                .into_iter()
+                .map(|directory| {
+                    directory
+                        .into_iter()
+                        .chain(journal_filter(config))
+                        .collect()
+                })
+                .collect(),
+            output: OutputFormat::from_config(config),
+            resolve_patterns,
+            templates,
+            open_alerts: DashMap::new(),
+            host_matches: DashMap::new(),
+            sample_counts: DashMap::new(),
+            all_of_patterns,
+            all_of_matches: DashMap::new(),
+            decoders,
+            lines: None,
         };
         info!("Loaded {} matching rules for alerts.", config.alerts.len());
         info!(
@@ -71,21 +319,504 @@ impl JournalProcessor {
         Ok(jp)
     }
 
-    pub async fn start(&self, tx: Sender<String>) -> Result<()> {
+    /// Shares the silences with other processors.
+    pub fn with_silences(mut self, silences: Arc<Silences>) -> Self {
+        self.silences = silences;
+        self
+    }
+
+    /// Shares the metrics with other processors.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Names the monitor the processor runs, to tell its rules apart from those of the
+    /// other monitors in the metrics and the heartbeat alerts.
+    pub fn with_monitor(mut self, monitor: &str) -> Self {
+        self.monitor = monitor.to_string();
+        self
+    }
+
+    /// Shares the startup warmup with other processors.
+    pub fn with_warmup(mut self, warmup: Arc<Warmup>) -> Self {
+        self.warmup = warmup;
+        self
+    }
+
+    /// Reads the lines from the channel instead of the journal, the processor stops
+    /// once all the senders are dropped.
+    #[cfg(test)]
+    pub fn with_lines(mut self, lines: flume::Receiver<String>) -> Self {
+        self.lines = Some(lines);
+        self
+    }
+
+    /// Whether the entry is filtered out by `exclude_units` or the transports. Entries
+    /// without a transport are only ignored when the transports are restricted.
+    pub fn excluded(&self, entry: &JournalEntry) -> bool {
+        let transport = entry.fields.get("_TRANSPORT");
+        entry
+            .unit
+            .as_ref()
+            .is_some_and(|unit| self.config.exclude_units.contains(unit))
+            || transport.is_some_and(|t| self.config.exclude_transports.contains(t))
+            || (!self.config.transports.is_empty()
+                && !transport.is_some_and(|t| self.config.transports.contains(t)))
+    }
+
+    /// Whether the entry was logged before the processor started while `quiet_start`
+    /// is set. Entries without a timestamp are never taken as old.
+    pub fn predates_start(&self, entry: &JournalEntry) -> bool {
+        self.config.quiet_start
+            && entry
+                .timestamp
+                .as_deref()
+                .and_then(parse_timestamp)
+                .is_some_and(|timestamp| timestamp < self.started_at)
+    }
+
+    /// Parses a line of journalctl output into an entry.
+    pub fn parse(&self, line: String) -> JournalEntry {
+        self.with_body_fields(self.output.parse(line))
+    }
+
+    /// Adds the keys of the message body to the fields of the entry, per `body_format`.
+    /// The journal fields take precedence, a body not in the format adds nothing.
+    fn with_body_fields(&self, mut entry: JournalEntry) -> JournalEntry {
+        for (key, value) in body::fields(self.config.body_format, &entry.message)
+            .into_iter()
+            .flatten()
+        {
+            entry.fields.entry(key).or_insert(value);
+        }
+        entry
+    }
+
+    /// Matches the entry against the alert rules, without building the alerts. Only
+    /// the first matching rule is returned unless `match_all` is set.
+    pub fn match_events(&self, entry: &JournalEntry) -> Vec<MatchEvent> {
+        // rules with required fields only match the entries missing one of them, rules
+        // with field values only the entries having them
+        let accept = |i: usize| {
+            let rule = &self.config.alerts[i];
+            rule.fields_match(&entry.fields)
+                && (rule.require_fields.is_empty()
+                    || !rule.missing_fields(&entry.fields).is_empty())
+                && (rule.decode.is_none() || self.decode(i, &entry.message).is_some())
+        };
+        let matches = if self.config.match_all {
+            self.matcher_alerts
+                .find_match_spans_by(&entry.message, accept)
+        } else {
+            self.matcher_alerts
+                .find_match_span_by(&entry.message, accept)
+                .into_iter()
+                .collect()
+        };
+        matches
+            .into_iter()
+            .map(|(i, line, span)| self.event(i, line, span, entry))
+            .collect()
+    }
+
+    /// The first match of the entry, see [`JournalProcessor::match_events`].
+    #[cfg(test)]
+    pub fn match_event(&self, entry: &JournalEntry) -> Option<MatchEvent> {
+        self.match_events(entry).into_iter().next()
+    }
+
+    /// The match of the rule at index `i` on the line.
+    fn event(
+        &self,
+        i: usize,
+        line: String,
+        span: Range<usize>,
+        entry: &JournalEntry,
+    ) -> MatchEvent {
+        let rule = &self.config.alerts[i];
+        MatchEvent {
+            rule_index: i,
+            rule_name: rule.name.clone(),
+            // an explicit severity wins over the one derived from the journal
+            severity: rule
+                .severity
+                .or_else(|| {
+                    entry
+                        .priority
+                        .filter(|_| self.config.severity_from_priority)
+                        .map(Severity::from_priority)
+                })
+                .unwrap_or_default(),
+            line,
+            span,
+        }
+    }
+
+    /// Matches an entry against the alert rules, returning the alerts it produces
+    /// and the matches they were built from.
+    pub fn match_alerts(&self, entry: &JournalEntry) -> Vec<(Alert, MatchEvent)> {
+        self.match_events(entry)
+            .into_iter()
+            .map(|event| (self.alert(&event, entry), event))
+            .collect()
+    }
+
+    /// The first alert of the entry, see [`JournalProcessor::match_alerts`].
+    #[cfg(test)]
+    pub fn match_alert(&self, entry: &JournalEntry) -> Option<(Alert, MatchEvent)> {
+        self.match_alerts(entry).into_iter().next()
+    }
+
+    /// Marks the alert of the match as open, if its rule has a resolve pattern.
+    fn open(&self, event: &MatchEvent) {
+        if self.resolve_patterns[event.rule_index].is_some() {
+            self.open_alerts
+                .entry(event.rule_index)
+                .or_insert_with(Instant::now);
+        }
+    }
+
+    /// Resolves the open alerts whose resolve pattern matches the entry, returning
+    /// the notices to send.
+    fn resolve(&self, entry: &JournalEntry) -> Vec<Alert> {
+        let resolved = self
+            .open_alerts
+            .iter()
+            .filter(|open| {
+                self.resolve_patterns[*open.key()]
+                    .as_ref()
+                    .is_some_and(|re| re.is_match(&entry.message))
+            })
+            .map(|open| (*open.key(), *open.value()))
+            .collect::<Vec<_>>();
+        resolved
+            .into_iter()
+            .map(|(i, opened)| {
+                self.open_alerts.remove(&i);
+                let rule = &self.config.alerts[i];
+                Alert {
+                    rule: Some(rule.name.clone()),
+                    kind: AlertKind::Recovery,
+                    // the notice goes where the alert went
+                    notify: rule.notify.clone(),
+                    ..Alert::new(format!(
+                        "✅ '{}' resolved after {}: {}",
+                        rule.name,
+                        format_duration(opened.elapsed()),
+                        entry.message
+                    ))
+                }
+            })
+            .collect()
+    }
+
+    /// Records the host of a match of a `min_hosts` rule. Once the rule matched on enough
+    /// distinct hosts within its window, the hosts are added to the alert and forgotten,
+    /// returns whether the alert is to be sent.
+    fn enough_hosts(&self, event: &MatchEvent, entry: &JournalEntry, alert: &mut Alert) -> bool {
+        let rule = &self.config.alerts[event.rule_index];
+        let Some(min_hosts) = rule.min_hosts else {
+            return true;
+        };
+        let Some(host) = entry.fields.get("_HOSTNAME") else {
+            return false;
+        };
+        let window = Duration::from_secs(rule.hosts_window);
+        let mut hosts = self.host_matches.entry(event.rule_index).or_default();
+        let now = Instant::now();
+        hosts.insert(host.clone(), now);
+        hosts.retain(|_, seen| now.duration_since(*seen) <= window);
+        if hosts.len() < min_hosts {
+            return false;
+        }
+        let mut names = std::mem::take(&mut *hosts).into_keys().collect::<Vec<_>>();
+        names.sort();
+        let count = names.len();
+        let listed = if count > MAX_LISTED_HOSTS {
+            format!(
+                "{} and {} more",
+                names[..MAX_LISTED_HOSTS].join(", "),
+                count - MAX_LISTED_HOSTS
+            )
+        } else {
+            names.join(", ")
+        };
+        alert.message = format!(
+            "{}\nSeen on {count} hosts within {}: {listed}",
+            alert.message,
+            format_duration(window)
+        );
+        true
+    }
+
+    /// Records which `all_of` patterns of the rule the line matched. Once all of them
+    /// matched within the window, the lines are added to the alert and forgotten,
+    /// returns whether the alert is to be sent.
+    fn all_matched(&self, event: &MatchEvent, alert: &mut Alert) -> bool {
+        let patterns = &self.all_of_patterns[event.rule_index];
+        if patterns.is_empty() {
+            return true;
+        }
+        let window = Duration::from_secs(self.config.alerts[event.rule_index].all_of_window);
+        let mut seen = self
+            .all_of_matches
+            .entry(event.rule_index)
+            .or_insert_with(|| vec![None; patterns.len()]);
+        let now = Instant::now();
+        for (pattern, seen) in patterns.iter().zip(seen.iter_mut()) {
+            if pattern.is_match(&event.line) {
+                *seen = Some((now, event.line.clone()));
+            } else if seen
+                .as_ref()
+                .is_some_and(|(at, _)| now.duration_since(*at) > window)
+            {
+                *seen = None;
+            }
+        }
+        if seen.iter().any(Option::is_none) {
+            return false;
+        }
+        let lines = std::mem::replace(&mut *seen, vec![None; patterns.len()])
+            .into_iter()
+            .flatten()
+            .zip(patterns)
+            .map(|((_, line), pattern)| format!("\n• {}: {line}", pattern.as_str()))
+            .collect::<String>();
+        alert.message = format!(
+            "{}\nAll {} patterns matched within {}:{lines}",
+            alert.message,
+            patterns.len(),
+            format_duration(window)
+        );
+        true
+    }
+
+    /// Counts a match of a `sample_rate` rule, returns whether it is one of the sampled
+    /// matches to alert on: the first one, then one every `1 / sample_rate`. The alert
+    /// notes the sampling and the matches skipped since the previous one.
+    fn sampled(&self, event: &MatchEvent, alert: &mut Alert) -> bool {
+        let rule = &self.config.alerts[event.rule_index];
+        let Some(rate) = rule.sample_rate else {
+            return true;
+        };
+        let every = (1.0 / rate).round() as usize;
+        let skipped = match self.sample_counts.get_mut(&event.rule_index) {
+            None => 0,
+            Some(mut skipped) if *skipped + 1 < every => {
+                *skipped += 1;
+                return false;
+            }
+            Some(skipped) => *skipped,
+        };
+        self.sample_counts.insert(event.rule_index, 0);
+        alert.message = match skipped {
+            0 => format!("{}\nSampled 1/{every}", alert.message),
+            skipped => format!(
+                "{}\nSampled 1/{every}, {skipped} matches skipped since the previous alert",
+                alert.message
+            ),
+        };
+        true
+    }
+
+    /// The decoded capture group of the rule in the line, `None` when the rule does not
+    /// decode or when the decoded group does not match its `decode_pattern`.
+    fn decode(&self, rule_index: usize, line: &str) -> Option<Decoded> {
+        let decoder = self.decoders[rule_index].as_ref()?;
+        decoder.decode(&self.matcher_alerts.captures(rule_index, line)?)
+    }
+
+    /// Renders the template of the rule with the captures of its pattern in the line,
+    /// the decoded group and its captures, and the fields of the entry.
+    fn render(
+        &self,
+        template: &Template,
+        event: &MatchEvent,
+        entry: &JournalEntry,
+        decoded: Option<&Decoded>,
+    ) -> String {
+        let rule = &self.config.alerts[event.rule_index];
+        let captures = self.matcher_alerts.captures(event.rule_index, &event.line);
+        template.render(&event.rule_name, |name| {
+            if let Some(decoded) = decoded {
+                let capture = decoded.captures.iter().find(|(group, _)| group == name);
+                if let Some((_, value)) = capture {
+                    return Some(value.clone());
+                }
+                if name == rule.decode_group {
+                    return Some(decoded.text.clone());
+                }
+            }
+            let capture = captures.as_ref().and_then(|captures| captures.group(name));
+            match capture {
+                Some((_, capture)) => Some(capture.to_string()),
+                None if name == "MESSAGE" => Some(entry.message.clone()),
+                None => entry.fields.get(name).cloned(),
+            }
+        })
+    }
+
+    /// The timestamp of an entry in the configured timezone, kept as is if it
+    /// cannot be parsed.
+    fn timestamp(&self, timestamp: &str) -> String {
+        parse_timestamp(timestamp).map_or_else(
+            || timestamp.to_string(),
+            |timestamp| self.config.timezone.format(timestamp),
+        )
+    }
+
+    /// Builds the alert of a matched entry.
+    fn alert(&self, event: &MatchEvent, entry: &JournalEntry) -> Alert {
+        let rule = &self.config.alerts[event.rule_index];
+        let decoded = self.decode(event.rule_index, &event.line);
+        if let Some(decoded) = decoded.as_ref().filter(|decoded| decoded.failed) {
+            warn!(
+                "Rule '{}' could not decode its capture group, using it as is: {}",
+                event.rule_name, decoded.text
+            );
+        }
+        // the decoded group takes its place in the line, and is what gets highlighted
+        let (line, span) = match &decoded {
+            Some(decoded) => {
+                let start = decoded.span.start;
+                let line = format!(
+                    "{}{}{}",
+                    &event.line[..start],
+                    decoded.text,
+                    &event.line[decoded.span.end..]
+                );
+                (line, start..start + decoded.text.len())
+            }
+            None => (event.line.clone(), event.span.clone()),
+        };
+        // render the template, or highlight the part that matched, before the transforms
+        let msg = if let Some(template) = &self.templates[event.rule_index] {
+            self.render(template, event, entry, decoded.as_ref())
+        } else if self.config.rich_formatting {
+            slack::highlight(&line, span)
+        } else {
+            line
+        };
+        // transform the message, the rule ones first
+        let msg = self.transforms_alerts[event.rule_index].apply(&msg);
+        let msg = self.transforms_global.apply(&msg);
+        // get the prefix for this alerts
+        let mut msg = format!("{}{}", rule.prefix, msg);
+        let missing = rule.missing_fields(&entry.fields);
+        if !missing.is_empty() {
+            msg = format!("{msg}\nMissing fields: {}", missing.join(", "));
+        }
+        if !rule.description.is_empty() {
+            msg = format!("{msg}\n{}", rule.description);
+        }
+        if !rule.runbook_url.is_empty() {
+            msg = if self.config.rich_formatting {
+                format!("{msg}\n<{}|Runbook>", rule.runbook_url)
+            } else {
+                format!("{msg}\nRunbook: {}", rule.runbook_url)
+            };
+        }
+        Alert {
+            rule: Some(event.rule_name.clone()),
+            severity: event.severity,
+            timestamp: entry
+                .timestamp
+                .as_deref()
+                .filter(|_| self.config.include_timestamp)
+                .map(|timestamp| self.timestamp(timestamp)),
+            kind: AlertKind::Rule,
+            always_notify: rule.always_notify,
+            suppression_ttl: rule.suppression_window(Local::now().num_seconds_from_midnight()),
+            notify: rule.notify.clone(),
+            context_lines: rule.context_lines,
+            journal_filter: if rule.context_lines > 0 {
+                self.journal_filters[entry.directory].clone()
+            } else {
+                Vec::new()
+            },
+            ..Alert::new(msg)
+        }
+    }
+
+    pub async fn start(&self, tx: AlertSender) -> Result<(), ProcessError> {
         info!("Journal processor started.");
         // Start the heartbeat monitoring thread
         let heartbeat_updates = self.heartbeat_updates.clone();
         let heartbeat_misses = self.heartbeat_misses.clone();
+        let heartbeat_counts = self.heartbeat_counts.clone();
+        let heartbeat_cadences = self.heartbeat_cadences.clone();
         let heartbeats = self.config.heartbeats.clone();
         let heartbeat_interval = self.config.heartbeat_interval;
         let heartbeat_tx = tx.clone();
+        let last_line = self.last_line.clone();
+        let stream_idle_timeout = Duration::from_secs(self.config.stream_idle_timeout);
+        let started = self.started;
+        let startup_grace = self.config.startup_grace;
+        let recovery_batch_window = Duration::from_secs(self.config.recovery_batch_window);
+        let heartbeat_jitter = self.config.heartbeat_jitter;
+        let metrics = self.metrics.clone();
+        let monitor = self.monitor.clone();
+        let mut tasks = Tasks(Vec::new());
 
-        spawn(async move {
+        tasks.0.push(spawn(async move {
             info!("Heartbeat monitoring thread started.");
+            // time of the last line read before the stream went idle
+            let mut stream_idle_since: Option<Instant> = None;
+            // recoveries waiting to be sent as one summary, and when the first came in
+            let mut recoveries: Vec<String> = Vec::new();
+            let mut recoveries_since: Option<Instant> = None;
+            // alert rules the batched recoveries clear the suppression of, and the misses
+            let mut recovery_resets: Vec<String> = Vec::new();
+            let mut recovery_keys: Vec<String> = Vec::new();
+            // Map of heartbeat index to the consecutive checks it was overdue
+            let mut overdue: HashMap<usize, usize> = HashMap::new();
             loop {
                 let now = std::time::Instant::now();
-                for entry in heartbeat_updates.iter() {
-                    let (i, (last_seen, msg)) = entry.pair();
+                // check the liveness of the whole stream
+                if !stream_idle_timeout.is_zero() {
+                    let last_line = *last_line.lock().unwrap();
+                    let idle = now.saturating_duration_since(last_line);
+                    let alert = match (idle > stream_idle_timeout, stream_idle_since) {
+                        (true, None) => {
+                            stream_idle_since = Some(last_line);
+                            Some(Alert {
+                                severity: Severity::Warning,
+                                kind: AlertKind::Heartbeat,
+                                ..Alert::new(format!(
+                                    "🔇 No journal lines read in the last {}.",
+                                    format_duration(idle)
+                                ))
+                            })
+                        }
+                        (false, Some(since)) => {
+                            stream_idle_since = None;
+                            Some(Alert {
+                                kind: AlertKind::Recovery,
+                                ..Alert::new(format!(
+                                    "🩹 Journal stream resumed after {} of silence.",
+                                    format_duration(last_line.saturating_duration_since(since))
+                                ))
+                            })
+                        }
+                        _ => None,
+                    };
+                    if let Some(alert) = alert {
+                        heartbeat_tx
+                            .send(alert)
+                            .await
+                            .inspect_err(|e| {
+                                error!("Failed to send stream liveness alert: {}", e);
+                            })
+                            .ok();
+                    }
+                }
+                // snapshot the states so no lock is held while sending alerts
+                let states = heartbeat_updates
+                    .iter()
+                    .map(|entry| (*entry.key(), entry.value().clone()))
+                    .collect::<Vec<_>>();
+                for (i, (last_seen, msg)) in states {
                     // TODO: make this a debug log
                     info!(
                         "Heartbeat state for index {}: pattern '{}', last seen {:?} ago",
@@ -93,36 +824,113 @@ impl JournalProcessor {
                         msg,
                         last_seen.elapsed()
                     );
-                    // retrieve the tolerance for this heartbeat
-                    let HeartbeatRule {
+                    // retrieve the tolerance for this heartbeat, skip stale indices
+                    let Some(HeartbeatRule {
                         tolerance,
                         prefix,
                         pattern,
-                    } = heartbeats[*i].clone();
+                        startup_grace: rule_grace,
+                        min_count,
+                        consecutive_misses,
+                        reset_alerts,
+                        cadence_sensitivity,
+                        ..
+                    }) = heartbeats.get(i).cloned()
+                    else {
+                        continue;
+                    };
+                    // do not evaluate the heartbeat until the startup grace elapsed
+                    let grace = Duration::from_secs(rule_grace.unwrap_or(startup_grace));
+                    if now.saturating_duration_since(started) < grace {
+                        debug!("Heartbeat {} in startup grace, skipping", i);
+                        continue;
+                    }
                     let tolerance = Duration::from_secs(tolerance);
-                    // if the heartbeat is overdue
-                    let msg = if now.saturating_duration_since(*last_seen) > tolerance {
+                    // for counting heartbeats, the matches in the last complete window
+                    let observed = min_count.and_then(|_| {
+                        heartbeat_counts
+                            .entry(i)
+                            .or_insert_with(|| WindowCount::new(started))
+                            .roll(now, tolerance)
+                    });
+                    // once learned, the cadence of the matches replaces the tolerance
+                    let cadence = cadence_sensitivity.and_then(|sensitivity| {
+                        let learned = heartbeat_cadences.get(&i)?.learned()?;
+                        Some((learned, learned.deadline(sensitivity)))
+                    });
+                    let since = now.saturating_duration_since(last_seen);
+                    // if the heartbeat is overdue or did not match often enough
+                    let msg = if let Some((learned, deadline)) =
+                        cadence.filter(|(_, deadline)| since > *deadline)
+                    {
                         let message = format!(
-                            "{} Heartbeat missed for pattern '{}'. Last seen {:?} ago.",
+                            concat!(
+                                "{} Heartbeat overdue for pattern '{}'. Last seen {} ago, ",
+                                "usually every {} ± {}, overdue after {}."
+                            ),
                             prefix,
                             msg,
-                            last_seen.elapsed()
+                            format_duration(last_seen.elapsed()),
+                            format_duration(learned.mean),
+                            format_duration(learned.deviation),
+                            format_duration(deadline)
+                        );
+                        Some(message)
+                    } else if cadence.is_none() && since > tolerance {
+                        let message = format!(
+                            "{} Heartbeat missed for pattern '{}'. Last seen {} ago.",
+                            prefix,
+                            msg,
+                            format_duration(last_seen.elapsed())
+                        );
+                        Some(message)
+                    } else if let Some((observed, expected)) = observed
+                        .zip(min_count)
+                        .filter(|(observed, expected)| observed < expected)
+                    {
+                        let message = format!(
+                            concat!(
+                                "{} Heartbeat below expected count for pattern '{}'. ",
+                                "Observed {} of {} expected in {}."
+                            ),
+                            prefix,
+                            msg,
+                            observed,
+                            expected,
+                            format_duration(tolerance)
                         );
                         Some(message)
                     } else {
                         None
                     };
-                    // now decide if to update or not
-                    let mut entry = heartbeat_misses.entry(*i).or_insert((now, 0));
-                    let (missed_at, missed_count) = entry.value_mut();
+                    Metrics::set_rule(
+                        &metrics.heartbeat_overdue,
+                        &monitor,
+                        i,
+                        &pattern,
+                        msg.is_some() as u64,
+                    );
+                    // transient misses are ignored until they last enough checks
+                    let sustained = sustained(&mut overdue, i, msg.is_some(), consecutive_misses);
+                    let msg = msg.filter(|_| sustained);
+                    // now decide if to update or not, the guard is released right away
+                    let (missed_at, missed_count) = *heartbeat_misses.entry(i).or_insert((now, 0));
 
-                    match (msg, *missed_count) {
+                    match (msg, missed_count) {
                         (Some(msg), 0) => {
                             // first time missed, will send alert below
-                            *missed_at = now;
-                            *missed_count += 1;
+                            heartbeat_misses.insert(i, (now, missed_count + 1));
+                            Metrics::inc_rule(&metrics.heartbeat_misses, &monitor, i, &pattern);
                             heartbeat_tx
-                                .send(msg)
+                                .send(Alert {
+                                    severity: Severity::Warning,
+                                    kind: AlertKind::Heartbeat,
+                                    thread_key: Some(heartbeat_thread_key(&monitor, &pattern)),
+                                    // the time since last seen changes on every check
+                                    dedup_key: Some(heartbeat_miss_key(&monitor, i)),
+                                    ..Alert::new(msg)
+                                })
+                                .await
                                 .inspect_err(|e| {
                                     error!("Failed to send heartbeat missed alert: {}", e);
                                 })
@@ -130,21 +938,37 @@ impl JournalProcessor {
                         }
                         (None, n) if n > 0 => {
                             // recovery
-                            let recovery_time = now.saturating_duration_since(*missed_at);
+                            Metrics::inc_rule(&metrics.heartbeat_recoveries, &monitor, i, &pattern);
+                            let recovery_time = now.saturating_duration_since(missed_at);
                             let recovery_message = format!(
-                                "🩹 Heartbeat recovered in {}s for pattern '{}'.",
-                                recovery_time.as_secs(),
+                                "🩹 Heartbeat recovered in {} for pattern '{}'.",
+                                format_duration(recovery_time),
                                 pattern,
                             );
-                            // send recovery alert
-                            heartbeat_tx
-                                .send(recovery_message)
-                                .inspect_err(|e| {
-                                    error!("Failed to send heartbeat recovery alert: {}", e);
-                                })
-                                .ok();
+                            if recovery_batch_window.is_zero() {
+                                // send recovery alert
+                                heartbeat_tx
+                                    .send(Alert {
+                                        kind: AlertKind::Recovery,
+                                        thread_key: Some(heartbeat_thread_key(&monitor, &pattern)),
+                                        reset_rules: reset_alerts,
+                                        reset_keys: vec![heartbeat_miss_key(&monitor, i)],
+                                        ..Alert::new(recovery_message)
+                                    })
+                                    .await
+                                    .inspect_err(|e| {
+                                        error!("Failed to send heartbeat recovery alert: {}", e);
+                                    })
+                                    .ok();
+                            } else {
+                                // batched, sent once the window elapses
+                                recoveries.push(recovery_message);
+                                recovery_resets.extend(reset_alerts);
+                                recovery_keys.push(heartbeat_miss_key(&monitor, i));
+                                recoveries_since.get_or_insert(now);
+                            }
                             // reset the missed count
-                            heartbeat_misses.remove(i);
+                            heartbeat_misses.remove(&i);
                         }
                         _ => {
                             // (None, 0) => heartbeat is fine, do nothing
@@ -152,102 +976,1679 @@ impl JournalProcessor {
                         }
                     }
                 }
-                sleep(Duration::from_secs(heartbeat_interval)).await;
+                // send the batched recoveries once the window elapsed
+                if recoveries_since.is_some_and(|since| {
+                    now.saturating_duration_since(since) >= recovery_batch_window
+                }) {
+                    recoveries_since = None;
+                    heartbeat_tx
+                        .send(Alert {
+                            kind: AlertKind::Recovery,
+                            reset_rules: std::mem::take(&mut recovery_resets),
+                            reset_keys: std::mem::take(&mut recovery_keys),
+                            ..Alert::new(recovery_summary(&std::mem::take(&mut recoveries)))
+                        })
+                        .await
+                        .inspect_err(|e| {
+                            error!("Failed to send heartbeat recovery alert: {}", e);
+                        })
+                        .ok();
+                }
+                sleep(jittered(
+                    Duration::from_secs(heartbeat_interval),
+                    heartbeat_jitter,
+                ))
+                .await;
             }
-        });
+        }));
+
+        // Start the heartbeat state sweep, removing entries of rules that no longer exist
+        let heartbeat_updates = self.heartbeat_updates.clone();
+        let heartbeat_misses = self.heartbeat_misses.clone();
+        let heartbeat_counts = self.heartbeat_counts.clone();
+        let heartbeat_cadences = self.heartbeat_cadences.clone();
+        let heartbeat_rules = self.config.heartbeats.len();
+        let sweep_interval = Duration::from_secs(self.config.heartbeat_sweep_interval);
+        if !sweep_interval.is_zero() {
+            tasks.0.push(spawn(async move {
+                loop {
+                    sleep(sweep_interval).await;
+                    sweep_heartbeat_state(
+                        &heartbeat_updates,
+                        &heartbeat_misses,
+                        &heartbeat_counts,
+                        &heartbeat_cadences,
+                        heartbeat_rules,
+                    );
+                }
+            }));
+        }
 
         // Start processing the journal
+        let mut stats = ReadStats::default();
+        if let Some(lines) = &self.lines {
+            let _running = Running::new(&self.metrics.journal_readers);
+            self.follow_channel(lines, |line| self.parse(line), &tx, &mut stats)
+                .await;
+            return Ok(());
+        }
+        if self.config.journal_source == JournalSource::Native {
+            info!("Reading the journal natively...");
+            return self.follow_native(&tx, &mut stats).await;
+        }
         info!("Starting journalctl process...");
-        let unit = self.config.systemd_unit.clone();
-        let alerts_matcher = &self.matcher_alerts;
-        let heartbeats_matcher = &self.matcher_heartbeats;
-
-        let mut args = vec![
-            "-oL", // flush output line by line
-            "journalctl",
-            "--follow",
-            "--lines",
-            "0",
-            "--output=cat",
-            "--no-pager",
-        ];
-
-        if self.config.systemd_unit.is_empty() {
-            warn!("No systemd unit specified, monitoring all logs.");
-        } else {
-            info!("Filtering logs for systemd unit: {}", unit);
-            args.extend_from_slice(&["--unit", &unit]);
+        let args = journalctl_args(&self.config);
+        let mut directories = directory_args(&self.config);
+        if directories.len() == 1 {
+            let args = args
+                .into_iter()
+                .chain(directories.pop().flatten())
+                .collect();
+            return self.follow_journalctl(args, &tx, &mut stats).await;
         }
 
-        let mut log_processed = 0u128;
-        let mut log_matched = 0u128;
+        // one journalctl per directory, their lines are matched in the order they are read
+        let (lines_tx, lines_rx) = flume::bounded(NATIVE_QUEUE_CAPACITY);
+        let mut readers = tokio::task::JoinSet::new();
+        for (index, directory) in directories.into_iter().enumerate() {
+            let args = args.iter().cloned().chain(directory).collect();
+            readers.spawn(forward_journalctl(
+                args,
+                index,
+                lines_tx.clone(),
+                self.config.buffer_size_bytes,
+            ));
+        }
+        drop(lines_tx);
+        let _running = Running::new(&self.metrics.journal_readers);
+        let entry = |(directory, line)| JournalEntry {
+            directory,
+            ..self.parse(line)
+        };
+        tokio::select! {
+            _ = self.follow_channel(&lines_rx, entry, &tx, &mut stats) => Ok(()),
+            // a journalctl failing for good stops the others, as a single one would
+            Some(res) = readers.join_next() => res?,
+        }
+    }
 
+    /// Follows the journal with journalctl, restarting it when it crashes.
+    async fn follow_journalctl(
+        &self,
+        args: Vec<String>,
+        tx: &AlertSender,
+        stats: &mut ReadStats,
+    ) -> Result<(), ProcessError> {
         loop {
             // outer loop to restart journalctl if it crashes
-            let mut child = Command::new("stdbuf")
-                .args(&args)
-                .stdout(Stdio::piped())
-                .spawn()
-                .context("Failed to spawn journalctl process")?;
-
-            let stdout = child
-                .stdout
-                .take()
-                .context("Failed to capture stdout of journalctl")?;
-
-            // use a large buffer (8MB) instead of the default 8KB
+            let (mut child, stdout, stderr) = spawn_journalctl(&args)?;
+            // counted as running until journalctl exits, or the processor is dropped
+            let running = Running::new(&self.metrics.journal_readers);
+            // lines read from this journalctl, an exit before any is not worth restarting
+            let mut child_lines = 0u64;
+
+            // use a large buffer (8MB by default) instead of the default 8KB
             // this will not help if the logs are generated faster than we can process them,
             // at a sustained rate, but it will help to smooth out short bursts
-            let buffer_size = 8 * 1024 * 1024;
-            let mut lines = BufReader::with_capacity(buffer_size, stdout).lines();
+            let mut reader = BufReader::with_capacity(self.config.buffer_size_bytes, stdout);
+            let mut buf = Vec::new();
             loop {
-                let Ok(Some(message)) = lines
-                    .next_line()
-                    .await
-                    .inspect_err(|e| warn!("jounranl process error {e}"))
+                // a held run is matched once its deadline passes, even without new lines
+                let read = read_line(&mut reader, &mut buf);
+                let read = match stats.run.as_ref().map(|run| run.deadline) {
+                    Some(deadline) => match tokio::time::timeout_at(deadline.into(), read).await {
+                        Ok(read) => read,
+                        Err(_) => {
+                            self.flush(tx, stats).await;
+                            continue;
+                        }
+                    },
+                    None => read.await,
+                };
+                let Ok(Some(line)) = read.inspect_err(|e| warn!("journal process error {e}"))
                 else {
-                    error!("Journalctl process terminated unexpectedly. Restarting...");
-                    // kill the process if it's still running
-                    let _ = child.kill().await;
-                    sleep(Duration::from_secs(1)).await;
+                    drop(running);
+                    restart_journalctl(&mut child, stderr, child_lines).await?;
                     break;
                 };
-                log_processed += 1;
-                // alerts matching
-                match alerts_matcher.find_match(&message) {
-                    Some((i, msg)) => {
-                        debug!("Matched alert log message: {}", message);
-                        // get the prefix for this alerts
-                        let prefix = &self.config.alerts[i].prefix;
-                        let msg = format!("{}{}", prefix, msg);
-                        // if we cannot process the message, just log and continue
-                        tx.send(msg)
-                            .inspect_err(|e| {
-                                error!("Failed to send alert message: {}", e);
-                            })
-                            .ok();
-                        log_matched += 1;
-                    }
-                    None => {
-                        debug!("No matching rule for log message: {}", message);
+                child_lines += 1;
+                // track how far behind the reader is
+                Metrics::set(
+                    &self.metrics.read_buffer_bytes,
+                    reader.buffer().len() as u64,
+                );
+                // strip the other fields so the matchers only see the message body
+                self.read(self.parse(line), tx, stats).await;
+            }
+        }
+    }
+
+    /// Reads the journal with libsystemd instead of journalctl, until reading fails.
+    async fn follow_native(
+        &self,
+        tx: &AlertSender,
+        stats: &mut ReadStats,
+    ) -> Result<(), ProcessError> {
+        // the journal is read on a blocking thread, it stops once the receiver is gone
+        let (entries_tx, entries_rx) = flume::bounded(NATIVE_QUEUE_CAPACITY);
+        let config = self.config.clone();
+        let reader = tokio::task::spawn_blocking(move || native::follow(&config, &entries_tx));
+        let _running = Running::new(&self.metrics.journal_readers);
+        self.follow_channel(&entries_rx, |entry| self.with_body_fields(entry), tx, stats)
+            .await;
+        reader.await?
+    }
+
+    /// Reads the items of the channel as journal entries until it is closed.
+    async fn follow_channel<T>(
+        &self,
+        items: &flume::Receiver<T>,
+        entry: impl Fn(T) -> JournalEntry,
+        tx: &AlertSender,
+        stats: &mut ReadStats,
+    ) {
+        loop {
+            let recv = items.recv_async();
+            let item = match stats.run.as_ref().map(|run| run.deadline) {
+                Some(deadline) => match tokio::time::timeout_at(deadline.into(), recv).await {
+                    Ok(item) => item,
+                    Err(_) => {
+                        self.flush(tx, stats).await;
+                        continue;
                     }
-                }
+                },
+                None => recv.await,
+            };
+            let Ok(item) = item else { break };
+            self.read(entry(item), tx, stats).await;
+        }
+        self.flush(tx, stats).await;
+    }
 
-                // heartbeats matching, if matched, update the last seen time
-                if let Some((i, msg)) = heartbeats_matcher.find_match(&message) {
-                    debug!("Matched heartbeat log message: {}", message);
-                    self.heartbeat_updates.insert(i, (Instant::now(), msg));
-                } else {
-                    debug!("No matching rule for log message: {}", message);
-                }
+    /// Matches an entry read from the journal, or holds it back while it repeats the
+    /// previous ones when collapsing them.
+    async fn read(&self, entry: JournalEntry, tx: &AlertSender, stats: &mut ReadStats) {
+        // a flood of lines is dropped before it reaches the matchers
+        if !self.within_rate(&entry, tx, stats).await {
+            return;
+        }
+        if self.config.collapse_repeats_secs == 0 {
+            return self.process(entry, 1, tx, stats).await;
+        }
+        if let Some(run) = &mut stats.run
+            && run.entry.message == entry.message
+            && run.entry.unit == entry.unit
+            && Instant::now() < run.deadline
+        {
+            run.count += 1;
+            *self.last_line.lock().unwrap() = Instant::now();
+            Metrics::inc(&self.metrics.lines_processed);
+            return;
+        }
+        self.flush(tx, stats).await;
+        stats.run = Some(Run {
+            entry,
+            count: 1,
+            deadline: Instant::now() + Duration::from_secs(self.config.collapse_repeats_secs),
+        });
+    }
 
-                if log_processed.is_multiple_of(self.config.print_count_interval) {
-                    info!(
-                        "Processed {} log messages, matched {} alerts.",
-                        log_processed, log_matched
-                    );
-                }
+    /// Takes the entry from the `max_lines_per_sec` budget. Above the rate the entries
+    /// are dropped, with one alert when the flood starts, until the rate stayed under
+    /// the limit for a second.
+    async fn within_rate(
+        &self,
+        entry: &JournalEntry,
+        tx: &AlertSender,
+        stats: &mut ReadStats,
+    ) -> bool {
+        let rate = self.config.max_lines_per_sec;
+        if rate == 0 {
+            return true;
+        }
+        let now = Instant::now();
+        let budget = stats
+            .budget
+            .get_or_insert_with(|| LineBudget::new(rate, now));
+        if budget.take(now) {
+            if budget.full()
+                && let Some(dropped) = budget.flood.take()
+            {
+                info!("Log flood ended, {} lines dropped", dropped);
+            }
+            return true;
+        }
+        *self.last_line.lock().unwrap() = now;
+        Metrics::inc(&self.metrics.lines_dropped);
+        if let Some(dropped) = &mut budget.flood {
+            *dropped += 1;
+            return false;
+        }
+        budget.flood = Some(1);
+        let source = match (&entry.unit, self.config.systemd_unit.as_str()) {
+            (Some(unit), _) => unit.as_str(),
+            (None, "") => "the journal",
+            (None, unit) => unit,
+        };
+        warn!("Log flood detected from {}, dropping lines", source);
+        tx.send(Alert {
+            severity: Severity::Warning,
+            ..Alert::new(format!(
+                "🌊 Log flood detected from {source}: more than {rate} lines per second, the lines above the limit are not matched."
+            ))
+        })
+        .await
+        .inspect_err(|e| error!("Failed to send log flood alert: {}", e))
+        .ok();
+        false
+    }
+
+    /// Matches the run of identical entries held back, if any.
+    async fn flush(&self, tx: &AlertSender, stats: &mut ReadStats) {
+        if let Some(run) = stats.run.take() {
+            self.process(run.entry, run.count, tx, stats).await;
+        }
+    }
+
+    /// Matches a journal entry against the rules, sending the alerts it produces.
+    /// `repeated` is the number of consecutive identical entries it stands for.
+    async fn process(
+        &self,
+        entry: JournalEntry,
+        repeated: usize,
+        tx: &AlertSender,
+        stats: &mut ReadStats,
+    ) {
+        stats.processed += 1;
+        let message = entry.message.as_str();
+        *self.last_line.lock().unwrap() = Instant::now();
+        Metrics::inc(&self.metrics.lines_processed);
+        // track how far behind the notifier is
+        let depth = tx.len();
+        Metrics::set(&self.metrics.channel_depth, depth as u64);
+        if !stats.backlogged && depth >= self.config.backlog_warn_threshold {
+            warn!("Notifier is falling behind, {} alerts queued", depth);
+            stats.backlogged = true;
+        } else if stats.backlogged && depth < self.config.backlog_warn_threshold / 2 {
+            info!("Notifier caught up, {} alerts queued", depth);
+            stats.backlogged = false;
+        }
+        // entries of excluded units and transports are read but never matched
+        if self.excluded(&entry) {
+            return;
+        }
+        if self.predates_start(&entry) {
+            debug!("Ignoring line logged before the start: {}", message);
+            return;
+        }
+        // blank lines only make empty alerts, they still count as journal activity
+        if self.config.skip_blank_lines && message.trim().is_empty() {
+            return;
+        }
+
+        // resolve lines of the open alerts, before they match a rule themselves
+        for alert in self.resolve(&entry) {
+            tx.send(alert)
+                .await
+                .inspect_err(|e| {
+                    error!("Failed to send alert resolved message: {}", e);
+                })
+                .ok();
+        }
+
+        // alerts matching, one per matching rule with `match_all`
+        let alerts = self.match_alerts(&entry);
+        if alerts.is_empty() {
+            debug!("No matching rule for log message: {}", message);
+        }
+        for (mut alert, event) in alerts {
+            alert.repeated = repeated;
+            debug!("Matched alert log message: {}", message);
+            stats.matched += 1;
+            Metrics::inc(&self.metrics.alerts_matched);
+            Metrics::inc_rule(
+                &self.metrics.rule_matches,
+                &self.monitor,
+                event.rule_index,
+                &event.rule_name,
+            );
+            let rule = event.rule_name.as_str();
+            // the silenced and held matches don't count towards the hosts, patterns or samples
+            if self.silences.is_silenced(rule) {
+                debug!("Rule '{}' is silenced, skipping alert", rule);
+            } else if !alert.always_notify && self.warmup.hold(&alert) {
+                debug!(
+                    "Rule '{}' matched during the startup window, holding alert",
+                    rule
+                );
+            } else if !self.enough_hosts(&event, &entry, &mut alert) {
+                debug!(
+                    "Rule '{}' matched on too few hosts so far, not alerting",
+                    rule
+                );
+            } else if !self.all_matched(&event, &mut alert) {
+                debug!(
+                    "Rule '{}' has not matched all of its patterns yet, not alerting",
+                    rule
+                );
+            } else if !self.sampled(&event, &mut alert) {
+                debug!("Rule '{}' match not sampled, not alerting", rule);
+            } else {
+                self.open(&event);
+                // if we cannot process the message, just log and continue
+                tx.send(alert)
+                    .await
+                    .inspect_err(|e| {
+                        error!("Failed to send alert message: {}", e);
+                    })
+                    .ok();
+            }
+        }
+
+        // heartbeats matching, if matched, update the last seen time
+        if let Some((i, msg)) = self.matcher_heartbeats.find_match(message) {
+            debug!("Matched heartbeat log message: {}", message);
+            let now = Instant::now();
+            self.heartbeat_updates.insert(i, (now, msg));
+            let rule = &self.config.heartbeats[i];
+            if rule.cadence_sensitivity.is_some() {
+                self.heartbeat_cadences
+                    .entry(i)
+                    .or_insert_with(|| Cadence::new(rule.cadence_samples))
+                    .record(now);
+            }
+            if rule.min_count.is_some() {
+                self.heartbeat_counts
+                    .entry(i)
+                    .or_insert_with(|| WindowCount::new(self.started))
+                    .count += repeated;
+            }
+        } else {
+            debug!("No matching rule for log message: {}", message);
+        }
+
+        if stats
+            .processed
+            .is_multiple_of(self.config.print_count_interval)
+        {
+            info!(
+                "Processed {} log messages, matched {} alerts.",
+                stats.processed, stats.matched
+            );
+        }
+    }
+}
+
+/// Removes the heartbeat state of indices outside of the rule set,
+/// returns the number of entries removed.
+fn sweep_heartbeat_state(
+    updates: &DashMap<usize, (Instant, String)>,
+    misses: &DashMap<usize, (Instant, usize)>,
+    counts: &DashMap<usize, WindowCount>,
+    cadences: &DashMap<usize, Cadence>,
+    rules: usize,
+) -> usize {
+    let mut removed = 0;
+    updates.retain(|i, (_, pattern)| {
+        let keep = *i < rules;
+        if !keep {
+            info!(
+                "Sweeping stale heartbeat state for index {}: '{}'",
+                i, pattern
+            );
+            removed += 1;
+        }
+        keep
+    });
+    misses.retain(|i, _| {
+        let keep = *i < rules;
+        if !keep {
+            info!("Sweeping stale heartbeat miss for index {}", i);
+            removed += 1;
+        }
+        keep
+    });
+    counts.retain(|i, _| {
+        let keep = *i < rules;
+        if !keep {
+            info!("Sweeping stale heartbeat count for index {}", i);
+            removed += 1;
+        }
+        keep
+    });
+    cadences.retain(|i, _| {
+        let keep = *i < rules;
+        if !keep {
+            info!("Sweeping stale heartbeat cadence for index {}", i);
+            removed += 1;
+        }
+        keep
+    });
+    if removed > 0 {
+        info!("Heartbeat sweep removed {} stale entries", removed);
+    }
+    removed
+}
+
+/// Builds the arguments for `stdbuf` to run `journalctl` with the configured filters.
+fn journalctl_args(config: &Config) -> Vec<String> {
+    let mut args: Vec<String> = [
+        "-oL", // flush output line by line
+        "journalctl",
+        "--follow",
+        "--lines",
+        "0",
+        OutputFormat::from_config(config).arg(),
+        "--no-pager",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+
+    if config.systemd_unit.is_empty() {
+        warn!("No systemd unit specified, monitoring all logs.");
+    } else {
+        info!("Filtering logs for systemd unit: {}", config.systemd_unit);
+    }
+    for identifier in &config.syslog_identifier {
+        info!("Filtering logs for syslog identifier: {}", identifier);
+    }
+    args.extend(journal_filter(config));
+
+    match config.boot.as_deref() {
+        Some("") => info!("Scoping logs to the current boot"),
+        Some(boot) => info!("Scoping logs to boot: {}", boot),
+        None => {}
+    }
+    args.extend(boot_arg(config));
+
+    // lines not matching the prefilter never reach the matchers
+    if !config.prefilter.is_empty() {
+        info!("Prefiltering logs with: {}", config.prefilter);
+        args.push(format!("--grep={}", config.prefilter));
+    }
+
+    args
+}
+
+/// Reads the next line without its line ending, `None` at the end of the stream.
+///
+/// Invalid UTF-8, e.g. from legacy services, is replaced rather than failing the read:
+/// a line based reader would error and end the stream on a single stray byte.
+///
+/// Cancel safe: a line partially read when the future is dropped is kept in `buf`,
+/// and completed by the next call.
+async fn read_line(
+    reader: &mut (impl AsyncBufRead + Unpin),
+    buf: &mut Vec<u8>,
+) -> std::io::Result<Option<String>> {
+    if reader.read_until(b'\n', buf).await? == 0 && buf.is_empty() {
+        return Ok(None);
+    }
+    if buf.ends_with(b"\n") {
+        buf.pop();
+        if buf.ends_with(b"\r") {
+            buf.pop();
+        }
+    }
+    let line = String::from_utf8_lossy(buf).into_owned();
+    buf.clear();
+    Ok(Some(line))
+}
+
+/// The key pairing the miss of a heartbeat with its recovery, stable across reloads.
+/// The monitors may watch the same pattern, each pairs its own miss and recovery.
+fn heartbeat_thread_key(monitor: &str, pattern: &str) -> String {
+    format!("heartbeat:{monitor}:{pattern}")
+}
+
+/// The text a heartbeat miss is deduplicated on, the same whatever the time since the
+/// heartbeat was last seen and across reloads. Its recovery clears it for the next miss.
+fn heartbeat_miss_key(monitor: &str, index: usize) -> String {
+    format!("heartbeat:{monitor}:{index}")
+}
+
+/// Counts the consecutive checks the heartbeat was missed, returns whether it has
+/// been missed for at least `consecutive` of them. A check where it is fine resets
+/// the count.
+fn sustained(
+    overdue: &mut HashMap<usize, usize>,
+    i: usize,
+    missed: bool,
+    consecutive: usize,
+) -> bool {
+    if !missed {
+        overdue.remove(&i);
+        return false;
+    }
+    let count = overdue.entry(i).or_insert(0);
+    *count += 1;
+    *count >= consecutive.max(1)
+}
+
+// number of journalctl error lines kept for the exit error
+const STDERR_LINES: usize = 10;
+// journal entries read natively but not processed yet
+const NATIVE_QUEUE_CAPACITY: usize = 1024;
+// hosts named in the alerts of `min_hosts` rules, the others are counted
+const MAX_LISTED_HOSTS: usize = 10;
+
+/// Starts journalctl with the arguments, its stderr collected on a task.
+fn spawn_journalctl(
+    args: &[String],
+) -> Result<(Child, ChildStdout, JoinHandle<Vec<String>>), ProcessError> {
+    let mut child = Command::new("stdbuf")
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(ProcessError::Spawn)?;
+    let stdout = child.stdout.take().ok_or(ProcessError::Capture("stdout"))?;
+    let stderr = child.stderr.take().ok_or(ProcessError::Capture("stderr"))?;
+    Ok((child, stdout, spawn(collect_stderr(stderr))))
+}
+
+/// Waits for a journalctl whose output ended before it is restarted, or fails when it
+/// exited with an error before reading any line.
+async fn restart_journalctl(
+    child: &mut Child,
+    stderr: JoinHandle<Vec<String>>,
+    lines: u64,
+) -> Result<(), ProcessError> {
+    // give the process a moment to exit, kill it if it's still running
+    let status = tokio::time::timeout(Duration::from_secs(5), child.wait()).await;
+    let _ = child.kill().await;
+    let stderr = stderr.await.unwrap_or_default();
+    if let Ok(Ok(status)) = status
+        && !status.success()
+        && lines == 0
+    {
+        // e.g. a missing permission, restarting would fail the same way
+        return Err(ProcessError::Exited {
+            status,
+            stderr: stderr.join("\n"),
+        });
+    }
+    error!("Journalctl process terminated unexpectedly. Restarting...");
+    sleep(Duration::from_secs(1)).await;
+    Ok(())
+}
+
+/// Follows the journal of a directory with journalctl, restarting it when it crashes,
+/// and sends its lines with the index of the directory until the receiver is gone.
+async fn forward_journalctl(
+    args: Vec<String>,
+    index: usize,
+    tx: flume::Sender<(usize, String)>,
+    buffer_size: usize,
+) -> Result<(), ProcessError> {
+    loop {
+        let (mut child, stdout, stderr) = spawn_journalctl(&args)?;
+        let mut reader = BufReader::with_capacity(buffer_size, stdout);
+        let mut buf = Vec::new();
+        let mut child_lines = 0u64;
+        loop {
+            let read = read_line(&mut reader, &mut buf).await;
+            let Ok(Some(line)) = read.inspect_err(|e| warn!("journal process error {e}")) else {
+                restart_journalctl(&mut child, stderr, child_lines).await?;
+                break;
+            };
+            child_lines += 1;
+            if tx.send_async((index, line)).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Logs the errors of journalctl as they come, returning the last ones once it exits.
+async fn collect_stderr(stderr: impl AsyncRead + Unpin) -> Vec<String> {
+    let mut lines = BufReader::new(stderr).lines();
+    let mut last = VecDeque::with_capacity(STDERR_LINES);
+    while let Ok(Some(line)) = lines.next_line().await {
+        warn!("journalctl: {}", line);
+        if last.len() == STDERR_LINES {
+            last.pop_front();
+        }
+        last.push_back(line);
+    }
+    last.into()
+}
+
+/// Randomizes the interval by up to ± `jitter` of it, so that hosts started together
+/// do not evaluate their heartbeats at the same time.
+fn jittered(interval: Duration, jitter: f64) -> Duration {
+    if jitter <= 0.0 {
+        return interval;
+    }
+    let factor = 1.0 + jitter * rand::random_range(-1.0..=1.0);
+    interval.mul_f64(factor.max(0.0))
+}
+
+/// Summarizes batched recovery messages in one, a single one is kept as is.
+fn recovery_summary(recoveries: &[String]) -> String {
+    match recoveries {
+        [recovery] => recovery.clone(),
+        _ => format!(
+            "🩹 {} heartbeats recovered:\n{}",
+            recoveries.len(),
+            recoveries
+                .iter()
+                .map(|r| format!("• {}", r.trim_start_matches("🩹 ")))
+                .collect::<Vec<_>>()
+                .join("\n")
+        ),
+    }
+}
+
+/// The journalctl arguments reading the monitored entries logged since `since`, e.g.
+/// `-1h`, without following the journal. The prefilter is left out.
+pub fn preview_args(config: &Config, since: &str) -> Vec<String> {
+    let mut args = vec![
+        format!("--since={since}"),
+        OutputFormat::from_config(config).arg().to_string(),
+        "--no-pager".to_string(),
+    ];
+    args.extend(journal_filter(config));
+    args.extend(boot_arg(config));
+    args
+}
+
+/// The journalctl argument of each journal directory read, a single `None` reading
+/// the local journal.
+pub fn directory_args(config: &Config) -> Vec<Option<String>> {
+    if config.journal_directory.is_empty() {
+        return vec![None];
+    }
+    config
+        .journal_directory
+        .iter()
+        .map(|directory| Some(format!("--directory={directory}")))
+        .collect()
+}
+
+/// The journalctl argument scoping the entries to the configured boot.
+fn boot_arg(config: &Config) -> Option<String> {
+    // an empty boot id means the current boot, same as a bare `--boot`
+    config.boot.as_deref().map(|boot| match boot {
+        "" => "--boot".to_string(),
+        boot => format!("--boot={boot}"),
+    })
+}
+
+/// The journalctl arguments selecting the monitored entries.
+pub fn journal_filter(config: &Config) -> Vec<String> {
+    let mut args = Vec::new();
+    if !config.systemd_unit.is_empty() {
+        args.extend(["--unit".to_string(), config.systemd_unit.clone()]);
+    }
+    // identifiers are combined with the unit filter, so only entries
+    // matching both the unit and one of the identifiers are returned
+    for identifier in &config.syslog_identifier {
+        args.extend(["--identifier".to_string(), identifier.clone()]);
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(toml: &str) -> Config {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn test_journalctl_args_unit_and_identifiers() {
+        let config = config(
+            r#"
+            slack_webhook_url = ""
+            systemd_unit = "myservice.service"
+            syslog_identifier = ["app", "worker"]
+            "#,
+        );
+        let args = journalctl_args(&config);
+        let tail = &args[args.len() - 6..];
+        assert_eq!(
+            tail,
+            [
+                "--unit",
+                "myservice.service",
+                "--identifier",
+                "app",
+                "--identifier",
+                "worker"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_journalctl_args_single_identifier() {
+        let config = config(
+            r#"
+            slack_webhook_url = ""
+            systemd_unit = ""
+            syslog_identifier = "app"
+            "#,
+        );
+        let args = journalctl_args(&config);
+        assert!(!args.contains(&"--unit".to_string()));
+        assert_eq!(&args[args.len() - 2..], ["--identifier", "app"]);
+    }
+
+    #[test]
+    fn test_sweep_heartbeat_state() {
+        let updates = DashMap::new();
+        let misses = DashMap::new();
+        for i in 0..3 {
+            updates.insert(i, (Instant::now(), format!("pattern-{i}")));
+        }
+        let counts = DashMap::new();
+        misses.insert(0, (Instant::now(), 1));
+        misses.insert(2, (Instant::now(), 1));
+        counts.insert(3, WindowCount::new(Instant::now()));
+
+        assert_eq!(
+            sweep_heartbeat_state(&updates, &misses, &counts, &DashMap::new(), 2),
+            3
+        );
+        assert_eq!(updates.len(), 2);
+        assert!(!updates.contains_key(&2));
+        assert_eq!(misses.len(), 1);
+        assert!(counts.is_empty());
+        assert_eq!(
+            sweep_heartbeat_state(&updates, &misses, &counts, &DashMap::new(), 2),
+            0
+        );
+    }
+
+    #[test]
+    fn test_window_count() {
+        let start = Instant::now();
+        let window = Duration::from_secs(60);
+        let mut count = WindowCount::new(start);
+        count.count = 3;
+        // nothing to judge until the first window is complete
+        assert_eq!(count.roll(start + Duration::from_secs(30), window), None);
+        count.count += 1;
+        assert_eq!(count.roll(start + window, window), Some(4));
+        assert_eq!(count.count, 0);
+        // the last complete window is kept while the current one fills
+        count.count = 2;
+        assert_eq!(count.roll(start + Duration::from_secs(90), window), Some(4));
+        assert_eq!(
+            count.roll(start + Duration::from_secs(120), window),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn test_journalctl_args_boot() {
+        let base = r#"
+            slack_webhook_url = ""
+            systemd_unit = ""
+            "#;
+        let args = journalctl_args(&config(base));
+        assert!(!args.iter().any(|a| a.starts_with("--boot")));
+
+        let args = journalctl_args(&config(&format!("{base}\nboot = \"-1\"")));
+        assert_eq!(args.last().unwrap(), "--boot=-1");
+
+        let args = journalctl_args(&config(&format!("{base}\nboot = \"\"")));
+        assert_eq!(args.last().unwrap(), "--boot");
+    }
+
+    #[test]
+    fn test_preview_args() {
+        let config = config(
+            r#"
+            slack_webhook_url = ""
+            systemd_unit = "myservice.service"
+            prefilter = "error"
+            boot = ""
+            "#,
+        );
+        assert_eq!(
+            preview_args(&config, "-1h"),
+            [
+                "--since=-1h",
+                "--output=cat",
+                "--no-pager",
+                "--unit",
+                "myservice.service",
+                "--boot"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_journal_directories() {
+        let base = "slack_webhook_url = \"\"\nsystemd_unit = \"app.service\"\n[[alerts]]\npattern = \"error\"\nprefix = \"\"";
+        assert_eq!(directory_args(&config(base)), [None]);
+
+        let config = config(&format!(
+            "journal_directory = [\"/var/log/journal/remote\", \"/mnt/web-1\"]\n{base}"
+        ));
+        assert_eq!(
+            directory_args(&config),
+            [
+                Some("--directory=/var/log/journal/remote".to_string()),
+                Some("--directory=/mnt/web-1".to_string())
+            ]
+        );
+        // the context of an alert is read from the directory of its entry
+        let processor = JournalProcessor::new(&config).unwrap();
+        assert_eq!(
+            processor.journal_filters[1],
+            ["--directory=/mnt/web-1", "--unit", "app.service"]
+        );
+    }
+
+    #[test]
+    fn test_journalctl_args_prefilter() {
+        let config = config(
+            r#"
+            slack_webhook_url = ""
+            systemd_unit = ""
+            prefilter = "(?i)error|warn"
+            "#,
+        );
+        let args = journalctl_args(&config);
+        assert_eq!(args.last().unwrap(), "--grep=(?i)error|warn");
+    }
+
+    #[test]
+    fn test_journalctl_args_output() {
+        let base = "slack_webhook_url = \"\"\nsystemd_unit = \"\"";
+        let output = |extra: &str| {
+            journalctl_args(&config(&format!("{base}\n{extra}")))
+                .into_iter()
+                .find(|a| a.starts_with("--output="))
+                .unwrap()
+        };
+        assert_eq!(output(""), "--output=cat");
+        assert_eq!(output("include_timestamp = true"), "--output=short-iso");
+        // the unit of each entry is only available in the structured output
+        assert_eq!(
+            output("exclude_units = [\"chatty.service\"]"),
+            "--output=json"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_max_lines_per_sec() {
+        let config = config(
+            r#"
+            slack_webhook_url = ""
+            systemd_unit = "app.service"
+            max_lines_per_sec = 2
+            [[alerts]]
+            pattern = "error"
+            prefix = ""
+            "#,
+        );
+        let processor = JournalProcessor::new(&config).unwrap();
+        let (tx, rx) = crate::channel::channel(&config, Arc::default());
+        let mut stats = ReadStats::default();
+        for _ in 0..5 {
+            processor
+                .read(processor.parse("error".to_string()), &tx, &mut stats)
+                .await;
+        }
+        // two lines matched, then a single alert for the three dropped
+        let messages = rx.drain().map(|alert| alert.message).collect::<Vec<_>>();
+        assert_eq!(messages.len(), 3);
+        assert!(messages[2].starts_with("🌊 Log flood detected from app.service"));
+        assert_eq!(
+            processor
+                .metrics
+                .lines_dropped
+                .load(std::sync::atomic::Ordering::Relaxed),
+            3
+        );
+
+        // the flood ends once the rate stayed under the limit for a second
+        let start = Instant::now();
+        let mut budget = LineBudget::new(2, start);
+        assert!(budget.take(start) && budget.take(start) && !budget.take(start));
+        assert!(budget.take(start + Duration::from_millis(500)));
+        assert!(!budget.full());
+        assert!(budget.take(start + Duration::from_millis(2000)));
+        assert!(budget.full());
+    }
+
+    #[test]
+    fn test_recovery_summary() {
+        let recoveries = [
+            "🩹 Heartbeat recovered in 30s for pattern 'a'.".to_string(),
+            "🩹 Heartbeat recovered in 60s for pattern 'b'.".to_string(),
+        ];
+        assert_eq!(recovery_summary(&recoveries[..1]), recoveries[0]);
+        assert_eq!(
+            recovery_summary(&recoveries),
+            "🩹 2 heartbeats recovered:\n\
+             • Heartbeat recovered in 30s for pattern 'a'.\n\
+             • Heartbeat recovered in 60s for pattern 'b'."
+        );
+    }
+
+    #[test]
+    fn test_match_event() {
+        let processor = JournalProcessor::new(&config(
+            r#"
+            slack_webhook_url = ""
+            systemd_unit = ""
+            severity_from_priority = true
+            [[alerts]]
+            name = "disk"
+            pattern = "disk \\w+"
+            prefix = "🔴 "
+            "#,
+        ))
+        .unwrap();
+        let entry = JournalEntry {
+            message: "error: disk full".to_string(),
+            priority: Some(2),
+            ..Default::default()
+        };
+        let (alert, event) = processor.match_alert(&entry).unwrap();
+        assert_eq!(
+            event,
+            MatchEvent {
+                rule_index: 0,
+                rule_name: "disk".to_string(),
+                severity: Severity::Critical,
+                line: "error: disk full".to_string(),
+                span: 7..16,
             }
+        );
+        assert_eq!(alert.message, "🔴 error: disk full");
+        assert_eq!(alert.severity, event.severity);
+        assert_eq!(processor.match_event(&JournalEntry::default()), None);
+    }
+
+    #[test]
+    fn test_runbook() {
+        let rules = r#"
+            slack_webhook_url = ""
+            systemd_unit = ""
+            [[alerts]]
+            pattern = "disk full"
+            prefix = ""
+            description = "The data volume is full"
+            runbook_url = "https://wiki.example.com/disk"
+            "#;
+        let entry = JournalEntry {
+            message: "disk full".to_string(),
+            ..Default::default()
+        };
+        let message = |toml: &str| {
+            let processor = JournalProcessor::new(&config(toml)).unwrap();
+            processor.match_alert(&entry).unwrap().0.message
+        };
+        assert_eq!(
+            message(rules),
+            "disk full\nThe data volume is full\nRunbook: https://wiki.example.com/disk"
+        );
+        assert_eq!(
+            message(&format!("rich_formatting = true\n{rules}")),
+            "`disk full`\nThe data volume is full\n<https://wiki.example.com/disk|Runbook>"
+        );
+    }
+
+    #[test]
+    fn test_template() {
+        let processor = JournalProcessor::new(&config(
+            r#"
+            slack_webhook_url = ""
+            systemd_unit = ""
+            [[alerts]]
+            pattern = '(?P<volume>/\w+) full, (\d+)%'
+            prefix = "🔴 "
+            template = "{_HOSTNAME}: {volume} at {2}% ({MESSAGE}) {UNKNOWN}"
+            "#,
+        ))
+        .unwrap();
+        assert_eq!(processor.output, OutputFormat::Json);
+        let entry = JournalEntry {
+            message: "disk /data full, 95%".to_string(),
+            fields: [("_HOSTNAME".to_string(), "web-1".to_string())].into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            processor.match_alert(&entry).unwrap().0.message,
+            "🔴 web-1: /data at 95% (disk /data full, 95%) "
+        );
+    }
+
+    #[test]
+    fn test_require_fields() {
+        let processor = JournalProcessor::new(&config(
+            r#"
+            slack_webhook_url = ""
+            systemd_unit = ""
+            [[alerts]]
+            name = "correlation"
+            pattern = "request"
+            prefix = ""
+            require_fields = ["CORRELATION_ID"]
+            [[alerts]]
+            name = "failed"
+            pattern = "failed"
+            prefix = ""
+            "#,
+        ))
+        .unwrap();
+        assert_eq!(processor.output, OutputFormat::Json);
+        let entry = |message: &str, id: &str| JournalEntry {
+            message: message.to_string(),
+            fields: [("CORRELATION_ID".to_string(), id.to_string())].into(),
+            ..Default::default()
+        };
+        let alert = processor
+            .match_alert(&entry("request done", " "))
+            .unwrap()
+            .0;
+        assert_eq!(alert.rule.as_deref(), Some("correlation"));
+        assert_eq!(
+            alert.message,
+            "request done\nMissing fields: CORRELATION_ID"
+        );
+        assert!(
+            processor
+                .match_alert(&entry("request done", "42"))
+                .is_none()
+        );
+        // a line with the fields falls through to the next rules
+        let alert = processor
+            .match_alert(&entry("request failed", "42"))
+            .unwrap()
+            .0;
+        assert_eq!(alert.rule.as_deref(), Some("failed"));
+    }
+
+    #[test]
+    fn test_quiet_start() {
+        let processor = JournalProcessor::new(&config(
+            r#"
+            slack_webhook_url = ""
+            systemd_unit = ""
+            quiet_start = true
+            [[alerts]]
+            pattern = "error"
+            prefix = ""
+            "#,
+        ))
+        .unwrap();
+        assert_eq!(processor.output, OutputFormat::ShortIso);
+        let old = processor.parse("2025-01-01T10:00:00+0100 web-1 app[42]: error".to_string());
+        assert!(processor.predates_start(&old));
+        let now = Local::now().format("%Y-%m-%dT%H:%M:%S%:z");
+        let new = processor.parse(format!("{now} web-1 app[42]: error"));
+        assert!(!processor.predates_start(&new));
+        // lines without a timestamp are processed
+        assert!(!processor.predates_start(&processor.parse("error".to_string())));
+    }
+
+    #[test]
+    fn test_transports() {
+        let processor = JournalProcessor::new(&config(
+            r#"
+            slack_webhook_url = ""
+            systemd_unit = ""
+            exclude_transports = ["kernel"]
+            [[alerts]]
+            pattern = "error"
+            prefix = ""
+            "#,
+        ))
+        .unwrap();
+        assert_eq!(processor.output, OutputFormat::Json);
+        let entry = |transport: &str| {
+            processor.parse(format!(
+                r#"{{"MESSAGE":"error","_TRANSPORT":"{transport}"}}"#
+            ))
+        };
+        assert!(processor.excluded(&entry("kernel")));
+        assert!(!processor.excluded(&entry("stdout")));
+        assert!(!processor.excluded(&processor.parse(r#"{"MESSAGE":"error"}"#.to_string())));
+
+        let processor = JournalProcessor::new(&config(
+            r#"
+            slack_webhook_url = ""
+            systemd_unit = ""
+            transports = ["kernel", "driver"]
+            [[alerts]]
+            pattern = "error"
+            prefix = ""
+            "#,
+        ))
+        .unwrap();
+        let entry = |transport: &str| {
+            processor.parse(format!(
+                r#"{{"MESSAGE":"error","_TRANSPORT":"{transport}"}}"#
+            ))
+        };
+        assert!(processor.excluded(&entry("stdout")));
+        assert!(!processor.excluded(&entry("kernel")));
+        assert!(processor.excluded(&processor.parse(r#"{"MESSAGE":"error"}"#.to_string())));
+    }
+
+    #[test]
+    fn test_match_all() {
+        let rules = r#"
+            slack_webhook_url = ""
+            systemd_unit = ""
+            [[alerts]]
+            name = "critical"
+            pattern = "CRITICAL"
+            prefix = "🔴 "
+            [[alerts]]
+            name = "db"
+            pattern = "postgres"
+            prefix = "🗄️ "
+            "#;
+        let entry = JournalEntry {
+            message: "CRITICAL postgres is down".to_string(),
+            ..Default::default()
+        };
+        let rules_matched = |match_all: &str| {
+            JournalProcessor::new(&config(&format!("{match_all}\n{rules}")))
+                .unwrap()
+                .match_alerts(&entry)
+                .into_iter()
+                .map(|(alert, _)| alert.rule.unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(rules_matched(""), ["critical"]);
+        assert_eq!(rules_matched("match_all = true"), ["critical", "db"]);
+    }
+
+    #[test]
+    fn test_match_fields() {
+        let processor = JournalProcessor::new(&config(
+            r#"
+            slack_webhook_url = ""
+            systemd_unit = ""
+            [[alerts]]
+            name = "stderr"
+            pattern = "."
+            prefix = ""
+            severity = "critical"
+            match_fields = { PRIORITY = "3" }
+            [[alerts]]
+            name = "failed"
+            pattern = "failed"
+            prefix = ""
+            "#,
+        ))
+        .unwrap();
+        assert_eq!(processor.output, OutputFormat::Json);
+        let entry = |message: &str, priority: &str| JournalEntry {
+            message: message.to_string(),
+            fields: [("PRIORITY".to_string(), priority.to_string())].into(),
+            ..Default::default()
+        };
+        let (alert, _) = processor.match_alert(&entry("panic", "3")).unwrap();
+        assert_eq!(alert.rule.as_deref(), Some("stderr"));
+        assert_eq!(alert.severity, Severity::Critical);
+        assert!(processor.match_alert(&entry("panic", "6")).is_none());
+        // lines without the value fall through to the next rules
+        let (alert, _) = processor
+            .match_alert(&entry("request failed", "6"))
+            .unwrap();
+        assert_eq!(alert.rule.as_deref(), Some("failed"));
+    }
+
+    #[test]
+    fn test_body_format() {
+        let processor = JournalProcessor::new(&config(
+            r#"
+            slack_webhook_url = ""
+            systemd_unit = ""
+            body_format = "logfmt"
+            [[alerts]]
+            pattern = "."
+            prefix = ""
+            match_fields = { level = "error" }
+            template = "{status}: {msg} on {_HOSTNAME}"
+            "#,
+        ))
+        .unwrap();
+        let line = |message: &str| {
+            let line = serde_json::json!({ "MESSAGE": message, "_HOSTNAME": "web-1" });
+            processor.parse(line.to_string())
+        };
+        let entry = line(r#"level=error status=503 msg="upstream down" _HOSTNAME=spoofed"#);
+        assert_eq!(
+            processor.match_alert(&entry).unwrap().0.message,
+            "503: upstream down on web-1"
+        );
+        assert!(processor.match_alert(&line("level=info msg=ok")).is_none());
+        // a body not in the format is matched as is, without its keys
+        assert!(processor.match_alert(&line("error: disk full")).is_none());
+    }
+
+    #[test]
+    fn test_decode() {
+        let processor = JournalProcessor::new(&config(
+            r#"
+            slack_webhook_url = ""
+            systemd_unit = ""
+            [[alerts]]
+            pattern = "cmd=([0-9a-f]+)"
+            prefix = "🔐 "
+            decode = "hex"
+            decode_group = "1"
+            decode_pattern = "^uid=0 "
+            [[alerts]]
+            pattern = "cmd="
+            prefix = ""
+            "#,
+        ))
+        .unwrap();
+        let entry = |message: &str| processor.parse(message.to_string());
+        // the decoded payload replaces the encoded one in the message
+        let (alert, event) = processor
+            .match_alert(&entry("audit cmd=7569643d30207375646f ok"))
+            .unwrap();
+        assert_eq!(event.rule_index, 0);
+        assert_eq!(alert.message, "🔐 audit cmd=uid=0 sudo ok");
+        // a decoded payload not matching the decode_pattern leaves the line to the next rule
+        let (_, event) = processor
+            .match_alert(&entry("audit cmd=7569643d31303030206c73"))
+            .unwrap();
+        assert_eq!(event.rule_index, 1);
+    }
+
+    #[test]
+    fn test_min_hosts() {
+        let processor = JournalProcessor::new(&config(
+            r#"
+            slack_webhook_url = ""
+            systemd_unit = ""
+            [[alerts]]
+            pattern = "disk error"
+            prefix = ""
+            min_hosts = 3
+            hosts_window = 300
+            "#,
+        ))
+        .unwrap();
+        assert_eq!(processor.output, OutputFormat::Json);
+        let matched = |host: &str| {
+            let entry = JournalEntry {
+                message: "disk error".to_string(),
+                fields: [("_HOSTNAME".to_string(), host.to_string())].into(),
+                ..Default::default()
+            };
+            let (mut alert, event) = processor.match_alert(&entry).unwrap();
+            processor
+                .enough_hosts(&event, &entry, &mut alert)
+                .then_some(alert.message)
+        };
+        // the same host counts once
+        assert_eq!(matched("web-2"), None);
+        assert_eq!(matched("web-2"), None);
+        assert_eq!(matched("web-1"), None);
+        assert_eq!(
+            matched("web-3").as_deref(),
+            Some("disk error\nSeen on 3 hosts within 5m: web-1, web-2, web-3")
+        );
+        // counting starts over after an alert
+        assert_eq!(matched("web-1"), None);
+    }
+
+    #[test]
+    fn test_all_of() {
+        let processor = JournalProcessor::new(&config(
+            r#"
+            slack_webhook_url = ""
+            systemd_unit = ""
+            [[alerts]]
+            name = "election"
+            pattern = "leader election started|timeout"
+            prefix = ""
+            all_of = ["leader election started", "timeout"]
+            all_of_window = 5
+            "#,
+        ))
+        .unwrap();
+        let matched = |line: &str| {
+            let entry = processor.parse(line.to_string());
+            let (mut alert, event) = processor.match_alert(&entry).unwrap();
+            processor
+                .all_matched(&event, &mut alert)
+                .then_some(alert.message)
+        };
+        assert_eq!(matched("leader election started"), None);
+        assert_eq!(matched("leader election started, term 2"), None);
+        assert_eq!(
+            matched("raft: timeout").as_deref(),
+            Some(
+                "raft: timeout\nAll 2 patterns matched within 5s:\n\
+                 • leader election started: leader election started, term 2\n\
+                 • timeout: raft: timeout"
+            )
+        );
+        // the patterns must match again after an alert
+        assert_eq!(matched("raft: timeout"), None);
+    }
+
+    #[test]
+    fn test_sample_rate() {
+        let processor = JournalProcessor::new(&config(
+            r#"
+            slack_webhook_url = ""
+            systemd_unit = ""
+            [[alerts]]
+            pattern = "cache miss"
+            prefix = ""
+            sample_rate = 0.25
+            "#,
+        ))
+        .unwrap();
+        let entry = JournalEntry {
+            message: "cache miss".to_string(),
+            ..Default::default()
+        };
+        let sampled = (0..9)
+            .filter_map(|_| {
+                let (mut alert, event) = processor.match_alert(&entry).unwrap();
+                processor
+                    .sampled(&event, &mut alert)
+                    .then_some(alert.message)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sampled,
+            [
+                "cache miss\nSampled 1/4",
+                "cache miss\nSampled 1/4, 3 matches skipped since the previous alert",
+                "cache miss\nSampled 1/4, 3 matches skipped since the previous alert",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_silenced_not_sampled() {
+        let config = config(
+            r#"
+            slack_webhook_url = ""
+            systemd_unit = ""
+            [[alerts]]
+            name = "cache"
+            pattern = "cache miss"
+            prefix = ""
+            sample_rate = 0.25
+            "#,
+        );
+        let processor = JournalProcessor::new(&config).unwrap();
+        let (tx, rx) = crate::channel::channel(&config, Arc::default());
+        let mut stats = ReadStats::default();
+        let entry = JournalEntry {
+            message: "cache miss".to_string(),
+            ..Default::default()
+        };
+        processor.process(entry.clone(), 0, &tx, &mut stats).await;
+        assert_eq!(rx.try_recv().unwrap().text(), "cache miss\nSampled 1/4");
+        // the silenced matches are not counted as skipped
+        processor.silences.silence("cache", Duration::from_secs(60));
+        for _ in 0..3 {
+            processor.process(entry.clone(), 0, &tx, &mut stats).await;
+        }
+        processor.silences.unsilence("cache");
+        for _ in 0..3 {
+            processor.process(entry.clone(), 0, &tx, &mut stats).await;
+            assert!(rx.try_recv().is_err());
+        }
+        processor.process(entry, 0, &tx, &mut stats).await;
+        assert_eq!(
+            rx.try_recv().unwrap().text(),
+            "cache miss\nSampled 1/4, 3 matches skipped since the previous alert"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_collapse_repeats() {
+        let config = config(
+            r#"
+            slack_webhook_url = ""
+            systemd_unit = ""
+            collapse_repeats_secs = 60
+            [[alerts]]
+            pattern = "error"
+            prefix = ""
+            "#,
+        );
+        let processor = JournalProcessor::new(&config).unwrap();
+        let (tx, rx) = crate::channel::channel(&config, Arc::default());
+        let mut stats = ReadStats::default();
+        let entry = |message: &str| JournalEntry {
+            message: message.to_string(),
+            ..Default::default()
+        };
+        for message in [
+            "error: disk full",
+            "error: disk full",
+            "error: disk full",
+            "done",
+        ] {
+            processor.read(entry(message), &tx, &mut stats).await;
+        }
+        let alert = rx.try_recv().unwrap();
+        assert_eq!(alert.text(), "error: disk full (repeated 3 times)");
+        // the last line is held until the run ends
+        assert!(rx.try_recv().is_err());
+        processor
+            .read(entry("error: db down"), &tx, &mut stats)
+            .await;
+        processor.flush(&tx, &mut stats).await;
+        assert_eq!(rx.try_recv().unwrap().text(), "error: db down");
+    }
+
+    #[tokio::test]
+    async fn test_start_with_lines() {
+        let config = config(
+            r#"
+            slack_webhook_url = ""
+            systemd_unit = ""
+            heartbeat_interval = 1
+            [[alerts]]
+            pattern = "error"
+            prefix = ""
+            [[alerts]]
+            pattern = '^\s*$'
+            prefix = ""
+            [[heartbeats]]
+            pattern = "health ok"
+            prefix = ""
+            tolerance = 2
+            "#,
+        );
+        let (lines_tx, lines_rx) = flume::unbounded();
+        let processor = JournalProcessor::new(&config).unwrap().with_lines(lines_rx);
+        let (tx, rx) = crate::channel::channel(&config, Arc::default());
+        // the notifier side, the next alert received
+        let next = || async {
+            tokio::time::timeout(Duration::from_secs(5), rx.recv_async())
+                .await
+                .unwrap()
+                .unwrap()
+        };
+        let test = async {
+            // blank lines are skipped before matching
+            lines_tx.send("  ".to_string()).unwrap();
+            lines_tx.send("error: disk full".to_string()).unwrap();
+            assert_eq!(next().await.text(), "error: disk full");
+            let missed = next().await;
+            assert_eq!(missed.kind, AlertKind::Heartbeat);
+            assert!(
+                missed
+                    .text()
+                    .contains("Heartbeat missed for pattern 'health ok'")
+            );
+            lines_tx.send("health ok".to_string()).unwrap();
+            let recovered = next().await;
+            assert_eq!(recovered.kind, AlertKind::Recovery);
+            assert!(recovered.text().contains("Heartbeat recovered"));
+            // the processor stops once the lines end
+            drop(lines_tx);
+        };
+        let (started, ()) = tokio::join!(processor.start(tx), test);
+        started.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_miss_key() {
+        let config = config(
+            r#"
+            slack_webhook_url = ""
+            systemd_unit = ""
+            heartbeat_interval = 1
+            [[heartbeats]]
+            pattern = "health ok"
+            prefix = ""
+            tolerance = 2
+            "#,
+        );
+        // the processors before and after a reload, each missing the heartbeat once
+        let mut missed = Vec::new();
+        for _ in 0..2 {
+            let (lines_tx, lines_rx) = flume::unbounded();
+            let processor = JournalProcessor::new(&config)
+                .unwrap()
+                .with_monitor("app")
+                .with_lines(lines_rx);
+            let (tx, rx) = crate::channel::channel(&config, Arc::default());
+            let next = || async {
+                tokio::time::timeout(Duration::from_secs(5), rx.recv_async())
+                    .await
+                    .unwrap()
+                    .unwrap()
+            };
+            let test = async {
+                let alert = next().await;
+                assert_eq!(alert.kind, AlertKind::Heartbeat);
+                missed.push(alert);
+                lines_tx.send("health ok".to_string()).unwrap();
+                let recovered = next().await;
+                assert_eq!(recovered.kind, AlertKind::Recovery);
+                // the recovery clears the miss, so that the next miss alerts again
+                assert_eq!(recovered.reset_keys, ["heartbeat:app:0"]);
+                drop(lines_tx);
+            };
+            let (started, ()) = tokio::join!(processor.start(tx), test);
+            started.unwrap();
+        }
+        // deduplicated on the heartbeat, not on the varying time since last seen
+        assert_eq!(missed[0].dedup_text(), "heartbeat:app:0");
+        assert_eq!(missed[0].dedup_text(), missed[1].dedup_text());
+        // paired with its recovery apart from the same pattern in other monitors
+        assert_eq!(
+            missed[0].thread_key.as_deref(),
+            Some("heartbeat:app:health ok")
+        );
+        assert_ne!(missed[0].message, missed[1].dedup_text());
+    }
+
+    #[tokio::test]
+    async fn test_journalctl_error() {
+        let stderr = collect_stderr(
+            "Hint: check the groups\nNo journal files were opened due to insufficient permissions.\n"
+                .as_bytes(),
+        )
+        .await;
+        assert_eq!(stderr.len(), 2);
+        let status = Command::new("false").status().await.unwrap();
+        let exited = |stderr: String| ProcessError::Exited { status, stderr }.to_string();
+        let error = exited(stderr.join("\n"));
+        assert!(error.starts_with("journalctl exited with exit status: 1: Hint"));
+        assert!(error.ends_with("in the 'systemd-journal' group"));
+        assert!(!exited(String::new()).contains("hint"));
+        // a missing permission is not worth restarting, a failed read is
+        let stderr = String::new();
+        assert!(ProcessError::Exited { status, stderr }.is_fatal());
+        let read = std::io::Error::from(std::io::ErrorKind::UnexpectedEof);
+        assert!(!ProcessError::Journal(read).is_fatal());
+        let missing = std::io::Error::from(std::io::ErrorKind::NotFound);
+        assert!(ProcessError::Spawn(missing).is_fatal());
+    }
+
+    #[test]
+    fn test_sustained() {
+        let checks = |misses: &[bool], consecutive: usize| {
+            let mut overdue = HashMap::new();
+            misses
+                .iter()
+                .map(|&missed| sustained(&mut overdue, 0, missed, consecutive))
+                .collect::<Vec<_>>()
+        };
+        // intermittent misses never add up
+        assert_eq!(
+            checks(&[true, true, false, true, false], 3),
+            [false, false, false, false, false]
+        );
+        // sustained ones alert on the third check, and keep on until recovered
+        assert_eq!(
+            checks(&[true, true, true, true, false, true], 3),
+            [false, false, true, true, false, false]
+        );
+        // the default alerts on the first check
+        assert_eq!(checks(&[true, false], 0), [true, false]);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let processor = JournalProcessor::new(&config(
+            r#"
+            slack_webhook_url = ""
+            systemd_unit = ""
+            [[alerts]]
+            name = "db"
+            pattern = "connection lost"
+            prefix = ""
+            resolve_pattern = "connection restored"
+            "#,
+        ))
+        .unwrap();
+        let entry = |message: &str| JournalEntry {
+            message: message.to_string(),
+            ..Default::default()
+        };
+        // nothing to resolve until the alert is open
+        assert!(processor.resolve(&entry("connection restored")).is_empty());
+        let (_, event) = processor.match_alert(&entry("connection lost")).unwrap();
+        processor.open(&event);
+        assert!(processor.resolve(&entry("still down")).is_empty());
+        let resolved = processor.resolve(&entry("connection restored"));
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].rule.as_deref(), Some("db"));
+        assert_eq!(resolved[0].kind, AlertKind::Recovery);
+        assert!(resolved[0].message.starts_with("✅ 'db' resolved after 0s"));
+        assert!(processor.resolve(&entry("connection restored")).is_empty());
+    }
+
+    #[test]
+    fn test_jittered() {
+        let interval = Duration::from_secs(30);
+        assert_eq!(jittered(interval, 0.0), interval);
+        for _ in 0..100 {
+            let jittered = jittered(interval, 0.2);
+            assert!(jittered >= Duration::from_secs(24) && jittered <= Duration::from_secs(36));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_line() {
+        let mut reader = BufReader::new(&b"ok\nbad \xff byte\r\nlast"[..]);
+        let mut buf = Vec::new();
+        let mut lines = Vec::new();
+        while let Some(line) = read_line(&mut reader, &mut buf).await.unwrap() {
+            lines.push(line);
         }
+        // the invalid byte is replaced and the following lines are still read
+        assert_eq!(lines, ["ok", "bad \u{fffd} byte", "last"]);
     }
 }
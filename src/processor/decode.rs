@@ -0,0 +1,151 @@
+use std::ops::Range;
+
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD_NO_PAD, URL_SAFE_NO_PAD};
+
+use super::matcher::{Captures, MatchLimits};
+use crate::config::{AlertRule, Encoding};
+use crate::error::MatcherError;
+
+/// Decodes a capture group of a rule, e.g. an encoded payload, and matches the decoded
+/// text with the rule's `decode_pattern`.
+pub struct Decoder {
+    encoding: Encoding,
+    group: String,
+    pattern: Option<regex::Regex>,
+}
+
+/// The capture group of a line, decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decoded {
+    // byte range of the group in the line
+    pub span: Range<usize>,
+    // the decoded group, or the group as is when it is not in the encoding
+    pub text: String,
+    pub failed: bool,
+    // named groups of the decode_pattern in the text
+    pub captures: Vec<(String, String)>,
+}
+
+impl Decoder {
+    /// The decoder of the rule, `None` without `decode`.
+    pub fn new(rule: &AlertRule, limits: MatchLimits) -> Result<Option<Self>, MatcherError> {
+        let Some(encoding) = rule.decode else {
+            return Ok(None);
+        };
+        let pattern = (!rule.decode_pattern.is_empty())
+            .then(|| {
+                regex::RegexBuilder::new(&rule.decode_pattern)
+                    .size_limit(limits.size_limit)
+                    .build()
+                    .map_err(|source| MatcherError::Decode {
+                        rule: rule.name.clone(),
+                        source,
+                    })
+            })
+            .transpose()?;
+        Ok(Some(Decoder {
+            encoding,
+            group: rule.decode_group.clone(),
+            pattern,
+        }))
+    }
+
+    /// Decodes the group in the captures, `None` when the group did not take part in
+    /// the match or when the decoded text does not match the pattern.
+    pub fn decode(&self, captures: &Captures) -> Option<Decoded> {
+        let (span, group) = captures.group(&self.group)?;
+        let (text, failed) = match decode(self.encoding, group) {
+            Some(bytes) => (String::from_utf8_lossy(&bytes).into_owned(), false),
+            None => (group.to_string(), true),
+        };
+        let captures = match &self.pattern {
+            None => Vec::new(),
+            Some(re) => {
+                let found = re.captures(&text)?;
+                re.capture_names()
+                    .flatten()
+                    .filter_map(|name| {
+                        Some((name.to_string(), found.name(name)?.as_str().to_string()))
+                    })
+                    .collect()
+            }
+        };
+        Some(Decoded {
+            span,
+            text,
+            failed,
+            captures,
+        })
+    }
+}
+
+/// Decodes the text, `None` when it is not in the encoding.
+fn decode(encoding: Encoding, text: &str) -> Option<Vec<u8>> {
+    match encoding {
+        Encoding::Base64 => {
+            let text = text.trim_end_matches('=');
+            STANDARD_NO_PAD
+                .decode(text)
+                .or_else(|_| URL_SAFE_NO_PAD.decode(text))
+                .ok()
+        }
+        Encoding::Hex => decode_hex(text),
+    }
+}
+
+/// Decodes hexadecimal digits, two per byte.
+pub fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::matcher::Matcher;
+
+    #[test]
+    fn test_decode() {
+        let rule = AlertRule {
+            name: "payload".to_string(),
+            pattern: r"payload=(?P<data>\S+)".to_string(),
+            decode: Some(Encoding::Base64),
+            decode_group: "data".to_string(),
+            decode_pattern: r"uid=(?P<uid>\d+)".to_string(),
+            ..Default::default()
+        };
+        let limits = MatchLimits::default();
+        let matcher = Matcher::new(std::slice::from_ref(&rule), limits).unwrap();
+        let decoder = Decoder::new(&rule, limits).unwrap().unwrap();
+        let decoded = |line: &str| decoder.decode(&matcher.captures(0, line).unwrap());
+
+        // "login uid=0 from 10.0.0.1"
+        let line = "audit payload=bG9naW4gdWlkPTAgZnJvbSAxMC4wLjAuMQ== ok";
+        assert_eq!(
+            decoded(line),
+            Some(Decoded {
+                span: 14..50,
+                text: "login uid=0 from 10.0.0.1".to_string(),
+                failed: false,
+                captures: vec![("uid".to_string(), "0".to_string())],
+            })
+        );
+        // "logout" does not match the decode_pattern
+        assert_eq!(decoded("audit payload=bG9nb3V0"), None);
+        // a group that is not base64 is matched as is
+        let decoded = decoded("audit payload=uid=42").unwrap();
+        assert!(decoded.failed);
+        assert_eq!(decoded.text, "uid=42");
+
+        assert_eq!(decode(Encoding::Hex, "6f4B"), Some(b"oK".to_vec()));
+        assert_eq!(decode(Encoding::Hex, "6f4"), None);
+        assert_eq!(decode(Encoding::Hex, "+f"), None);
+        assert_eq!(decode(Encoding::Base64, "-_8"), Some(vec![0xfb, 0xff]));
+    }
+}
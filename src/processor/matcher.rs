@@ -1,36 +1,278 @@
-use anyhow::Result;
+use std::cmp::Reverse;
+use std::ops::Range;
+
+use crate::config::{AlertRule, Config, HeartbeatRule, RegexEngine};
+use crate::error::MatcherError;
+
+// upper bound of the compiled size of a pattern, well above any sensible rule
+const DEFAULT_REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+/// Bounds on the cost of matching, against pathological patterns or lines.
+#[derive(Debug, Clone, Copy)]
+pub struct MatchLimits {
+    // maximum compiled size of each pattern, in bytes
+    pub size_limit: usize,
+    // lines are truncated to this length before matching, 0 to disable
+    pub max_line_length: usize,
+}
+
+impl Default for MatchLimits {
+    fn default() -> Self {
+        MatchLimits {
+            size_limit: DEFAULT_REGEX_SIZE_LIMIT,
+            max_line_length: 0,
+        }
+    }
+}
+
+impl MatchLimits {
+    /// The limits set in the config, unset values keep the defaults.
+    pub fn from_config(config: &Config) -> Self {
+        MatchLimits {
+            size_limit: match config.regex_size_limit {
+                0 => DEFAULT_REGEX_SIZE_LIMIT,
+                size_limit => size_limit,
+            },
+            max_line_length: config.max_line_length,
+        }
+    }
+}
+
+/// A rule that can be compiled into a [`Matcher`].
+pub trait MatchRule {
+    /// The regex pattern of the rule.
+    fn pattern(&self) -> &str;
+
+    /// The priority of the rule, higher priorities are matched first.
+    fn priority(&self) -> i32 {
+        0
+    }
+
+    /// The name of the rule used in errors, if it has one.
+    fn name(&self) -> Option<&str> {
+        None
+    }
+
+    /// The engine the pattern is compiled with.
+    fn engine(&self) -> RegexEngine {
+        RegexEngine::Regex
+    }
+}
+
+impl MatchRule for String {
+    fn pattern(&self) -> &str {
+        self
+    }
+}
+
+impl MatchRule for AlertRule {
+    fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    fn name(&self) -> Option<&str> {
+        Some(&self.name)
+    }
+
+    fn engine(&self) -> RegexEngine {
+        self.engine
+    }
+}
+
+impl MatchRule for HeartbeatRule {
+    fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    fn engine(&self) -> RegexEngine {
+        self.engine
+    }
+}
+
+/// A rule pattern compiled with the engine of the rule.
+pub enum Pattern {
+    Regex(regex::Regex),
+    Fancy(fancy_regex::Regex),
+}
+
+impl Pattern {
+    /// Compiles the pattern of the rule at index `i`, within the size limit.
+    pub fn new<R: MatchRule>(
+        rule: &R,
+        i: usize,
+        limits: MatchLimits,
+    ) -> Result<Self, MatcherError> {
+        let pattern = rule.pattern();
+        let name = || rule.name().map_or(format!("#{i}"), |n| format!("'{n}'"));
+        match rule.engine() {
+            RegexEngine::Regex => regex::RegexBuilder::new(pattern)
+                .size_limit(limits.size_limit)
+                .dfa_size_limit(limits.size_limit)
+                .build()
+                .map(Pattern::Regex)
+                .map_err(|source| MatcherError::Pattern {
+                    pattern: pattern.to_string(),
+                    rule: name(),
+                    source,
+                }),
+            RegexEngine::Fancy => fancy_regex::RegexBuilder::new(pattern)
+                .delegate_size_limit(limits.size_limit)
+                .delegate_dfa_size_limit(limits.size_limit)
+                .build()
+                .map(Pattern::Fancy)
+                .map_err(|source| MatcherError::FancyPattern {
+                    pattern: pattern.to_string(),
+                    rule: name(),
+                    source: Box::new(source),
+                }),
+        }
+    }
+
+    /// The byte range of the first match in the line. A fancy pattern exceeding its
+    /// backtracking limit on the line does not match.
+    pub fn find(&self, line: &str) -> Option<Range<usize>> {
+        match self {
+            Pattern::Regex(re) => re.find(line).map(|m| m.range()),
+            Pattern::Fancy(re) => re.find(line).ok().flatten().map(|m| m.range()),
+        }
+    }
+
+    pub fn is_match(&self, line: &str) -> bool {
+        self.find(line).is_some()
+    }
+
+    /// The capture groups of the pattern in the line, if it matches.
+    pub fn captures<'l>(&self, line: &'l str) -> Option<Captures<'l>> {
+        match self {
+            Pattern::Regex(re) => re.captures(line).map(Captures::Regex),
+            Pattern::Fancy(re) => re.captures(line).ok().flatten().map(Captures::Fancy),
+        }
+    }
+}
+
+/// The capture groups of a match, from either engine.
+pub enum Captures<'l> {
+    Regex(regex::Captures<'l>),
+    Fancy(fancy_regex::Captures<'l, str>),
+}
+
+impl<'l> Captures<'l> {
+    /// The byte range and the text of the group, by number or by name.
+    pub fn group(&self, group: &str) -> Option<(Range<usize>, &'l str)> {
+        match (self, group.parse::<usize>()) {
+            (Captures::Regex(captures), Ok(i)) => captures.get(i).map(|m| (m.range(), m.as_str())),
+            (Captures::Regex(captures), Err(_)) => {
+                captures.name(group).map(|m| (m.range(), m.as_str()))
+            }
+            (Captures::Fancy(captures), Ok(i)) => captures.get(i).map(|m| (m.range(), m.as_str())),
+            (Captures::Fancy(captures), Err(_)) => {
+                captures.name(group).map(|m| (m.range(), m.as_str()))
+            }
+        }
+    }
+}
 
 /// A struct that holds compiled regex patterns and can find matches in log lines.
 pub struct Matcher {
-    // A vector of tuples containing the index of the pattern and the compiled regex.
-    patterns: Vec<(usize, regex::Regex)>,
+    // A vector of tuples containing the index of the pattern and the compiled regex,
+    // sorted by priority (descending) and then by index.
+    patterns: Vec<(usize, Pattern)>,
+    // lines are truncated to this length before matching, 0 to disable
+    max_line_length: usize,
 }
 
 impl Matcher {
-    pub fn new(patterns: &[String]) -> Result<Self> {
+    pub fn new<R: MatchRule>(rules: &[R], limits: MatchLimits) -> Result<Self, MatcherError> {
         // Compile the regex patterns and store them with their indices.
-        let patterns = patterns
+        let mut patterns = rules
             .iter()
             .enumerate()
-            .map(|(i, rule)| {
-                let re = regex::Regex::new(rule)
-                    .map_err(|e| anyhow::anyhow!("Invalid regex pattern '{}': {}", rule, e))?;
-                Ok((i, re))
-            })
-            .collect::<Result<Vec<(usize, regex::Regex)>>>()?;
-        Ok(Matcher { patterns })
+            .map(|(i, rule)| Ok((rule.priority(), i, Pattern::new(rule, i, limits)?)))
+            .collect::<Result<Vec<(i32, usize, Pattern)>, MatcherError>>()?;
+        // the sort is stable, so rules with the same priority keep their config order
+        patterns.sort_by_key(|(priority, _, _)| Reverse(*priority));
+        let patterns = patterns.into_iter().map(|(_, i, re)| (i, re)).collect();
+        Ok(Matcher {
+            patterns,
+            max_line_length: limits.max_line_length,
+        })
     }
 
-    /// Finds the first matching pattern for the given log line.
+    /// Finds the matching pattern with the highest priority for the given log line,
+    /// ties are resolved by the position of the rule.
     pub fn find_match(&self, line: &str) -> Option<(usize, String)> {
-        // Check each pattern to see if it matches the given line.
-        for (i, re) in &self.patterns {
-            if re.is_match(line) {
-                return Some((*i, line.into()));
-            }
-        }
-        None
+        self.find_match_span(line).map(|(i, line, _)| (i, line))
+    }
+
+    /// Like [`Matcher::find_match`], also returning the byte range of the line that matched.
+    /// Lines longer than the limit are truncated to it, on a character boundary.
+    pub fn find_match_span(&self, line: &str) -> Option<(usize, String, Range<usize>)> {
+        self.find_match_span_by(line, |_| true)
+    }
+
+    /// Like [`Matcher::find_match_span`], skipping the rules whose index is not
+    /// accepted, e.g. when the entry lacks what the rule needs besides its pattern.
+    pub fn find_match_span_by(
+        &self,
+        line: &str,
+        accept: impl Fn(usize) -> bool,
+    ) -> Option<(usize, String, Range<usize>)> {
+        let line = truncate(line, self.max_line_length);
+        self.spans(line, accept)
+            .next()
+            .map(|(i, span)| (i, line.into(), span))
+    }
+
+    /// Like [`Matcher::find_match_span_by`], returning all the matching patterns in
+    /// priority order instead of the first one.
+    pub fn find_match_spans_by(
+        &self,
+        line: &str,
+        accept: impl Fn(usize) -> bool,
+    ) -> Vec<(usize, String, Range<usize>)> {
+        let line = truncate(line, self.max_line_length);
+        self.spans(line, accept)
+            .map(|(i, span)| (i, line.into(), span))
+            .collect()
+    }
+
+    /// The accepted patterns matching the line, with the byte range they matched.
+    fn spans<'a>(
+        &'a self,
+        line: &'a str,
+        accept: impl Fn(usize) -> bool + 'a,
+    ) -> impl Iterator<Item = (usize, Range<usize>)> + 'a {
+        self.patterns
+            .iter()
+            .filter(move |(i, _)| accept(*i))
+            .filter_map(|(i, re)| re.find(line).map(|span| (*i, span)))
+    }
+
+    /// The capture groups of the pattern of the rule in the line, if it matches.
+    pub fn captures<'l>(&self, rule: usize, line: &'l str) -> Option<Captures<'l>> {
+        let (_, re) = self.patterns.iter().find(|(i, _)| *i == rule)?;
+        re.captures(line)
+    }
+}
+
+/// Truncates the line to at most `max` bytes, unless `max` is 0.
+fn truncate(line: &str, max: usize) -> &str {
+    if max == 0 || line.len() <= max {
+        return line;
+    }
+    let mut end = max;
+    while !line.is_char_boundary(end) {
+        end -= 1;
     }
+    &line[..end]
 }
 
 #[cfg(test)]
@@ -41,8 +283,11 @@ mod tests {
     fn test_matcher() {
         let rules = ["error", "warn", "(?i)quorum not reached"];
 
-        let matcher =
-            Matcher::new(&rules.iter().map(|s| s.to_string()).collect::<Vec<String>>()).unwrap();
+        let matcher = Matcher::new(
+            &rules.iter().map(|s| s.to_string()).collect::<Vec<String>>(),
+            MatchLimits::default(),
+        )
+        .unwrap();
 
         let tests = vec![
             (
@@ -64,4 +309,89 @@ mod tests {
             assert_eq!(matcher.find_match(input), expected);
         }
     }
+
+    #[test]
+    fn test_matcher_priority() {
+        let rule = |pattern: &str, priority: i32| AlertRule {
+            pattern: pattern.to_string(),
+            priority,
+            ..Default::default()
+        };
+        let rules = [rule("error", 0), rule("disk", 10), rule("full", 10)];
+        let matcher = Matcher::new(&rules, MatchLimits::default()).unwrap();
+
+        // the higher priority rule wins over the earlier one
+        assert_eq!(matcher.find_match("error: disk full").unwrap().0, 1);
+        // same priority resolves to the earlier rule
+        assert_eq!(matcher.find_match("error: full").unwrap().0, 2);
+        assert_eq!(matcher.find_match("error").unwrap().0, 0);
+        // all the matches, in the same order
+        let all = |line: &str| {
+            matcher
+                .find_match_spans_by(line, |_| true)
+                .into_iter()
+                .map(|(i, _, _)| i)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(all("error: disk full"), [1, 2, 0]);
+        assert!(all("all good").is_empty());
+    }
+
+    #[test]
+    fn test_matcher_span() {
+        let matcher =
+            Matcher::new(&["disk (full|low)".to_string()], MatchLimits::default()).unwrap();
+        let (i, line, span) = matcher.find_match_span("error: disk full on /").unwrap();
+        assert_eq!(i, 0);
+        assert_eq!(&line[span], "disk full");
+        assert_eq!(matcher.find_match_span("all good"), None);
+    }
+
+    #[test]
+    fn test_matcher_fancy() {
+        let rule = |engine: RegexEngine| AlertRule {
+            name: "repeated".to_string(),
+            pattern: r"(?<word>\w+) \k<word>(?! ok)".to_string(),
+            engine,
+            ..Default::default()
+        };
+        // backreferences and lookaround need the fancy engine
+        let err = Matcher::new(&[rule(RegexEngine::Regex)], MatchLimits::default())
+            .err()
+            .unwrap();
+        assert!(matches!(err, MatcherError::Pattern { .. }));
+        let matcher = Matcher::new(&[rule(RegexEngine::Fancy)], MatchLimits::default()).unwrap();
+        let (i, line, span) = matcher.find_match_span("error: disk disk full").unwrap();
+        assert_eq!((i, &line[span]), (0, "disk disk"));
+        assert_eq!(matcher.find_match("disk disk ok"), None);
+        let captures = matcher.captures(0, "disk disk full").unwrap();
+        assert_eq!(captures.group("word"), Some((0..4, "disk")));
+        assert_eq!(captures.group("0"), Some((0..9, "disk disk")));
+    }
+
+    #[test]
+    fn test_matcher_limits() {
+        // a pattern compiling past the size limit is rejected, naming the rule
+        let rule = AlertRule {
+            name: "huge".to_string(),
+            pattern: r"\w{1000}".to_string(),
+            ..Default::default()
+        };
+        let limits = MatchLimits {
+            size_limit: 10_000,
+            max_line_length: 8,
+        };
+        let err = Matcher::new(&[rule], limits).err().unwrap();
+        assert!(matches!(err, MatcherError::Pattern { .. }));
+        assert!(err.to_string().contains("rule 'huge'"), "{err}");
+
+        // only the start of long lines is matched
+        let matcher = Matcher::new(&["disk".to_string(), "full".to_string()], limits).unwrap();
+        assert_eq!(
+            matcher.find_match("disk is full"),
+            Some((0, "disk is ".to_string()))
+        );
+        assert_eq!(matcher.find_match("the big disk is full"), None);
+        assert_eq!(truncate("ééé", 3), "é");
+    }
 }
@@ -1,4 +1,16 @@
+mod body;
+mod cadence;
 mod core;
+mod decode;
 mod matcher;
+mod native;
+mod parse;
+mod template;
+mod transform;
+mod warmup;
 
 pub use core::*;
+pub use decode::decode_hex;
+pub use matcher::{MatchLimits, Pattern};
+pub use native::check_native;
+pub use warmup::Warmup;
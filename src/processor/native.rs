@@ -0,0 +1,179 @@
+use crate::config::{Config, JournalSource};
+use crate::error::{ConfigError, ProcessError};
+
+use super::parse::JournalEntry;
+
+/// Checks that the journal can be read natively with the config: the binary must
+/// be built with the `native-journal` feature, boots can only be selected by id and
+/// the journal directories are left to journalctl.
+pub fn check_native(config: &Config) -> Result<(), ConfigError> {
+    if config.journal_source != JournalSource::Native {
+        return Ok(());
+    }
+    if !cfg!(feature = "native-journal") {
+        return Err(ConfigError::Invalid(
+            "journal_source = \"native\" needs a build with the native-journal feature".to_string(),
+        ));
+    }
+    if !config.journal_directory.is_empty() {
+        return Err(ConfigError::Invalid(
+            "journal_directory is read with journalctl, it needs journal_source = \"journalctl\""
+                .to_string(),
+        ));
+    }
+    if let Some(boot) = &config.boot
+        && !matches!(boot.as_str(), "" | "0")
+        && !(boot.len() == 32 && boot.chars().all(|c| c.is_ascii_hexdigit()))
+    {
+        return Err(ConfigError::Invalid(format!(
+            "boot '{boot}' is not supported with the native journal, use the current boot or a boot id"
+        )));
+    }
+    Ok(())
+}
+
+/// Follows the journal with libsystemd, sending the entries written from now on
+/// until the receiver is gone or reading fails.
+#[cfg(feature = "native-journal")]
+pub fn follow(config: &Config, tx: &flume::Sender<JournalEntry>) -> Result<(), ProcessError> {
+    imp::follow(config, tx).map_err(ProcessError::Journal)
+}
+
+#[cfg(not(feature = "native-journal"))]
+pub fn follow(_config: &Config, _tx: &flume::Sender<JournalEntry>) -> Result<(), ProcessError> {
+    Err(ProcessError::Journal(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "built without the native-journal feature",
+    )))
+}
+
+#[cfg(feature = "native-journal")]
+mod imp {
+    use std::io;
+    use std::time::Duration;
+
+    use log::info;
+    use systemd::journal::{Journal, JournalRecord, OpenOptions};
+
+    use super::JournalEntry;
+    use crate::config::Config;
+    use crate::processor::parse::format_timestamp;
+
+    // how often a follower waiting for entries checks that its receiver is still there
+    const WAIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+    pub fn follow(config: &Config, tx: &flume::Sender<JournalEntry>) -> io::Result<()> {
+        let mut journal = OpenOptions::default()
+            .system(true)
+            .local_only(true)
+            .open()?;
+        add_matches(&mut journal, config)?;
+        let prefilter = prefilter(&config.prefilter)?;
+
+        // only the entries written from now on, as journalctl --lines 0
+        journal.seek_tail()?;
+        journal.previous()?;
+        loop {
+            while let Some(fields) = journal.next_entry()? {
+                let entry = entry(fields, journal.timestamp_usec().ok());
+                if prefilter
+                    .as_ref()
+                    .is_some_and(|re| !re.is_match(&entry.message))
+                {
+                    continue;
+                }
+                if tx.send(entry).is_err() {
+                    return Ok(());
+                }
+            }
+            if tx.is_disconnected() {
+                return Ok(());
+            }
+            journal.wait(Some(WAIT_TIMEOUT))?;
+        }
+    }
+
+    /// Filters the journal as journalctl does with the unit, identifiers and boot.
+    /// Matches on different fields must all hold, those on the same field are
+    /// alternatives.
+    fn add_matches(journal: &mut Journal, config: &Config) -> io::Result<()> {
+        if !config.systemd_unit.is_empty() {
+            let unit = unit_name(&config.systemd_unit);
+            info!("Filtering logs for systemd unit: {}", unit);
+            journal.match_add("_SYSTEMD_UNIT", unit)?;
+        }
+        for identifier in &config.syslog_identifier {
+            info!("Filtering logs for syslog identifier: {}", identifier);
+            journal.match_add("SYSLOG_IDENTIFIER", identifier.as_str())?;
+        }
+        if let Some(boot) = &config.boot {
+            let boot = match boot.as_str() {
+                "" | "0" => current_boot_id()?,
+                boot => boot.to_string(),
+            };
+            info!("Scoping logs to boot: {}", boot);
+            journal.match_add("_BOOT_ID", boot)?;
+        }
+        Ok(())
+    }
+
+    /// Completes a unit name without a type as a service, as journalctl does.
+    fn unit_name(unit: &str) -> String {
+        if unit.contains('.') {
+            unit.to_string()
+        } else {
+            format!("{unit}.service")
+        }
+    }
+
+    fn current_boot_id() -> io::Result<String> {
+        let id = std::fs::read_to_string("/proc/sys/kernel/random/boot_id")?;
+        Ok(id.trim().replace('-', ""))
+    }
+
+    /// Compiles the prefilter, case insensitive unless it has uppercase characters,
+    /// as journalctl --grep.
+    fn prefilter(pattern: &str) -> io::Result<Option<regex::Regex>> {
+        if pattern.is_empty() {
+            return Ok(None);
+        }
+        regex::RegexBuilder::new(pattern)
+            .case_insensitive(!pattern.chars().any(char::is_uppercase))
+            .build()
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+
+    fn entry(fields: JournalRecord, realtime: Option<u64>) -> JournalEntry {
+        JournalEntry {
+            message: fields.get("MESSAGE").cloned().unwrap_or_default(),
+            timestamp: realtime.map(format_timestamp),
+            priority: fields.get("PRIORITY").and_then(|p| p.parse().ok()),
+            unit: fields.get("_SYSTEMD_UNIT").cloned(),
+            fields: fields.into_iter().collect(),
+            ..Default::default()
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_entry() {
+            let fields = JournalRecord::from([
+                ("MESSAGE".to_string(), "disk full".to_string()),
+                ("PRIORITY".to_string(), "3".to_string()),
+                ("_SYSTEMD_UNIT".to_string(), "app.service".to_string()),
+            ]);
+            let entry = entry(fields, Some(0));
+            assert_eq!(entry.message, "disk full");
+            assert_eq!(entry.priority, Some(3));
+            assert_eq!(entry.unit.as_deref(), Some("app.service"));
+            assert_eq!(unit_name("app"), "app.service");
+            assert_eq!(unit_name("app.timer"), "app.timer");
+            assert!(prefilter("error").unwrap().unwrap().is_match("ERROR"));
+            assert!(!prefilter("Error").unwrap().unwrap().is_match("ERROR"));
+        }
+    }
+}
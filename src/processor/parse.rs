@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+
+use crate::config::Config;
+
+/// The journalctl output format, depending on what the config needs from the entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// The message only.
+    Cat,
+    /// The message prefixed with the timestamp, host and identifier.
+    ShortIso,
+    /// One JSON object per entry, with all the fields.
+    Json,
+}
+
+/// A journal entry, with the fields the processor uses.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub message: String,
+    pub timestamp: Option<String>,
+    // syslog priority, 0 (emerg) to 7 (debug)
+    pub priority: Option<u8>,
+    // the systemd unit that logged the entry
+    pub unit: Option<String>,
+    // all the text fields of the entry, when read as JSON
+    pub fields: HashMap<String, String>,
+    // index of the journal directory the entry was read from, with several
+    pub directory: usize,
+}
+
+impl OutputFormat {
+    pub fn from_config(config: &Config) -> Self {
+        let templates = config.alerts.iter().any(|rule| {
+            !rule.template.is_empty()
+                || !rule.require_fields.is_empty()
+                || !rule.match_fields.is_empty()
+                || rule.min_hosts.is_some()
+        });
+        let filters = !config.exclude_units.is_empty()
+            || !config.transports.is_empty()
+            || !config.exclude_transports.is_empty();
+        if config.severity_from_priority || filters || templates {
+            OutputFormat::Json
+        } else if config.include_timestamp || config.quiet_start {
+            OutputFormat::ShortIso
+        } else {
+            OutputFormat::Cat
+        }
+    }
+
+    /// The journalctl argument selecting the format.
+    pub fn arg(self) -> &'static str {
+        match self {
+            OutputFormat::Cat => "--output=cat",
+            OutputFormat::ShortIso => "--output=short-iso",
+            OutputFormat::Json => "--output=json",
+        }
+    }
+
+    /// Parses a line of journalctl output, lines not in the expected format are
+    /// taken whole as the message.
+    pub fn parse(self, line: String) -> JournalEntry {
+        match self {
+            OutputFormat::Cat => None,
+            OutputFormat::ShortIso => {
+                split_short_iso(&line).map(|(timestamp, message)| JournalEntry {
+                    message: message.to_string(),
+                    timestamp: Some(timestamp.to_string()),
+                    ..Default::default()
+                })
+            }
+            OutputFormat::Json => parse_json(&line),
+        }
+        .unwrap_or(JournalEntry {
+            message: line,
+            ..Default::default()
+        })
+    }
+}
+
+/// Splits a `--output=short-iso` journal line into its timestamp and message body,
+/// e.g. `2025-01-01T10:00:00+0000 host unit[42]: message`.
+fn split_short_iso(line: &str) -> Option<(&str, &str)> {
+    let (timestamp, rest) = line.split_once(' ')?;
+    // the timestamp starts with the year and contains the date/time separator
+    if !timestamp.starts_with(|c: char| c.is_ascii_digit()) || !timestamp.contains('T') {
+        return None;
+    }
+    // skip the hostname, the message follows the `identifier[pid]: ` part
+    let (_host, rest) = rest.split_once(' ')?;
+    let (_identifier, message) = rest.split_once(": ")?;
+    Some((timestamp, message))
+}
+
+/// Parses a `--output=json` journal line.
+fn parse_json(line: &str) -> Option<JournalEntry> {
+    let entry: serde_json::Value = serde_json::from_str(line).ok()?;
+    // journald exports messages that are not valid UTF-8 as an array of bytes
+    let message = match &entry["MESSAGE"] {
+        serde_json::Value::String(message) => message.clone(),
+        serde_json::Value::Array(bytes) => {
+            let bytes = bytes
+                .iter()
+                .filter_map(|b| b.as_u64().and_then(|b| u8::try_from(b).ok()))
+                .collect::<Vec<_>>();
+            String::from_utf8_lossy(&bytes).into_owned()
+        }
+        _ => String::new(),
+    };
+    let timestamp = entry["__REALTIME_TIMESTAMP"]
+        .as_str()
+        .and_then(|t| t.parse().ok())
+        .map(format_timestamp);
+    let priority = entry["PRIORITY"].as_str().and_then(|p| p.parse().ok());
+    let unit = entry["_SYSTEMD_UNIT"].as_str().map(String::from);
+    let fields = match entry {
+        serde_json::Value::Object(fields) => fields
+            .into_iter()
+            .filter_map(|(name, value)| match value {
+                serde_json::Value::String(value) => Some((name, value)),
+                _ => None,
+            })
+            .collect(),
+        _ => HashMap::new(),
+    };
+    Some(JournalEntry {
+        message,
+        timestamp,
+        priority,
+        unit,
+        fields,
+        ..Default::default()
+    })
+}
+
+/// Parses the timestamp of an entry, as written by `--output=short-iso` or
+/// [`format_timestamp`].
+pub(super) fn parse_timestamp(timestamp: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_str(timestamp, "%Y-%m-%dT%H:%M:%S%#z")
+        .or_else(|_| DateTime::parse_from_rfc3339(timestamp))
+        .ok()
+        .map(|timestamp| timestamp.to_utc())
+}
+
+/// Formats microseconds since the epoch as an UTC ISO 8601 timestamp.
+pub(super) fn format_timestamp(micros: u64) -> String {
+    let secs = micros / 1_000_000;
+    let (days, time) = ((secs / 86400) as i64, secs % 86400);
+    // civil date from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_short_iso() {
+        assert_eq!(
+            split_short_iso("2025-01-01T10:00:00+0000 web-1 app[42]: error: disk full"),
+            Some(("2025-01-01T10:00:00+0000", "error: disk full"))
+        );
+        assert_eq!(
+            split_short_iso("2025-01-01T10:00:00+0000 web-1 kernel: Out of memory"),
+            Some(("2025-01-01T10:00:00+0000", "Out of memory"))
+        );
+        // the timestamp prefix never reaches the matchers, the body is kept intact
+        assert_eq!(
+            split_short_iso("2025-01-01T10:00:00+0000 web-1 app[42]: "),
+            Some(("2025-01-01T10:00:00+0000", ""))
+        );
+        assert_eq!(split_short_iso("-- Boot 0123456789abcdef --"), None);
+        assert_eq!(
+            OutputFormat::ShortIso.parse("plain line".to_string()),
+            JournalEntry {
+                message: "plain line".to_string(),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_json() {
+        let line = r#"{"MESSAGE":"error: disk full","PRIORITY":"3","__REALTIME_TIMESTAMP":"1735725600123456","_SYSTEMD_UNIT":"app.service"}"#;
+        let entry = OutputFormat::Json.parse(line.to_string());
+        assert_eq!(
+            entry,
+            JournalEntry {
+                message: "error: disk full".to_string(),
+                timestamp: Some("2025-01-01T10:00:00Z".to_string()),
+                priority: Some(3),
+                unit: Some("app.service".to_string()),
+                fields: entry.fields.clone(),
+                directory: 0,
+            }
+        );
+        assert_eq!(entry.fields["_SYSTEMD_UNIT"], "app.service");
+        // non UTF-8 messages are exported as bytes
+        let entry = OutputFormat::Json.parse(r#"{"MESSAGE":[104,105,255]}"#.to_string());
+        assert_eq!(entry.message, "hi\u{fffd}");
+        assert_eq!(entry.priority, None);
+        assert_eq!(
+            format_timestamp(951_782_400_000_000),
+            "2000-02-29T00:00:00Z"
+        );
+        let parsed = |timestamp| parse_timestamp(timestamp).map(|t| t.timestamp());
+        assert_eq!(parsed("2000-02-29T00:00:00Z"), Some(951_782_400));
+        assert_eq!(parsed("2000-02-29T01:00:00+0100"), Some(951_782_400));
+        assert_eq!(parsed("2000-02-29T01:00:00+01:00"), Some(951_782_400));
+        assert_eq!(parsed("yesterday"), None);
+    }
+}
@@ -0,0 +1,97 @@
+use log::warn;
+
+use crate::error::MatcherError;
+
+/// A piece of a message template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    // a capture group name or number, or a journal field
+    Placeholder(String),
+}
+
+/// A message template such as `{_HOSTNAME}: {MESSAGE}`, `{{` and `}}` are literal
+/// braces.
+#[derive(Debug, Clone)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    pub fn new(rule: &str, template: &str) -> Result<Self, MatcherError> {
+        let invalid = |reason: &str| MatcherError::Template {
+            rule: rule.to_string(),
+            reason: reason.to_string(),
+        };
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.next_if_eq(&'{').is_some() => text.push('{'),
+                '}' if chars.next_if_eq(&'}').is_some() => text.push('}'),
+                '{' => {
+                    let mut name = String::new();
+                    let closed = loop {
+                        match chars.next() {
+                            Some('}') => break true,
+                            Some(c) => name.push(c),
+                            None => break false,
+                        }
+                    };
+                    if !closed {
+                        return Err(invalid("unclosed '{', use '{{' for a literal brace"));
+                    }
+                    if name.is_empty() || name.contains('{') {
+                        return Err(invalid("placeholders must be a name between braces"));
+                    }
+                    parts.push(Part::Text(std::mem::take(&mut text)));
+                    parts.push(Part::Placeholder(name));
+                }
+                '}' => return Err(invalid("unmatched '}', use '}}' for a literal brace")),
+                c => text.push(c),
+            }
+        }
+        parts.push(Part::Text(text));
+        parts.retain(|part| part != &Part::Text(String::new()));
+        Ok(Template { parts })
+    }
+
+    /// Renders the template with the values of the placeholders, unknown ones are
+    /// rendered empty with a warning.
+    pub fn render(&self, rule: &str, value: impl Fn(&str) -> Option<String>) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Placeholder(name) => value(name).unwrap_or_else(|| {
+                    warn!(
+                        "Unknown placeholder '{}' in the template of rule '{}'",
+                        name, rule
+                    );
+                    String::new()
+                }),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_template() {
+        let template = Template::new("disk", "{_HOSTNAME}: {MESSAGE} {{unit={unit}}}").unwrap();
+        let value = |name: &str| match name {
+            "_HOSTNAME" => Some("web-1".to_string()),
+            "MESSAGE" => Some("disk full".to_string()),
+            _ => None,
+        };
+        assert_eq!(template.render("disk", value), "web-1: disk full {unit=}");
+
+        assert!(Template::new("disk", "{MESSAGE").is_err());
+        assert!(Template::new("disk", "MESSAGE}").is_err());
+        assert!(Template::new("disk", "{}").is_err());
+    }
+}
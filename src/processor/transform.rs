@@ -0,0 +1,59 @@
+use crate::config::TransformRule;
+use crate::error::MatcherError;
+
+/// A list of compiled regex replacements applied to matched messages.
+pub struct Transformer {
+    // A vector of tuples containing the compiled regex and its replacement template.
+    rules: Vec<(regex::Regex, String)>,
+}
+
+impl Transformer {
+    pub fn new(rules: &[TransformRule]) -> Result<Self, MatcherError> {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                let re =
+                    regex::Regex::new(&rule.find).map_err(|source| MatcherError::Transform {
+                        pattern: rule.find.clone(),
+                        source,
+                    })?;
+                Ok((re, rule.replace.clone()))
+            })
+            .collect::<Result<Vec<(regex::Regex, String)>, MatcherError>>()?;
+        Ok(Transformer { rules })
+    }
+
+    /// Applies all the replacements in order, each one sees the output of the previous.
+    pub fn apply(&self, message: &str) -> String {
+        self.rules
+            .iter()
+            .fold(message.to_string(), |message, (re, replace)| {
+                re.replace_all(&message, replace.as_str()).into_owned()
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transformer() {
+        let rule = |find: &str, replace: &str| TransformRule {
+            find: find.to_string(),
+            replace: replace.to_string(),
+        };
+        let transformer = Transformer::new(&[
+            rule(r"password=\S+", "password=***"),
+            rule(r"/(?:[\w.-]+/)+([\w.-]+)", "…/$1"),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            transformer.apply("login failed password=hunter2 in /opt/app/lib/auth.py"),
+            "login failed password=*** in …/auth.py"
+        );
+        assert_eq!(transformer.apply("nothing to do"), "nothing to do");
+        assert!(Transformer::new(&[rule("(", "")]).is_err());
+    }
+}
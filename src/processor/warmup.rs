@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::alert::Alert;
+use crate::config::Severity;
+use crate::control::format_duration;
+
+/// The alerts held back right after startup, sent as a single summary once the
+/// warmup ends. Shared by the processors, so that a reload does not start it over.
+#[derive(Default)]
+pub struct Warmup {
+    // end of the warmup, disabled when none
+    until: Option<Instant>,
+    duration: Duration,
+    held: Mutex<Held>,
+}
+
+#[derive(Default)]
+struct Held {
+    // Map of rule name to the number of its alerts held back
+    counts: HashMap<String, usize>,
+    critical: bool,
+}
+
+impl Warmup {
+    pub fn new(duration: Duration) -> Self {
+        Warmup {
+            until: (!duration.is_zero()).then(|| Instant::now() + duration),
+            duration,
+            held: Mutex::default(),
+        }
+    }
+
+    /// The end of the warmup, none when disabled.
+    pub fn until(&self) -> Option<Instant> {
+        self.until
+    }
+
+    /// Counts the alert instead of sending it if the warmup is ongoing, returns
+    /// whether it was held back.
+    pub fn hold(&self, alert: &Alert) -> bool {
+        if self.until.is_none_or(|until| Instant::now() >= until) {
+            return false;
+        }
+        let mut held = self.held.lock().unwrap();
+        let rule = alert.rule.clone().unwrap_or_default();
+        *held.counts.entry(rule).or_default() += 1;
+        held.critical |= alert.severity == Severity::Critical;
+        true
+    }
+
+    /// Takes the summary of the alerts held back, if any.
+    pub fn summary(&self) -> Option<Alert> {
+        let held = std::mem::take(&mut *self.held.lock().unwrap());
+        if held.counts.is_empty() {
+            return None;
+        }
+        let mut counts = held.counts.into_iter().collect::<Vec<_>>();
+        counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+        let total = counts.iter().map(|(_, count)| count).sum::<usize>();
+        let rules = counts
+            .iter()
+            .map(|(rule, count)| format!("{rule} ({count})"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        Some(Alert {
+            severity: if held.critical {
+                Severity::Critical
+            } else {
+                Severity::Warning
+            },
+            ..Alert::new(format!(
+                "⏳ {total} alerts during the {} startup window: {rules}",
+                format_duration(self.duration)
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_warmup() {
+        let alert = |rule: &str| Alert {
+            rule: Some(rule.to_string()),
+            ..Alert::new("disk full")
+        };
+        let warmup = Warmup::new(Duration::from_secs(60));
+        assert!(warmup.hold(&alert("disk")));
+        assert!(warmup.hold(&alert("disk")));
+        assert!(warmup.hold(&alert("db")));
+        let summary = warmup.summary().unwrap();
+        assert_eq!(
+            summary.message,
+            "⏳ 3 alerts during the 1m startup window: disk (2), db (1)"
+        );
+        assert_eq!(summary.severity, Severity::Warning);
+        assert!(warmup.summary().is_none());
+
+        // disabled by default
+        assert!(!Warmup::default().hold(&alert("disk")));
+    }
+}
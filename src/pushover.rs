@@ -0,0 +1,201 @@
+use std::time::Duration;
+
+use flume::Receiver;
+use log::{debug, error, info};
+
+use crate::alert::Alert;
+use crate::config::{PushoverConfig, SendRetry, Severity, SuppressionTtl};
+use crate::error::NotifierError;
+use crate::notifier::{self, Attempt};
+use crate::suppression::{DedupKey, Suppressions};
+
+const MESSAGES_URL: &str = "https://api.pushover.net/1/messages.json";
+// title of the alerts without a rule
+const DEFAULT_TITLE: &str = "journal-alerts";
+
+/// Pushes the alerts of the configured severities to phones with Pushover.
+pub struct Pushover {
+    config: PushoverConfig,
+    client: reqwest::Client,
+    repeats: Suppressions,
+    suppression_ttl: SuppressionTtl,
+    // the duplicates are suppressed in front of all the notifiers instead
+    shared_suppression: bool,
+    send_retry: SendRetry,
+}
+
+impl Pushover {
+    pub fn new(config: PushoverConfig) -> Self {
+        Pushover {
+            config,
+            client: reqwest::Client::new(),
+            repeats: Suppressions::default(),
+            suppression_ttl: SuppressionTtl::default(),
+            shared_suppression: false,
+            send_retry: SendRetry::default(),
+        }
+    }
+
+    /// Sets how long duplicates are suppressed, per severity.
+    pub fn with_suppression_ttl(mut self, suppression_ttl: SuppressionTtl) -> Self {
+        self.suppression_ttl = suppression_ttl;
+        self
+    }
+
+    /// Leaves the duplicate suppression to the shared layer in front of the notifiers.
+    pub fn with_shared_suppression(mut self) -> Self {
+        self.shared_suppression = true;
+        self
+    }
+
+    /// Detects the duplicates on the text built by the `dedup_key` steps.
+    pub fn with_dedup_key(mut self, dedup_key: DedupKey) -> Self {
+        self.repeats = std::mem::take(&mut self.repeats).with_dedup_key(dedup_key);
+        self
+    }
+
+    /// Sets how the failed pushes are retried.
+    pub fn with_send_retry(mut self, send_retry: SendRetry) -> Self {
+        self.send_retry = send_retry;
+        self
+    }
+
+    pub async fn start(&self, rx: Receiver<Alert>) -> Result<(), NotifierError> {
+        info!("Pushover notifier started.");
+        loop {
+            let Ok(alert) = rx.recv_async().await else {
+                info!("Pushover notifier receiver channel closed, exiting.");
+                break;
+            };
+            self.repeats.reset_for(&alert);
+            // alerts routed here explicitly are pushed whatever their severity
+            if alert.notify.is_empty() && !self.config.severities.contains(&alert.severity) {
+                continue;
+            }
+
+            // each duplicate would buzz the phone again, critical rules excepted
+            if !alert.always_notify && !self.shared_suppression {
+                if let Some(count) = self.repeats.duplicate(alert.dedup_text()) {
+                    debug!("Not pushing duplicate alert, count: {}", count);
+                    continue;
+                }
+                let ttl = alert
+                    .suppression_ttl
+                    .unwrap_or_else(|| self.suppression_ttl.get(alert.severity));
+                self.repeats
+                    .insert(alert.dedup_text(), alert.rule.clone(), ttl);
+            }
+
+            if let Err(e) = self.send_alert(&alert).await {
+                error!("Error sending alert to Pushover: {}", e);
+                self.repeats.remove(alert.dedup_text());
+            }
+            self.repeats.sweep();
+        }
+        Ok(())
+    }
+
+    pub async fn send_alert(&self, alert: &Alert) -> Result<(), NotifierError> {
+        if self.config.token.is_empty() || self.config.user.is_empty() {
+            info!("{}", alert.text());
+            return Ok(());
+        }
+
+        let payload = self.payload(alert);
+        notifier::send_with_retry("Pushover", &self.send_retry, || async {
+            let res = self
+                .client
+                .post(MESSAGES_URL)
+                .timeout(Duration::from_secs(10))
+                .json(&payload)
+                .send()
+                .await
+                .map_err(|e| Attempt::Retry(format!("HTTP client error {e}")))?;
+            let status = res.status();
+            if status.is_success() {
+                return Ok(());
+            }
+            if status.is_server_error() || status.as_u16() == 429 {
+                return Err(Attempt::Retry(format!("Status: {status}")));
+            }
+            // a rejected request, the errors say why
+            let body: serde_json::Value = res.json().await.map_err(|source| {
+                Attempt::Fatal(NotifierError::Response {
+                    notifier: "Pushover",
+                    source,
+                })
+            })?;
+            Err(Attempt::Fatal(NotifierError::Api {
+                notifier: "Pushover",
+                details: format!("status {}, errors: {}", status, body["errors"]),
+            }))
+        })
+        .await
+    }
+
+    fn payload(&self, alert: &Alert) -> serde_json::Value {
+        let priority = priority(alert.severity);
+        let mut payload = serde_json::json!({
+            "token": self.config.token,
+            "user": self.config.user,
+            "title": alert.rule.as_deref().unwrap_or(DEFAULT_TITLE),
+            "message": alert.text(),
+            "priority": priority,
+        });
+        // emergency notifications repeat until acknowledged, Pushover requires the bounds
+        if priority == EMERGENCY {
+            payload["retry"] = serde_json::Value::from(self.config.retry);
+            payload["expire"] = serde_json::Value::from(self.config.expire);
+        }
+        payload
+    }
+}
+
+const EMERGENCY: i8 = 2;
+
+/// Maps the severity to a Pushover priority: critical alerts are emergencies,
+/// informational ones do not make a sound.
+fn priority(severity: Severity) -> i8 {
+    match severity {
+        Severity::Critical => EMERGENCY,
+        Severity::Warning => 0,
+        Severity::Info => -1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload() {
+        let pushover = Pushover::new(PushoverConfig {
+            token: "app".to_string(),
+            user: "me".to_string(),
+            ..Default::default()
+        });
+        let alert = Alert {
+            rule: Some("disk".to_string()),
+            severity: Severity::Critical,
+            ..Alert::new("disk full")
+        };
+        assert_eq!(
+            pushover.payload(&alert),
+            serde_json::json!({
+                "token": "app",
+                "user": "me",
+                "title": "disk",
+                "message": "disk full",
+                "priority": 2,
+                "retry": 60,
+                "expire": 3600,
+            })
+        );
+
+        // only emergencies carry the retry and expire
+        let payload = pushover.payload(&Alert::new("started"));
+        assert_eq!(payload["title"], DEFAULT_TITLE);
+        assert_eq!(payload["priority"], -1);
+        assert!(payload.get("retry").is_none());
+    }
+}
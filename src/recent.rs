@@ -0,0 +1,93 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// An alert handed to Slack, with the duplicates suppressed since.
+#[derive(Debug, Clone)]
+pub struct Recent {
+    pub sent: Instant,
+    pub rule: Option<String>,
+    pub message: String,
+    pub suppressed: usize,
+    // suppression key of the message, to count its duplicates
+    key: u64,
+}
+
+/// The last alerts sent, oldest first, for the `recent` control command.
+#[derive(Default)]
+pub struct RecentAlerts {
+    entries: Mutex<VecDeque<Recent>>,
+    // number of alerts kept, none when 0
+    capacity: usize,
+}
+
+impl RecentAlerts {
+    pub fn new(capacity: usize) -> Self {
+        RecentAlerts {
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+        }
+    }
+
+    /// Records a sent alert with its suppression key, forgetting the oldest one when
+    /// full.
+    pub fn sent(&self, rule: Option<String>, message: &str, key: u64) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(Recent {
+            sent: Instant::now(),
+            rule,
+            message: message.to_string(),
+            suppressed: 0,
+            key,
+        });
+    }
+
+    /// Counts a suppressed duplicate against the last time its key was sent.
+    pub fn suppressed(&self, key: u64) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(recent) = entries.iter_mut().rev().find(|recent| recent.key == key) {
+            recent.suppressed += 1;
+        }
+    }
+
+    /// The last `limit` alerts sent, oldest first.
+    pub fn list(&self, limit: usize) -> Vec<Recent> {
+        let entries = self.entries.lock().unwrap();
+        let skip = entries.len().saturating_sub(limit);
+        entries.iter().skip(skip).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recent_alerts() {
+        let recent = RecentAlerts::new(2);
+        recent.sent(Some("disk".to_string()), "disk full", 1);
+        recent.sent(None, "started", 2);
+        recent.suppressed(1);
+        recent.suppressed(3);
+        let list = recent.list(10);
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].suppressed, 1);
+
+        // the oldest alert is forgotten when full
+        recent.sent(Some("db".to_string()), "db down", 3);
+        let list = recent.list(1);
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].message, "db down");
+        assert_eq!(recent.list(10)[0].message, "started");
+
+        let disabled = RecentAlerts::new(0);
+        disabled.sent(None, "started", 2);
+        assert!(disabled.list(10).is_empty());
+    }
+}
@@ -0,0 +1,229 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use flume::{Receiver, Sender};
+use log::{debug, error, info, warn};
+use tokio::select;
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::task::JoinSet;
+use tokio::time::sleep;
+
+use crate::backoff::Backoff;
+use crate::channel::AlertSender;
+use crate::config::{Config, FailurePolicy};
+use crate::control::{Silences, format_duration};
+use crate::error::{self, MatcherError, ProcessError};
+use crate::metrics::Metrics;
+use crate::processor::{JournalProcessor, Warmup};
+
+// wait before starting a failed processor again, doubled on each consecutive failure
+const PROCESSOR_RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// The runtime state shared by the processors, kept across reloads.
+#[derive(Clone, Default)]
+pub struct SharedState {
+    pub silences: Arc<Silences>,
+    pub metrics: Arc<Metrics>,
+    pub warmup: Arc<Warmup>,
+}
+
+/// Creates one processor per monitor of the config, compiling all the rules.
+pub fn processors(
+    config: &Config,
+    state: &SharedState,
+) -> Result<Vec<Arc<JournalProcessor>>, MatcherError> {
+    config
+        .monitors()
+        .into_iter()
+        .map(|(name, config)| {
+            info!("Setting up monitor '{}'", name);
+            let processor = JournalProcessor::new(&config)?
+                .with_silences(state.silences.clone())
+                .with_metrics(state.metrics.clone())
+                .with_monitor(&name)
+                .with_warmup(state.warmup.clone());
+            Ok(Arc::new(processor))
+        })
+        .collect()
+}
+
+/// Runs the processors until one of them fails. On a reload request the config is
+/// loaded again from `path` and, if valid, the processors are replaced with new ones;
+/// an invalid config keeps the current processors running. A change of `maintenance`
+/// alone keeps the processors, and their heartbeat state, running.
+pub async fn run(
+    config: &Config,
+    path: &str,
+    mut processors: Vec<Arc<JournalProcessor>>,
+    state: SharedState,
+    tx: AlertSender,
+    reload: Receiver<()>,
+) -> Result<(), ProcessError> {
+    let mut current = rules(config);
+    let mut policy = config.on_processor_failure;
+    loop {
+        // dropping the tasks on reload stops the processors and their journalctl
+        let mut tasks = JoinSet::new();
+        Metrics::set(&state.metrics.monitors, processors.len() as u64);
+        for processor in processors {
+            tasks.spawn(supervise(processor, tx.clone(), policy));
+        }
+        processors = loop {
+            select! {
+                res = tasks.join_next() => match res {
+                    Some(res) => res??,
+                    None => return Ok(()),
+                },
+                Ok(()) = reload.recv_async() => {
+                    let config = match Config::load(Some(path.to_string())) {
+                        Ok(config) => config,
+                        Err(e) => {
+                            error!(
+                                "Config reload failed, keeping the current config: {}",
+                                error::report(&e)
+                            );
+                            continue;
+                        }
+                    };
+                    let new = rules(&config);
+                    let processors = if new.is_some() && new == current {
+                        None
+                    } else {
+                        match self::processors(&config, &state) {
+                            Ok(processors) => Some(processors),
+                            Err(e) => {
+                                error!(
+                                    "Config reload failed, keeping the current config: {}",
+                                    error::report(&e)
+                                );
+                                continue;
+                            }
+                        }
+                    };
+                    tx.set_maintenance(config.maintenance)
+                        .await
+                        .inspect_err(|e| error!("Failed to send maintenance notice: {}", e))
+                        .ok();
+                    match processors {
+                        Some(processors) => {
+                            info!("Config reloaded from {}", path);
+                            // the old processors stop before their rule series are forgotten
+                            tasks.shutdown().await;
+                            state.metrics.clear_rules();
+                            current = new;
+                            policy = config.on_processor_failure;
+                            break processors;
+                        }
+                        None => info!("Config reloaded from {}, the rules are unchanged", path),
+                    }
+                }
+            }
+        };
+    }
+}
+
+/// Runs the processor until it stops. When it fails and the policy is to restart, it
+/// is started again after a backoff unless the error is fatal, e.g. a missing
+/// permission; a transient failure such as spawning journalctl is retried.
+async fn supervise(
+    processor: Arc<JournalProcessor>,
+    tx: AlertSender,
+    policy: FailurePolicy,
+) -> Result<(), ProcessError> {
+    let mut backoff = Backoff::new(PROCESSOR_RESTART_DELAY);
+    loop {
+        let started = Instant::now();
+        match processor.start(tx.clone()).await {
+            Err(e) if policy == FailurePolicy::Restart && !e.is_fatal() => {
+                let delay = backoff.delay(started.elapsed());
+                error!(
+                    "Processor failed, restarting in {}: {}",
+                    format_duration(delay),
+                    error::report(&e)
+                );
+                sleep(delay).await;
+            }
+            res => return res,
+        }
+    }
+}
+
+/// The config the processors are created from, everything but `maintenance`.
+fn rules(config: &Config) -> Option<String> {
+    let config = Config {
+        maintenance: false,
+        ..config.clone()
+    };
+    toml::to_string(&config).ok()
+}
+
+/// Requests a reload on every SIGHUP, e.g. from `systemctl reload`.
+pub async fn watch_sighup(reload: Sender<()>) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            warn!("Cannot listen for SIGHUP, reload on signal disabled: {}", e);
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        info!("SIGHUP received, reloading the config");
+        if reload.send(()).is_err() {
+            return;
+        }
+    }
+}
+
+/// Requests a reload whenever the modification time of the file changes.
+pub async fn watch_mtime(path: String, interval: Duration, reload: Sender<()>) {
+    let modified = |path: &str| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    let mut last: Option<SystemTime> = modified(&path);
+    info!("Watching {} for changes every {:?}", path, interval);
+    loop {
+        sleep(interval).await;
+        let current = modified(&path);
+        if current.is_none() {
+            // e.g. in the middle of an atomic replace, check again later
+            warn!("Cannot read the modification time of {}", path);
+            continue;
+        }
+        if current != last {
+            debug!("{} changed, requesting a reload", path);
+            last = current;
+            if reload.send(()).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_watch_mtime() {
+        let path = std::env::temp_dir().join("journal-alerts-test-reload.toml");
+        std::fs::write(&path, "a").unwrap();
+        let (tx, rx) = flume::unbounded();
+        let watch = tokio::spawn(watch_mtime(
+            path.to_str().unwrap().to_string(),
+            Duration::from_millis(20),
+            tx,
+        ));
+
+        // an unchanged file does not trigger a reload
+        sleep(Duration::from_millis(100)).await;
+        assert!(rx.is_empty());
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(1))
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(1), rx.recv_async())
+            .await
+            .unwrap()
+            .unwrap();
+
+        watch.abort();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
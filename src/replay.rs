@@ -0,0 +1,209 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+
+use flate2::read::MultiGzDecoder;
+use log::info;
+
+use crate::config::{AlertRule, Config};
+use crate::error::{ProcessError, ReplayError};
+use crate::processor::{self, JournalProcessor};
+
+// how far back the journal is previewed by default, in journalctl --since syntax
+pub const DEFAULT_PREVIEW_SINCE: &str = "-1h";
+
+// magic bytes at the start of a gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Runs the alert rules against a log file, printing the alerts it would have sent.
+/// With multiple monitors, the rules of all of them are matched.
+pub fn replay(config: &Config, path: &str) -> Result<(), ReplayError> {
+    let processors = config
+        .monitors()
+        .iter()
+        .map(|(_, config)| JournalProcessor::new(config))
+        .collect::<Result<Vec<_>, _>>()?;
+    let reader = open(path)?;
+    let (lines, matched) = replay_lines(&processors, reader, &mut std::io::stdout().lock())?;
+    info!(
+        "Replayed {} lines, {} alerts would have been sent",
+        lines, matched
+    );
+    Ok(())
+}
+
+/// Runs a single pattern against the journal logged since `since`, printing the lines
+/// it matches. The rules of the config are left out, only its unit, identifier and
+/// boot filters, per monitor, and its journal directories select the journal read.
+pub fn preview(config: &Config, pattern: &str, since: &str) -> Result<(), ReplayError> {
+    let rule = AlertRule {
+        name: "preview".to_string(),
+        pattern: pattern.to_string(),
+        ..Default::default()
+    };
+    let (mut lines, mut matched) = (0, 0);
+    for (name, monitor) in config.monitors() {
+        let config = Config {
+            alerts: vec![rule.clone()],
+            heartbeats: Vec::new(),
+            exclude_units: Vec::new(),
+            transports: Vec::new(),
+            exclude_transports: Vec::new(),
+            quiet_start: false,
+            ..monitor
+        };
+        let processor = JournalProcessor::new(&config)?;
+        info!("Previewing '{pattern}' on the journal of monitor '{name}' since {since}");
+        for directory in processor::directory_args(&config) {
+            let mut child = Command::new("journalctl")
+                .args(processor::preview_args(&config, since))
+                .args(directory)
+                .stdout(Stdio::piped())
+                .spawn()
+                .map_err(ProcessError::Spawn)?;
+            let stdout = child.stdout.take().ok_or(ProcessError::Capture("stdout"))?;
+            let (read, found) = replay_lines(
+                std::slice::from_ref(&processor),
+                BufReader::new(stdout),
+                &mut std::io::stdout().lock(),
+            )?;
+            let status = child.wait().map_err(ProcessError::Wait)?;
+            if !status.success() {
+                return Err(ProcessError::Failed(status).into());
+            }
+            lines += read;
+            matched += found;
+        }
+    }
+    info!("Previewed {} lines, {} matched", lines, matched);
+    Ok(())
+}
+
+/// Opens a log file, transparently decompressing gzip content.
+fn open(path: &str) -> Result<Box<dyn BufRead>, ReplayError> {
+    let file = File::open(path).map_err(|source| ReplayError::Open {
+        path: path.to_string(),
+        source,
+    })?;
+    let mut reader = BufReader::new(file);
+
+    // gzip compressed input is detected from its content rather than the extension
+    let gzip = reader
+        .fill_buf()
+        .map_err(|source| ReplayError::Read {
+            path: path.to_string(),
+            source,
+        })?
+        .starts_with(&GZIP_MAGIC);
+    if gzip {
+        info!("Replaying gzip compressed file: {path}");
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))))
+    } else {
+        info!("Replaying file: {path}");
+        Ok(Box::new(reader))
+    }
+}
+
+/// Matches each line of the reader, writing the would-be alerts to `out`.
+/// Returns the number of lines read and matched.
+fn replay_lines(
+    processors: &[JournalProcessor],
+    mut reader: impl BufRead,
+    out: &mut impl Write,
+) -> Result<(usize, usize), ReplayError> {
+    let mut buf = Vec::new();
+    let (mut lines, mut matched) = (0, 0);
+    loop {
+        buf.clear();
+        if reader
+            .read_until(b'\n', &mut buf)
+            .map_err(ReplayError::Lines)?
+            == 0
+        {
+            break;
+        }
+        lines += 1;
+        // archived logs may contain invalid UTF-8, do not give up on them
+        let line = String::from_utf8_lossy(&buf);
+        let line = line.trim_end_matches(['\n', '\r']);
+        for processor in processors {
+            let entry = processor.parse(line.to_string());
+            if processor.predates_start(&entry) {
+                continue;
+            }
+            for (alert, event) in processor.match_alerts(&entry) {
+                matched += 1;
+                let span = event.span;
+                writeln!(
+                    out,
+                    "line {} [{}] matched '{}' at {}..{}: {}",
+                    lines,
+                    event.rule_name,
+                    &event.line[span.clone()],
+                    span.start,
+                    span.end,
+                    alert.message
+                )
+                .map_err(ReplayError::Write)?;
+            }
+        }
+    }
+    Ok((lines, matched))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use std::io::Read;
+
+    const LOG: &str = "starting\nerror: disk full\nall good\nwarn: slow query\n";
+
+    fn processor() -> JournalProcessor {
+        let config: Config = toml::from_str(
+            r#"
+            slack_webhook_url = ""
+            systemd_unit = ""
+            [[alerts]]
+            name = "disk"
+            pattern = "disk \\w+"
+            prefix = "🔴 "
+            [[alerts]]
+            name = "warn"
+            pattern = "warn"
+            prefix = "🟠 "
+            "#,
+        )
+        .unwrap();
+        JournalProcessor::new(&config).unwrap()
+    }
+
+    #[test]
+    fn test_replay_lines() {
+        let mut out = Vec::new();
+        let (lines, matched) = replay_lines(&[processor()], LOG.as_bytes(), &mut out).unwrap();
+        assert_eq!((lines, matched), (4, 2));
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "line 2 [disk] matched 'disk full' at 7..16: 🔴 error: disk full\n\
+             line 4 [warn] matched 'warn' at 0..4: 🟠 warn: slow query\n"
+        );
+    }
+
+    #[test]
+    fn test_replay_gzip() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(LOG.as_bytes()).unwrap();
+        let path = std::env::temp_dir().join("journal-alerts-replay-test.log.gz");
+        std::fs::write(&path, encoder.finish().unwrap()).unwrap();
+
+        let mut content = String::new();
+        open(path.to_str().unwrap())
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(content, LOG);
+    }
+}
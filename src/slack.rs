@@ -1,91 +1,1163 @@
+use std::io::Write;
+use std::ops::Range;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use anyhow::Result;
 use dashmap::DashMap;
+use flate2::Compression;
+use flate2::write::GzEncoder;
 use flume::Receiver;
 use log::{debug, error, info, warn};
+use reqwest::header::HeaderMap;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
 use tokio::time::sleep;
 
+use crate::alert::{Alert, AlertKind};
+use crate::config::{
+    ContextUpload, SendRetry, SeverityLabels, SlackChannels, SlackIdentity, SlackReactions,
+    SuppressionTtl,
+};
+use crate::context;
+use crate::dedup::SharedDedup;
+use crate::error::NotifierError;
+use crate::notifier::{self, Attempt};
+use crate::recent::RecentAlerts;
+use crate::suppression::{Suppressions, Suppressor};
+
+const API_POST_MESSAGE_URL: &str = "https://slack.com/api/chat.postMessage";
+const API_REACTIONS_ADD_URL: &str = "https://slack.com/api/reactions.add";
+const API_GET_UPLOAD_URL: &str = "https://slack.com/api/files.getUploadURLExternal";
+const API_COMPLETE_UPLOAD_URL: &str = "https://slack.com/api/files.completeUploadExternal";
+// time a post to Slack may take, before it is retried
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+// errors of the Web API worth retrying, the others fail the same way again
+const API_TRANSIENT_ERRORS: [&str; 4] = [
+    "ratelimited",
+    "internal_error",
+    "service_unavailable",
+    "request_timeout",
+];
+/// Id of the Acknowledge button, in the interactivity requests of its clicks.
+pub const ACK_ACTION_ID: &str = "acknowledge";
+
+/// How the arguments of a Web API call are sent, the file uploads only take a form.
+#[derive(Debug, Clone, Copy)]
+enum Encoding {
+    Json,
+    Form,
+}
+
+/// Sends the request, the network errors, rate limiting and server errors are retried.
+async fn send(request: reqwest::RequestBuilder) -> Result<reqwest::Response, Attempt> {
+    match request.timeout(SEND_TIMEOUT).send().await {
+        Ok(res) if res.status().is_success() => Ok(res),
+        Ok(res) if res.status().is_client_error() && res.status().as_u16() != 429 => {
+            Err(Attempt::Fatal(NotifierError::Status {
+                notifier: "Slack",
+                status: res.status(),
+            }))
+        }
+        Ok(res) => Err(Attempt::Retry(format!("Status: {}", res.status()))),
+        Err(e) => Err(Attempt::Retry(format!("HTTP client error {e}"))),
+    }
+}
+
+/// Posts the payload to a webhook, retrying on network errors, rate limiting and
+/// server errors.
+async fn post_webhook(
+    client: &reqwest::Client,
+    url: &str,
+    headers: &HeaderMap,
+    payload: &serde_json::Value,
+    retry: &SendRetry,
+) -> Result<(), NotifierError> {
+    notifier::send_with_retry("Slack", retry, || async {
+        send(client.post(url).headers(headers.clone()).json(payload))
+            .await
+            .map(drop)
+    })
+    .await
+}
+
+/// Calls a method of the Web API, retrying as the webhooks and on its transient errors.
+/// The API answers 200 on most errors, the body of a successful call is returned.
+async fn call_api(
+    client: &reqwest::Client,
+    token: &str,
+    url: &str,
+    payload: &serde_json::Value,
+    encoding: Encoding,
+    retry: &SendRetry,
+) -> Result<serde_json::Value, NotifierError> {
+    notifier::send_with_retry("Slack", retry, || async {
+        let request = client.post(url).bearer_auth(token);
+        let res = send(match encoding {
+            Encoding::Json => request.json(payload),
+            Encoding::Form => request.form(payload),
+        })
+        .await?;
+        let body: serde_json::Value = res.json().await.map_err(|source| {
+            Attempt::Fatal(NotifierError::Response {
+                notifier: "Slack",
+                source,
+            })
+        })?;
+        match body["error"].as_str() {
+            _ if body["ok"] == true => Ok(body),
+            Some(error) if API_TRANSIENT_ERRORS.contains(&error) => {
+                Err(Attempt::Retry(format!("API error {error}")))
+            }
+            _ => Err(Attempt::Fatal(NotifierError::Api {
+                notifier: "Slack",
+                details: body["error"].to_string(),
+            })),
+        }
+    })
+    .await
+}
+
+/// Posting through the Web API with a bot token. Unlike webhooks it returns the `ts`
+/// of the posted message, needed to thread the following alerts under it.
+#[derive(Clone)]
+struct SlackApi {
+    token: String,
+    channel: String,
+    // channels per severity, `channel` when unset
+    channels: SlackChannels,
+    // emoji the alerts are reacted with per severity
+    reactions: SlackReactions,
+    // how long alerts of a rule are threaded under its first message, disabled when 0
+    thread_ttl: Duration,
+    // Map of rule name to the thread parent and when it was posted
+    threads: Arc<DashMap<String, (Parent, Instant)>>,
+    // post the recovery of a heartbeat as a reply to its miss
+    thread_heartbeats: bool,
+    // Map of heartbeat thread key to its miss, until it recovers
+    misses: Arc<DashMap<String, Parent>>,
+    // add the Acknowledge button to the alerts of the rules
+    ack_button: bool,
+    // how the journal context is attached to the alerts
+    context_upload: ContextUpload,
+}
+
+/// A posted message the following ones are threaded under, replies are posted in
+/// its channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Parent {
+    channel: String,
+    ts: String,
+}
+
+impl SlackApi {
+    /// The channel of the alert's severity.
+    fn channel(&self, alert: &Alert) -> &str {
+        self.channels.get(alert.severity).unwrap_or(&self.channel)
+    }
+
+    /// The emoji the alert is reacted with, for the alerts of the rules and heartbeats.
+    fn reaction(&self, alert: &Alert) -> Option<&str> {
+        matches!(alert.kind, AlertKind::Rule | AlertKind::Heartbeat)
+            .then(|| self.reactions.get(alert.severity))
+            .flatten()
+    }
+
+    /// The thread the alert should be posted in, if an alert of its rule started one,
+    /// or the miss of a heartbeat that recovers was posted.
+    fn thread(&self, alert: &Alert) -> Option<Parent> {
+        if let Some(key) = alert.thread_key.as_ref().filter(|_| self.thread_heartbeats) {
+            // a new miss forgets the previous one, e.g. recovered in a batch, so that a
+            // recovery is posted on its own when its miss failed to send
+            let miss = self.misses.remove(key).map(|(_, parent)| parent);
+            return miss.filter(|_| alert.kind == AlertKind::Recovery);
+        }
+        let rule = alert.rule.as_ref().filter(|_| !self.thread_ttl.is_zero())?;
+        self.threads
+            .remove_if(rule, |_, (_, posted)| posted.elapsed() >= self.thread_ttl);
+        self.threads.get(rule).map(|thread| thread.0.clone())
+    }
+
+    /// Records a posted alert as the parent of the next alerts of its rule, or of
+    /// the recovery of its heartbeat.
+    fn start_thread(&self, alert: &Alert, parent: Parent) {
+        if let Some(key) = alert.thread_key.as_ref().filter(|_| self.thread_heartbeats) {
+            if alert.kind == AlertKind::Heartbeat {
+                self.misses.insert(key.clone(), parent);
+            }
+            return;
+        }
+        if let Some(rule) = alert.rule.as_ref().filter(|_| !self.thread_ttl.is_zero()) {
+            self.threads.insert(rule.clone(), (parent, Instant::now()));
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Slack {
-    webhook_url: String,
+    // webhooks every alert is posted to
+    webhook_urls: Vec<String>,
+    // extra headers of the webhook requests, e.g. for an authenticating gateway
+    webhook_headers: HeaderMap,
     client: reqwest::Client,
-    repeats: DashMap<String, (usize, Instant)>,
+    suppressor: Suppressor,
+    // the duplicates are suppressed in front of all the notifiers instead
+    shared_suppression: bool,
+    send_retry: SendRetry,
+    identity: SlackIdentity,
+    severity_labels: SeverityLabels,
+    // text wrapped around every message
+    message_prefix: String,
+    message_suffix: String,
+    // number of alerts sent at the same time
+    send_concurrency: usize,
+    // post with the Web API instead of the webhook
+    api: Option<SlackApi>,
 }
 
 impl Slack {
-    pub fn new(webhook_url: String) -> Self {
+    pub fn new(mut webhook_urls: Vec<String>) -> Self {
+        webhook_urls.retain(|url| !url.is_empty());
         Slack {
-            webhook_url,
+            webhook_urls,
+            webhook_headers: HeaderMap::new(),
             client: reqwest::Client::new(),
-            repeats: DashMap::new(),
+            suppressor: Suppressor::default(),
+            shared_suppression: false,
+            send_retry: SendRetry::default(),
+            identity: SlackIdentity::default(),
+            severity_labels: SeverityLabels::default(),
+            message_prefix: String::new(),
+            message_suffix: String::new(),
+            send_concurrency: 1,
+            api: None,
         }
     }
 
-    pub async fn start(&self, rx: Receiver<String>) -> Result<()> {
+    /// Adds the headers to the webhook requests, not to the Web API ones.
+    pub fn with_webhook_headers(mut self, webhook_headers: HeaderMap) -> Self {
+        self.webhook_headers = webhook_headers;
+        self
+    }
+
+    /// Returns the duplicate suppression state.
+    pub fn suppressions(&self) -> Arc<Suppressions> {
+        self.suppressor.suppressions()
+    }
+
+    /// Replaces the duplicate suppression state, e.g. to bound it.
+    pub fn with_suppressions(mut self, suppressions: Suppressions) -> Self {
+        self.suppressor = self.suppressor.with_suppressions(suppressions);
+        self
+    }
+
+    /// Returns the decisions on the duplicates, to share them with the other notifiers.
+    pub fn suppressor(&self) -> Suppressor {
+        self.suppressor.clone()
+    }
+
+    /// Leaves the duplicate suppression to the shared layer in front of the notifiers.
+    pub fn with_shared_suppression(mut self) -> Self {
+        self.shared_suppression = true;
+        self
+    }
+
+    /// Returns the last alerts sent.
+    pub fn recent_alerts(&self) -> Arc<RecentAlerts> {
+        self.suppressor.recent_alerts()
+    }
+
+    /// Keeps the last `capacity` alerts sent, with their suppressed duplicates.
+    pub fn with_recent_alerts(mut self, capacity: usize) -> Self {
+        self.suppressor = self.suppressor.with_recent_alerts(capacity);
+        self
+    }
+
+    /// Sets how long duplicates are suppressed, per severity.
+    pub fn with_suppression_ttl(mut self, suppression_ttl: SuppressionTtl) -> Self {
+        self.suppressor = self.suppressor.with_suppression_ttl(suppression_ttl);
+        self
+    }
+
+    /// Sets how the failed posts to the webhooks and the Web API are retried.
+    pub fn with_send_retry(mut self, send_retry: SendRetry) -> Self {
+        self.send_retry = send_retry;
+        self
+    }
+
+    /// Sets the username and icons the messages are posted with.
+    pub fn with_identity(mut self, identity: SlackIdentity) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    /// Sets the labels shown before the messages, per severity.
+    pub fn with_severity_labels(mut self, severity_labels: SeverityLabels) -> Self {
+        self.severity_labels = severity_labels;
+        self
+    }
+
+    /// Sets the text wrapped around every message, e.g. to tag the environment.
+    pub fn with_message_wrap(mut self, prefix: String, suffix: String) -> Self {
+        self.message_prefix = prefix;
+        self.message_suffix = suffix;
+        self
+    }
+
+    /// Sets how many identical messages are sent before suppressing the following
+    /// ones, e.g. to show an escalation.
+    pub fn with_suppress_after(mut self, suppress_after: usize) -> Self {
+        self.suppressor = self.suppressor.with_suppress_after(suppress_after);
+        self
+    }
+
+    /// Sets how many alerts are sent at the same time, so a slow send does not hold
+    /// up the others. With more than one, alerts may be delivered out of order.
+    pub fn with_send_concurrency(mut self, send_concurrency: usize) -> Self {
+        self.send_concurrency = send_concurrency.max(1);
+        self
+    }
+
+    /// Posts with the Web API to the channel instead of the webhook, threading the
+    /// alerts of a rule under its first one for `thread_ttl`.
+    pub fn with_api(mut self, token: String, channel: String, thread_ttl: Duration) -> Self {
+        self.api = Some(SlackApi {
+            token,
+            channel,
+            channels: SlackChannels::default(),
+            reactions: SlackReactions::default(),
+            thread_ttl,
+            threads: Arc::new(DashMap::new()),
+            thread_heartbeats: false,
+            misses: Arc::new(DashMap::new()),
+            ack_button: false,
+            context_upload: ContextUpload::Inline,
+        });
+        self
+    }
+
+    /// Posts the alerts to the channel of their severity, with the Web API.
+    pub fn with_severity_channels(mut self, channels: SlackChannels) -> Self {
+        if let Some(api) = &mut self.api {
+            api.channels = channels;
+        }
+        self
+    }
+
+    /// Reacts to the alerts with the emoji of their severity, with the Web API.
+    pub fn with_severity_reactions(mut self, reactions: SlackReactions) -> Self {
+        if let Some(api) = &mut self.api {
+            api.reactions = reactions;
+        }
+        self
+    }
+
+    /// Posts the recovery of a heartbeat as a reply to its miss, with the Web API.
+    pub fn with_heartbeat_threads(mut self) -> Self {
+        if let Some(api) = &mut self.api {
+            api.thread_heartbeats = true;
+        }
+        self
+    }
+
+    /// Adds an Acknowledge button to the alerts of the rules posted with the Web API,
+    /// its clicks are answered by the interactivity endpoint.
+    pub fn with_ack_button(mut self) -> Self {
+        if let Some(api) = &mut self.api {
+            api.ack_button = true;
+        }
+        self
+    }
+
+    /// Uploads the journal context of the alerts posted with the Web API as a file in
+    /// their thread, compressed or not. Webhooks cannot upload, their context stays inline.
+    pub fn with_context_upload(mut self, context_upload: ContextUpload) -> Self {
+        if let Some(api) = &mut self.api {
+            api.context_upload = context_upload;
+        }
+        self
+    }
+
+    /// Whether the journal context is uploaded as a file instead of sent in the message.
+    fn uploads_context(&self) -> bool {
+        self.api
+            .as_ref()
+            .is_some_and(|api| api.context_upload != ContextUpload::Inline)
+    }
+
+    /// Enables deduplication shared with other instances.
+    pub fn with_shared_dedup(mut self, shared_dedup: SharedDedup) -> Self {
+        self.suppressor = self.suppressor.with_shared_dedup(shared_dedup);
+        self
+    }
+
+    pub async fn start(&self, rx: Receiver<Alert>) -> Result<(), NotifierError> {
         info!("Slack notifier started.");
 
-        let repeats = self.repeats.clone();
-        tokio::spawn(async move {
+        let repeats = self.suppressions();
+        let api = self.api.clone();
+        let sweeper = tokio::spawn(async move {
             loop {
-                // remove suppression entries older than 1 hour
-                // TODO: make this configurable
-                let cutoff = Instant::now() - Duration::from_secs(3600);
-                repeats.retain(|_, &mut (_, timestamp)| timestamp >= cutoff);
+                // remove expired suppression entries and threads every hour
+                repeats.sweep();
+                if let Some(api) = &api {
+                    api.threads
+                        .retain(|_, (_, posted)| posted.elapsed() < api.thread_ttl);
+                }
                 sleep(Duration::from_secs(3600)).await;
             }
         });
+        let res = self.receive(rx).await;
+        // a restarted notifier starts its own sweeper
+        sweeper.abort();
+        res
+    }
 
+    /// Sends the alerts received until the channel is closed, and the sends in flight.
+    async fn receive(&self, rx: Receiver<Alert>) -> Result<(), NotifierError> {
+        // sends run on their own tasks, bounded by the permits
+        let slack = Arc::new(self.clone());
+        let permits = Arc::new(Semaphore::new(self.send_concurrency));
         loop {
-            let Ok(message) = rx.recv_async().await else {
+            let Ok(alert) = rx.recv_async().await else {
                 info!("Slack notifier receiver channel closed, exiting.");
                 break;
             };
-            debug!("Received alert message: {}", message);
-
-            // to avoid spamming, check for duplicates
-            if let Some(mut entry) = self.repeats.get_mut(&message) {
-                let (count, _) = entry.value_mut();
-                *count += 1usize;
-                warn!(
-                    "Suppressing duplicate alert detected, count: {}: {}",
-                    *count, message
-                );
+            debug!("Received alert message: {}", alert.message);
+            if !self.shared_suppression && !self.suppressor.admit(&alert).await {
                 continue;
             }
+            let permit = permits.clone().acquire_owned().await?;
+            let slack = slack.clone();
+            tokio::spawn(async move {
+                slack.deliver(alert).await;
+                drop(permit);
+            });
+        }
 
-            if let Err(e) = self.send_alert(&message).await {
-                error!("Error sending alert to Slack: {}", e);
-                continue;
+        // wait for the sends in flight
+        let _ = permits.acquire_many(self.send_concurrency as u32).await?;
+        Ok(())
+    }
+
+    /// Sends the alert with its context, a failed one is not suppressed.
+    async fn deliver(&self, mut alert: Alert) {
+        // fetched only now, so that suppressed duplicates do not run journalctl
+        self.enrich(&mut alert).await;
+        if let Err(e) = self.send_alert(&alert).await {
+            error!("Error sending alert to Slack: {}", e);
+            if !self.shared_suppression {
+                self.suppressor.forget(&alert);
             }
+        }
+    }
 
-            // insert into repeats map with count 1 and current instant
-            self.repeats
-                .insert(message.clone(), (1usize, Instant::now()));
+    /// Attaches the recent journal lines to the alert, if its rule asks for them.
+    async fn enrich(&self, alert: &mut Alert) {
+        if alert.context_lines == 0 {
+            return;
+        }
+        let max_bytes = if self.uploads_context() {
+            context::MAX_UPLOAD_BYTES
+        } else {
+            context::MAX_CONTEXT_BYTES
+        };
+        match context::recent_lines(&alert.journal_filter, alert.context_lines, max_bytes).await {
+            Ok(lines) if lines.is_empty() => debug!("No journal context for: {}", alert.message),
+            Ok(lines) => alert.context = Some(lines),
+            Err(e) => warn!("Failed to read the journal context: {}", e),
+        }
+    }
+
+    pub async fn send_alert(&self, alert: &Alert) -> Result<(), NotifierError> {
+        if let Some(api) = &self.api {
+            return self.post_message(api, alert).await;
+        }
+        if self.webhook_urls.is_empty() {
+            info!("{}", self.text(alert));
+            return Ok(());
         }
 
+        // fan out to all the webhooks, each one retries on its own
+        let payload = Arc::new(self.payload(alert));
+        let mut sends = JoinSet::new();
+        for url in &self.webhook_urls {
+            let (client, url, payload) = (self.client.clone(), url.clone(), payload.clone());
+            let (headers, retry) = (self.webhook_headers.clone(), self.send_retry);
+            sends.spawn(
+                async move { post_webhook(&client, &url, &headers, &payload, &retry).await },
+            );
+        }
+        let mut failed = 0;
+        while let Some(res) = sends.join_next().await {
+            if let Err(e) = res? {
+                error!("{}", e);
+                failed += 1;
+            }
+        }
+
+        // resending after a partial failure would duplicate the alert on the others
+        if failed == self.webhook_urls.len() {
+            return Err(NotifierError::AllWebhooks);
+        }
         Ok(())
     }
 
-    pub async fn send_alert(&self, message: &str) -> Result<()> {
-        if self.webhook_url.is_empty() {
-            info!("{message}");
+    /// Posts the alert with the Web API to the channel of its severity, or in the
+    /// thread of its rule if there is one.
+    async fn post_message(&self, api: &SlackApi, alert: &Alert) -> Result<(), NotifierError> {
+        let mut payload = self.payload(alert);
+        let thread = api.thread(alert);
+        let channel = match &thread {
+            Some(parent) => {
+                payload["thread_ts"] = serde_json::Value::from(parent.ts.as_str());
+                parent.channel.as_str()
+            }
+            None => api.channel(alert),
+        };
+        payload["channel"] = serde_json::Value::from(channel);
+        let res = call_api(
+            &self.client,
+            &api.token,
+            API_POST_MESSAGE_URL,
+            &payload,
+            Encoding::Json,
+            &self.send_retry,
+        )
+        .await?;
+        let Some(ts) = res["ts"].as_str() else {
             return Ok(());
+        };
+        // replies go to the channel id of the answer, the config may name the channel
+        let channel = res["channel"].as_str().unwrap_or(channel);
+        if let Some(context) = alert.context.as_ref().filter(|_| self.uploads_context()) {
+            let thread_ts = thread.as_ref().map_or(ts, |parent| parent.ts.as_str());
+            self.attach_context(api, channel, thread_ts, context).await;
         }
+        // the alert is posted whether the reaction is added or not
+        if let Some(reaction) = api.reaction(alert)
+            && let Err(e) = self.react(api, channel, ts, reaction).await
+        {
+            warn!("Failed to react with :{}: to the alert: {}", reaction, e);
+        }
+        if thread.is_none() {
+            let (channel, ts) = (channel.to_string(), ts.to_string());
+            api.start_thread(alert, Parent { channel, ts });
+        }
+        Ok(())
+    }
+
+    /// Adds the emoji reaction to a posted message.
+    async fn react(
+        &self,
+        api: &SlackApi,
+        channel: &str,
+        ts: &str,
+        reaction: &str,
+    ) -> Result<(), NotifierError> {
+        let payload = serde_json::json!({ "channel": channel, "timestamp": ts, "name": reaction });
+        call_api(
+            &self.client,
+            &api.token,
+            API_REACTIONS_ADD_URL,
+            &payload,
+            Encoding::Json,
+            &self.send_retry,
+        )
+        .await
+        .map(drop)
+    }
 
-        let payload = serde_json::json!({ "text": message });
-        let res = self
-            .client
-            .post(&self.webhook_url)
-            .json(&payload)
-            .send()
+    /// Uploads the context in the thread of the alert. When the upload fails, e.g. the
+    /// token lacks the `files:write` scope, the context is replied inline instead.
+    async fn attach_context(&self, api: &SlackApi, channel: &str, thread_ts: &str, context: &str) {
+        let (name, content) = context_file(context, api.context_upload);
+        let Err(e) = self
+            .upload_file(api, channel, thread_ts, name, content)
             .await
-            .inspect_err(|e| error!("HTTP client error {}", e))?;
+        else {
+            return;
+        };
+        warn!(
+            "Failed to upload the journal context, replying it inline: {}",
+            e
+        );
+        let context = context::tail(context, context::MAX_CONTEXT_BYTES);
+        let payload = serde_json::json!({
+            "channel": channel,
+            "thread_ts": thread_ts,
+            "text": code_block(context),
+        });
+        let res = call_api(
+            &self.client,
+            &api.token,
+            API_POST_MESSAGE_URL,
+            &payload,
+            Encoding::Json,
+            &self.send_retry,
+        )
+        .await;
+        if let Err(e) = res {
+            warn!("Failed to reply the journal context: {}", e);
+        }
+    }
+
+    /// Uploads the file in the thread, in the three steps of the Web API: an upload url
+    /// is asked for, the content posted to it, then the upload completed in the channel.
+    async fn upload_file(
+        &self,
+        api: &SlackApi,
+        channel: &str,
+        thread_ts: &str,
+        name: &str,
+        content: Vec<u8>,
+    ) -> Result<(), NotifierError> {
+        let args = serde_json::json!({ "filename": name, "length": content.len() });
+        let res = call_api(
+            &self.client,
+            &api.token,
+            API_GET_UPLOAD_URL,
+            &args,
+            Encoding::Form,
+            &self.send_retry,
+        )
+        .await?;
+        let (Some(url), Some(id)) = (res["upload_url"].as_str(), res["file_id"].as_str()) else {
+            return Err(NotifierError::Api {
+                notifier: "Slack",
+                details: "no upload_url in the answer".to_string(),
+            });
+        };
+        notifier::send_with_retry("Slack", &self.send_retry, || async {
+            send(self.client.post(url).body(content.clone()))
+                .await
+                .map(drop)
+        })
+        .await?;
+        let args = serde_json::json!({
+            "files": serde_json::json!([{ "id": id, "title": name }]).to_string(),
+            "channel_id": channel,
+            "thread_ts": thread_ts,
+        });
+        call_api(
+            &self.client,
+            &api.token,
+            API_COMPLETE_UPLOAD_URL,
+            &args,
+            Encoding::Form,
+            &self.send_retry,
+        )
+        .await
+        .map(drop)
+    }
+
+    /// The text of the alert, after its severity label and wrapped with the message
+    /// prefix and suffix.
+    fn text(&self, alert: &Alert) -> String {
+        let label = match self.severity_labels.get(alert.severity) {
+            "" => String::new(),
+            label => format!("{label} "),
+        };
+        format!(
+            "{}{label}{}{}",
+            self.message_prefix,
+            alert.text(),
+            self.message_suffix
+        )
+    }
 
-        if !res.status().is_success() {
-            error!("Failed to send alert to Slack. Status: {}", res.status());
+    /// Builds the webhook payload, the identity fields are only set when configured.
+    fn payload(&self, alert: &Alert) -> serde_json::Value {
+        let identity = &self.identity;
+        let mut text = self.text(alert);
+        if let Some(context) = alert.context.as_ref().filter(|_| !self.uploads_context()) {
+            text = format!("{text}\n{}", code_block(context));
+        }
+        let mut payload = serde_json::json!({ "text": text });
+        let heartbeat = matches!(alert.kind, AlertKind::Heartbeat | AlertKind::Recovery);
+        let icon_emoji = if heartbeat && !identity.heartbeat_icon_emoji.is_empty() {
+            identity.heartbeat_icon_emoji.as_str()
+        } else {
+            identity.icon_emoji.as_str()
+        };
+        // an emoji takes precedence over the url in Slack, so send only one of them
+        let icon_url = if icon_emoji.is_empty() {
+            identity.icon_url.as_str()
+        } else {
+            ""
+        };
+        for (key, value) in [
+            ("username", identity.username.as_str()),
+            ("icon_emoji", icon_emoji),
+            ("icon_url", icon_url),
+        ] {
+            if !value.is_empty() {
+                payload[key] = serde_json::Value::from(value);
+            }
+        }
+        let ack_button = self.api.as_ref().is_some_and(|api| api.ack_button);
+        if ack_button && alert.kind == AlertKind::Rule {
+            // the button identifies the alert by its key in the suppression entries
+            let key = self.suppressions().key(alert.dedup_text());
+            payload["attachments"] = serde_json::json!([{
+                "blocks": [{
+                    "type": "actions",
+                    "elements": [{
+                        "type": "button",
+                        "action_id": ACK_ACTION_ID,
+                        "text": { "type": "plain_text", "text": "Acknowledge" },
+                        "value": key.to_string(),
+                    }],
+                }],
+            }]);
         }
+        payload
+    }
+}
 
-        Ok(())
+/// Formats the journal context as a code block.
+fn code_block(context: &str) -> String {
+    // a fence in the context would end the code block early
+    format!("```\n{}\n```", context.replace("```", "'''"))
+}
+
+/// The name and content of the file the context is uploaded as. Should the compression
+/// fail, the context is uploaded uncompressed.
+fn context_file(context: &str, upload: ContextUpload) -> (&'static str, Vec<u8>) {
+    if upload == ContextUpload::Gzip {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        match encoder
+            .write_all(context.as_bytes())
+            .and_then(|()| encoder.finish())
+        {
+            Ok(compressed) => return ("context.log.gz", compressed),
+            Err(e) => warn!("Failed to compress the journal context: {}", e),
+        }
+    }
+    ("context.log", context.as_bytes().to_vec())
+}
+
+/// Highlights the span of the message with Slack formatting, backticks are used
+/// unless the span contains one, in which case it falls back to bold.
+pub fn highlight(message: &str, span: Range<usize>) -> String {
+    let Some(matched) = message.get(span.clone()) else {
+        return message.to_string();
+    };
+    let marker = match (matched.contains('`'), matched.contains('*')) {
+        _ if matched.trim().is_empty() => return message.to_string(),
+        (false, _) => "`",
+        (true, false) => "*",
+        (true, true) => return message.to_string(),
+    };
+    format!(
+        "{}{marker}{matched}{marker}{}",
+        &message[..span.start],
+        &message[span.end..]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Severity;
+
+    #[test]
+    fn test_payload() {
+        let slack = Slack::new(Vec::new());
+        let alert = Alert::new("hello");
+        assert_eq!(
+            slack.payload(&alert),
+            serde_json::json!({ "text": "hello" })
+        );
+
+        let slack = slack.with_identity(SlackIdentity {
+            username: "prod-alerts".to_string(),
+            icon_emoji: ":rotating_light:".to_string(),
+            heartbeat_icon_emoji: ":heartbeat:".to_string(),
+            ..Default::default()
+        });
+        assert_eq!(
+            slack.payload(&alert),
+            serde_json::json!({
+                "text": "hello",
+                "username": "prod-alerts",
+                "icon_emoji": ":rotating_light:",
+            })
+        );
+        let recovery = Alert {
+            kind: AlertKind::Recovery,
+            ..Alert::new("recovered")
+        };
+        assert_eq!(slack.payload(&recovery)["icon_emoji"], ":heartbeat:");
+
+        let alert = Alert {
+            context: Some("line 1\nline 2".to_string()),
+            ..Alert::new("hello")
+        };
+        assert_eq!(
+            slack.payload(&alert)["text"],
+            "hello\n```\nline 1\nline 2\n```"
+        );
+
+        // the wrapping goes around the message, the context stays last
+        let slack = slack.with_message_wrap("[prod] ".to_string(), " [journal-alerts]".to_string());
+        assert_eq!(
+            slack.payload(&alert)["text"],
+            "[prod] hello [journal-alerts]\n```\nline 1\nline 2\n```"
+        );
+
+        // the severity label goes before the rule prefix, inside the wrapping
+        let slack = slack.with_severity_labels(SeverityLabels {
+            critical: "🔴 CRIT".to_string(),
+            ..Default::default()
+        });
+        let alert = Alert {
+            severity: Severity::Critical,
+            ..Alert::new("🚨 disk full")
+        };
+        assert_eq!(
+            slack.payload(&alert)["text"],
+            "[prod] 🔴 CRIT 🚨 disk full [journal-alerts]"
+        );
+        assert_eq!(
+            slack.payload(&Alert::new("hello"))["text"],
+            "[prod] hello [journal-alerts]"
+        );
+
+        // the alerts of the rules get the Acknowledge button, with the Web API
+        let slack = Slack::new(Vec::new())
+            .with_api(String::new(), "#alerts".to_string(), Duration::ZERO)
+            .with_ack_button();
+        let alert = Alert {
+            kind: AlertKind::Rule,
+            ..Alert::new("disk full")
+        };
+        let button = &slack.payload(&alert)["attachments"][0]["blocks"][0]["elements"][0];
+        assert_eq!(button["action_id"], ACK_ACTION_ID);
+        assert_eq!(
+            button["value"],
+            crate::suppression::key("disk full").to_string().as_str()
+        );
+        assert!(
+            slack
+                .payload(&Alert::new("started"))
+                .get("attachments")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_context_upload() {
+        use std::io::Read;
+
+        let alert = Alert {
+            context: Some("line 1\nline 2".to_string()),
+            ..Alert::new("hello")
+        };
+        // webhooks cannot upload, the context stays in the message
+        let slack = Slack::new(Vec::new()).with_context_upload(ContextUpload::Gzip);
+        assert_eq!(
+            slack.payload(&alert)["text"],
+            "hello\n```\nline 1\nline 2\n```"
+        );
+        let slack = slack
+            .with_api("xoxb".to_string(), "#alerts".to_string(), Duration::ZERO)
+            .with_context_upload(ContextUpload::Gzip);
+        assert_eq!(slack.payload(&alert)["text"], "hello");
+
+        let (name, content) = context_file("line 1\nline 2", ContextUpload::Gzip);
+        assert_eq!(name, "context.log.gz");
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(content.as_slice())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "line 1\nline 2");
+        assert_eq!(
+            context_file("line 1", ContextUpload::File),
+            ("context.log", b"line 1".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_highlight() {
+        assert_eq!(highlight("error: disk full", 7..16), "error: `disk full`");
+        assert_eq!(highlight("run `ls` failed", 4..8), "run *`ls`* failed");
+        assert_eq!(highlight("a `*` b", 2..5), "a `*` b");
+        assert_eq!(highlight("a   b", 1..3), "a   b");
+        assert_eq!(highlight("short", 2..10), "short");
+    }
+
+    /// Serves a webhook answering the messages containing "slow" after a delay,
+    /// the texts are reported as they are answered.
+    async fn webhook() -> (String, Receiver<String>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let (tx, rx) = flume::unbounded();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    // the payloads are small JSON objects, read until the body is complete
+                    let mut request = Vec::new();
+                    let mut buf = [0; 4096];
+                    while !request.ends_with(b"}") {
+                        let n = stream.read(&mut buf).await.unwrap();
+                        if n == 0 {
+                            return;
+                        }
+                        request.extend_from_slice(&buf[..n]);
+                    }
+                    let request = String::from_utf8(request).unwrap();
+                    let (_, body) = request.split_once("\r\n\r\n").unwrap();
+                    let body: serde_json::Value = serde_json::from_str(body).unwrap();
+                    let text = body["text"].as_str().unwrap().to_string();
+                    if text.contains("slow") {
+                        sleep(Duration::from_millis(500)).await;
+                    }
+                    stream
+                        .write_all(
+                            b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                        )
+                        .await
+                        .unwrap();
+                    tx.send(text).unwrap();
+                });
+            }
+        });
+        (url, rx)
+    }
+
+    #[tokio::test]
+    async fn test_send_concurrency() {
+        let (url, answered) = webhook().await;
+        let slack = Slack::new(vec![url]).with_send_concurrency(2);
+        let (tx, rx) = flume::unbounded();
+        tx.send(Alert::new("slow")).unwrap();
+        tx.send(Alert::new("fast")).unwrap();
+        drop(tx);
+        let notifier = tokio::spawn(async move { slack.start(rx).await });
+
+        // the fast alert is not held up by the slow one sent before it
+        assert_eq!(answered.recv_async().await.unwrap(), "fast");
+        assert_eq!(answered.recv_async().await.unwrap(), "slow");
+        // the notifier waits for the sends in flight before exiting
+        notifier.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_start_stops_sweeper() {
+        let slack = Slack::new(Vec::new());
+        let suppressions = slack.suppressions();
+        let held = Arc::strong_count(&suppressions);
+        for _ in 0..3 {
+            let (tx, rx) = flume::unbounded();
+            drop(tx);
+            slack.start(rx).await.unwrap();
+        }
+        // the sweepers of the stopped notifiers let go of the suppression state
+        for _ in 0..100 {
+            if Arc::strong_count(&suppressions) == held {
+                return;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        panic!("{} sweepers left", Arc::strong_count(&suppressions) - held);
+    }
+
+    #[tokio::test]
+    async fn test_call_api() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // answers each call with the next body, as the Web API does with a 200
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!(
+            "http://{}/api/chat.postMessage",
+            listener.local_addr().unwrap()
+        );
+        tokio::spawn(async move {
+            let answers = [
+                r#"{"ok":false,"error":"ratelimited"}"#,
+                r#"{"ok":true,"ts":"1700000000.000100"}"#,
+                r#"{"ok":false,"error":"channel_not_found"}"#,
+            ];
+            for answer in answers {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                while !request.ends_with(b"}") {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{answer}",
+                    answer.len()
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        let client = reqwest::Client::new();
+        let retry = SendRetry {
+            attempts: 2,
+            delay_ms: 0,
+        };
+        let payload = serde_json::json!({ "channel": "#alerts", "text": "disk full" });
+        // rate limited calls are retried
+        let res = call_api(&client, "xoxb", &url, &payload, Encoding::Json, &retry).await;
+        assert_eq!(res.unwrap()["ts"], "1700000000.000100");
+        // the other errors fail for good
+        let res = call_api(&client, "xoxb", &url, &payload, Encoding::Json, &retry).await;
+        assert!(
+            matches!(res, Err(NotifierError::Api { ref details, .. }) if details.contains("channel_not_found")),
+            "{res:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_suppress_after() {
+        let (url, answered) = webhook().await;
+        let slack = Slack::new(vec![url]).with_suppress_after(2);
+        let (tx, rx) = flume::unbounded();
+        for message in ["disk full", "disk full", "disk full", "done"] {
+            tx.send(Alert::new(message)).unwrap();
+        }
+        drop(tx);
+        slack.start(rx).await.unwrap();
+
+        // the first two identical messages are sent, the third one is suppressed
+        for expected in ["disk full", "disk full", "done"] {
+            assert_eq!(answered.recv_async().await.unwrap(), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_webhook_fan_out() {
+        let (url, answered) = webhook().await;
+        // a webhook refusing connections
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let down = format!("http://{}/", listener.local_addr().unwrap());
+        drop(listener);
+
+        // the failing webhook does not prevent sending to the other one
+        let slack = Slack::new(vec![down.clone(), url]);
+        slack.send_alert(&Alert::new("disk full")).await.unwrap();
+        assert_eq!(answered.recv_async().await.unwrap(), "disk full");
+        assert!(
+            Slack::new(vec![down])
+                .send_alert(&Alert::new("disk full"))
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_threads() {
+        let slack = Slack::new(Vec::new()).with_api(
+            "xoxb-token".to_string(),
+            "#alerts".to_string(),
+            Duration::from_secs(3600),
+        );
+        let api = slack.api.as_ref().unwrap();
+        let alert = |rule: &str| Alert {
+            rule: Some(rule.to_string()),
+            ..Alert::new("disk full")
+        };
+        let parent = |ts: &str| Parent {
+            channel: "C01".to_string(),
+            ts: ts.to_string(),
+        };
+        assert_eq!(api.thread(&alert("disk")), None);
+        api.start_thread(&alert("disk"), parent("1700000000.000100"));
+        // the next alerts of the rule go in the thread, other rules start their own
+        assert_eq!(
+            api.thread(&alert("disk")),
+            Some(parent("1700000000.000100"))
+        );
+        assert_eq!(api.thread(&alert("oom")), None);
+        // alerts without a rule are never threaded
+        api.start_thread(&Alert::new("started"), parent("1700000000.000200"));
+        assert_eq!(api.threads.len(), 1);
+
+        // the recovery of a heartbeat is posted under its miss, once
+        let slack = slack.with_heartbeat_threads();
+        let api = slack.api.as_ref().unwrap();
+        let heartbeat = |kind: AlertKind| Alert {
+            kind,
+            thread_key: Some("heartbeat:app:ready".to_string()),
+            ..Alert::new("Heartbeat missed")
+        };
+        api.start_thread(
+            &heartbeat(AlertKind::Heartbeat),
+            parent("1700000000.000300"),
+        );
+        let recovery = heartbeat(AlertKind::Recovery);
+        assert_eq!(api.thread(&recovery), Some(parent("1700000000.000300")));
+        assert_eq!(api.thread(&recovery), None);
+        // a new miss that failed to send leaves its recovery unthreaded
+        api.start_thread(
+            &heartbeat(AlertKind::Heartbeat),
+            parent("1700000000.000400"),
+        );
+        assert_eq!(api.thread(&heartbeat(AlertKind::Heartbeat)), None);
+        assert_eq!(api.thread(&recovery), None);
+        // monitors watching the same pattern pair their own miss and recovery
+        let other = |kind: AlertKind| Alert {
+            thread_key: Some("heartbeat:db:ready".to_string()),
+            ..heartbeat(kind)
+        };
+        api.start_thread(
+            &heartbeat(AlertKind::Heartbeat),
+            parent("1700000000.000500"),
+        );
+        api.start_thread(&other(AlertKind::Heartbeat), parent("1700000000.000600"));
+        assert_eq!(
+            api.thread(&other(AlertKind::Recovery)),
+            Some(parent("1700000000.000600"))
+        );
+        assert_eq!(api.thread(&recovery), Some(parent("1700000000.000500")));
+
+        // expired threads are not reused
+        let slack = Slack::new(Vec::new()).with_api(String::new(), String::new(), Duration::ZERO);
+        let api = slack.api.as_ref().unwrap();
+        api.start_thread(&alert("disk"), parent("1700000000.000100"));
+        assert_eq!(api.thread(&alert("disk")), None);
+    }
+
+    #[test]
+    fn test_severity_channels() {
+        let slack = Slack::new(Vec::new())
+            .with_api(
+                "xoxb-token".to_string(),
+                "#logs".to_string(),
+                Duration::ZERO,
+            )
+            .with_severity_channels(SlackChannels {
+                critical: "#incidents".to_string(),
+                ..SlackChannels::default()
+            });
+        let api = slack.api.as_ref().unwrap();
+        let alert = |severity: Severity| Alert {
+            severity,
+            ..Alert::new("disk full")
+        };
+        assert_eq!(api.channel(&alert(Severity::Critical)), "#incidents");
+        assert_eq!(api.channel(&alert(Severity::Warning)), "#logs");
+
+        let slack = slack.with_severity_reactions(SlackReactions {
+            critical: ":fire:".to_string(),
+            info: "eyes".to_string(),
+            ..SlackReactions::default()
+        });
+        let api = slack.api.as_ref().unwrap();
+        let rule = |severity: Severity| Alert {
+            kind: AlertKind::Rule,
+            ..alert(severity)
+        };
+        assert_eq!(api.reaction(&rule(Severity::Critical)), Some("fire"));
+        assert_eq!(api.reaction(&rule(Severity::Info)), Some("eyes"));
+        assert_eq!(api.reaction(&rule(Severity::Warning)), None);
+        // the notices of the tool itself are not reacted to
+        assert_eq!(api.reaction(&alert(Severity::Critical)), None);
     }
 }
@@ -0,0 +1,528 @@
+use std::borrow::Cow;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use log::{debug, info, warn};
+
+use crate::alert::Alert;
+use crate::config::{DedupStep, SuppressionTtl};
+use crate::dedup::SharedDedup;
+use crate::error::MatcherError;
+use crate::metrics::Metrics;
+use crate::recent::RecentAlerts;
+
+// length of the message sample kept with each entry
+const SAMPLE_LEN: usize = 80;
+
+/// A message already sent, with the number of times it was seen since.
+#[derive(Debug, Clone)]
+pub struct Repeat {
+    // the beginning of the message, for logging
+    pub sample: String,
+    pub count: usize,
+    pub first_seen: Instant,
+    // last time the message was seen, the least recently seen entries are evicted first
+    pub last_seen: Instant,
+    // how long duplicates are suppressed
+    pub ttl: Duration,
+    // name of the rule that produced the message, if any
+    pub rule: Option<String>,
+    // who acknowledged the message, its duplicates are suppressed until it expires
+    pub acknowledged: Option<String>,
+}
+
+impl Repeat {
+    fn expired(&self) -> bool {
+        self.first_seen.elapsed() >= self.ttl
+    }
+}
+
+/// The duplicate suppression state, keyed by a hash of the normalized message.
+///
+/// Hashing bounds the memory used by each entry regardless of the message length.
+/// A collision between two different messages makes the second one suppressed as a
+/// duplicate of the first, which is acceptable given the 64 bits hash and the limited
+/// lifetime of the entries.
+///
+/// With a maximum number of entries, the least recently seen one is evicted to make
+/// room for a new message; its next occurrence is then treated as a new message.
+#[derive(Default)]
+pub struct Suppressions {
+    entries: DashMap<u64, Repeat>,
+    // maximum number of entries, unbounded when 0
+    max_entries: usize,
+    metrics: Arc<Metrics>,
+    dedup_key: DedupKey,
+}
+
+/// The compiled `dedup_key` steps, turning a message into the text its duplicates
+/// are detected on, e.g. to ignore the ids in it.
+#[derive(Debug, Clone, Default)]
+pub struct DedupKey {
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone)]
+enum Step {
+    Lowercase,
+    StripDigits,
+    FirstWords(usize),
+    Capture(regex::Regex),
+}
+
+impl DedupKey {
+    pub fn new(steps: &[DedupStep]) -> Result<Self, MatcherError> {
+        let steps = steps
+            .iter()
+            .map(|step| {
+                Ok(match step {
+                    DedupStep::Lowercase => Step::Lowercase,
+                    DedupStep::StripDigits => Step::StripDigits,
+                    DedupStep::FirstWords(words) => Step::FirstWords(*words),
+                    DedupStep::Capture(pattern) => {
+                        Step::Capture(regex::Regex::new(pattern).map_err(|source| {
+                            MatcherError::DedupKey {
+                                pattern: pattern.clone(),
+                                source,
+                            }
+                        })?)
+                    }
+                })
+            })
+            .collect::<Result<_, MatcherError>>()?;
+        Ok(DedupKey { steps })
+    }
+
+    /// Applies the steps in order to the message.
+    pub fn apply<'m>(&self, message: &'m str) -> Cow<'m, str> {
+        self.steps
+            .iter()
+            .fold(Cow::Borrowed(message), |text, step| match step {
+                Step::Lowercase => Cow::Owned(text.to_lowercase()),
+                Step::StripDigits => Cow::Owned(text.replace(|c: char| c.is_ascii_digit(), "")),
+                Step::FirstWords(words) => Cow::Owned(
+                    text.split_whitespace()
+                        .take(*words)
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                ),
+                Step::Capture(re) => {
+                    let captured = re.captures(&text).and_then(|captures| {
+                        let group = captures.get(1).or_else(|| captures.get(0))?;
+                        Some(group.as_str().to_string())
+                    });
+                    captured.map_or(text, Cow::Owned)
+                }
+            })
+    }
+}
+
+/// Hashes the message with whitespace normalized.
+pub fn key(message: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for word in message.split_whitespace() {
+        word.hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+impl Suppressions {
+    pub fn new(max_entries: usize, metrics: Arc<Metrics>) -> Self {
+        Suppressions {
+            entries: DashMap::new(),
+            max_entries,
+            metrics,
+            dedup_key: DedupKey::default(),
+        }
+    }
+
+    /// Detects the duplicates on the text built by the `dedup_key` steps.
+    pub fn with_dedup_key(mut self, dedup_key: DedupKey) -> Self {
+        self.dedup_key = dedup_key;
+        self
+    }
+
+    /// The text duplicates of the message are detected on.
+    pub fn dedup_text<'m>(&self, message: &'m str) -> Cow<'m, str> {
+        self.dedup_key.apply(message)
+    }
+
+    /// The key of the message in the entries.
+    pub fn key(&self, message: &str) -> u64 {
+        key(&self.dedup_text(message))
+    }
+
+    /// Counts a duplicate of the message, returns the count if the message was
+    /// already sent and its suppression has not expired.
+    pub fn duplicate(&self, message: &str) -> Option<usize> {
+        let key = self.key(message);
+        self.entries.remove_if(&key, |_, repeat| repeat.expired());
+        let mut entry = self.entries.get_mut(&key)?;
+        entry.count += 1;
+        entry.last_seen = Instant::now();
+        Some(entry.count)
+    }
+
+    /// Records a sent message, its duplicates are suppressed for `ttl`.
+    pub fn insert(&self, message: &str, rule: Option<String>, ttl: Duration) {
+        let key = self.key(message);
+        if self.max_entries > 0
+            && self.entries.len() >= self.max_entries
+            && !self.entries.contains_key(&key)
+        {
+            self.evict();
+        }
+        let now = Instant::now();
+        self.entries.insert(
+            key,
+            Repeat {
+                sample: message.chars().take(SAMPLE_LEN).collect(),
+                count: 1,
+                first_seen: now,
+                last_seen: now,
+                ttl,
+                rule,
+                acknowledged: None,
+            },
+        );
+        self.update_gauge();
+    }
+
+    /// Acknowledges a sent message by its key, its duplicates are suppressed for the
+    /// `window` from now. Returns the beginning of the message, empty when it is not
+    /// known anymore, e.g. a critical alert, in which case an entry is created.
+    pub fn acknowledge(&self, key: u64, by: &str, window: Duration) -> String {
+        self.entries.remove_if(&key, |_, repeat| repeat.expired());
+        let now = Instant::now();
+        let mut entry = self.entries.entry(key).or_insert_with(|| Repeat {
+            sample: String::new(),
+            count: 1,
+            first_seen: now,
+            last_seen: now,
+            ttl: Duration::ZERO,
+            rule: None,
+            acknowledged: None,
+        });
+        entry.ttl = entry.first_seen.elapsed() + window;
+        entry.acknowledged = Some(by.to_string());
+        let sample = entry.sample.clone();
+        drop(entry);
+        self.update_gauge();
+        sample
+    }
+
+    /// Counts a duplicate of an acknowledged message, returns who acknowledged it.
+    pub fn acknowledged(&self, message: &str) -> Option<String> {
+        let key = self.key(message);
+        self.entries.remove_if(&key, |_, repeat| repeat.expired());
+        let mut entry = self.entries.get_mut(&key)?;
+        let by = entry.acknowledged.clone()?;
+        entry.count += 1;
+        entry.last_seen = Instant::now();
+        Some(by)
+    }
+
+    /// Removes the least recently seen entry.
+    fn evict(&self) {
+        let oldest = self
+            .entries
+            .iter()
+            .min_by_key(|e| e.value().last_seen)
+            .map(|e| *e.key());
+        if let Some((_, repeat)) = oldest.and_then(|key| self.entries.remove(&key)) {
+            debug!("Suppression entry evicted: {}", repeat.sample);
+            Metrics::inc(&self.metrics.suppression_evictions);
+        }
+    }
+
+    fn update_gauge(&self) {
+        Metrics::set(&self.metrics.suppression_entries, self.entries.len() as u64);
+    }
+
+    /// Forgets a message, e.g. when sending it failed.
+    pub fn remove(&self, message: &str) {
+        self.entries.remove(&self.key(message));
+        self.update_gauge();
+    }
+
+    /// Clears the suppressed duplicates the alert resets: those of the rules it
+    /// resolves or links, and the misses of the heartbeats it recovers.
+    pub fn reset_for(&self, alert: &Alert) {
+        for rule in alert.resets() {
+            self.reset(Some(rule));
+        }
+        for key in &alert.reset_keys {
+            self.remove(key);
+        }
+    }
+
+    /// Removes the expired entries, logging a summary of the suppressed ones.
+    pub fn sweep(&self) {
+        self.entries.retain(|_, repeat| {
+            let expired = repeat.expired();
+            if expired && repeat.count > 1 {
+                info!(
+                    "Suppression expired, seen {} times: {}",
+                    repeat.count, repeat.sample
+                );
+            }
+            !expired
+        });
+        self.update_gauge();
+    }
+
+    /// Clears the entries of the rule, or all of them, returns the number of entries removed.
+    pub fn reset(&self, rule: Option<&str>) -> usize {
+        let before = self.entries.len();
+        match rule {
+            Some(rule) => self
+                .entries
+                .retain(|_, repeat| repeat.rule.as_deref() != Some(rule)),
+            None => self.entries.clear(),
+        }
+        let removed = before.saturating_sub(self.entries.len());
+        self.update_gauge();
+        info!(
+            "Suppression reset for {}: {} entries removed",
+            rule.unwrap_or("all rules"),
+            removed
+        );
+        removed
+    }
+}
+
+/// Decides which alerts are sent and which are suppressed as duplicates, in Slack or,
+/// with `shared_suppression`, once in front of all the notifiers.
+#[derive(Clone)]
+pub struct Suppressor {
+    repeats: Arc<Suppressions>,
+    // the last alerts sent, for the control socket
+    recent: Arc<RecentAlerts>,
+    shared_dedup: Option<SharedDedup>,
+    suppression_ttl: SuppressionTtl,
+    // number of identical messages sent before the following ones are suppressed
+    suppress_after: usize,
+}
+
+impl Default for Suppressor {
+    fn default() -> Self {
+        Suppressor {
+            repeats: Arc::new(Suppressions::default()),
+            recent: Arc::new(RecentAlerts::default()),
+            shared_dedup: None,
+            suppression_ttl: SuppressionTtl::default(),
+            suppress_after: 1,
+        }
+    }
+}
+
+impl Suppressor {
+    /// Returns the duplicate suppression state.
+    pub fn suppressions(&self) -> Arc<Suppressions> {
+        self.repeats.clone()
+    }
+
+    /// Replaces the duplicate suppression state, e.g. to bound it.
+    pub fn with_suppressions(mut self, suppressions: Suppressions) -> Self {
+        self.repeats = Arc::new(suppressions);
+        self
+    }
+
+    /// Returns the last alerts sent.
+    pub fn recent_alerts(&self) -> Arc<RecentAlerts> {
+        self.recent.clone()
+    }
+
+    /// Keeps the last `capacity` alerts sent, with their suppressed duplicates.
+    pub fn with_recent_alerts(mut self, capacity: usize) -> Self {
+        self.recent = Arc::new(RecentAlerts::new(capacity));
+        self
+    }
+
+    /// Sets how long duplicates are suppressed, per severity.
+    pub fn with_suppression_ttl(mut self, suppression_ttl: SuppressionTtl) -> Self {
+        self.suppression_ttl = suppression_ttl;
+        self
+    }
+
+    /// Sets how many identical messages are sent before suppressing the following ones.
+    pub fn with_suppress_after(mut self, suppress_after: usize) -> Self {
+        self.suppress_after = suppress_after.max(1);
+        self
+    }
+
+    /// Enables deduplication shared with other instances.
+    pub fn with_shared_dedup(mut self, shared_dedup: SharedDedup) -> Self {
+        self.shared_dedup = Some(shared_dedup);
+        self
+    }
+
+    /// Returns whether the alert is to be sent, recording it as sent, or whether it
+    /// is a duplicate to suppress.
+    pub async fn admit(&self, alert: &Alert) -> bool {
+        let message = &alert.message;
+        // a resolved rule alerts again on its next match, a heartbeat on its next miss
+        self.repeats.reset_for(alert);
+
+        // an acknowledged alert stays quiet until the acknowledgment expires, even critical
+        if let Some(by) = self.repeats.acknowledged(alert.dedup_text()) {
+            info!("Suppressing alert acknowledged by {}: {}", by, message);
+            self.recent.suppressed(self.repeats.key(alert.dedup_text()));
+            return false;
+        }
+
+        // critical alerts bypass the suppression entirely
+        if !alert.always_notify {
+            // to avoid spamming, check for duplicates, the first ones may still be sent
+            let duplicate = self.repeats.duplicate(alert.dedup_text());
+            if let Some(count) = duplicate.filter(|count| *count > self.suppress_after) {
+                warn!(
+                    "Suppressing duplicate alert detected, count: {}: {}",
+                    count, message
+                );
+                self.recent.suppressed(self.repeats.key(alert.dedup_text()));
+                return false;
+            }
+
+            if duplicate.is_none() {
+                let ttl = alert
+                    .suppression_ttl
+                    .unwrap_or_else(|| self.suppression_ttl.get(alert.severity));
+                // check if another instance already reported it, on errors send anyway
+                if let Some(shared_dedup) = &self.shared_dedup {
+                    match shared_dedup
+                        .claim(&self.repeats.dedup_text(alert.dedup_text()))
+                        .await
+                    {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            info!("Alert already reported by another instance: {}", message);
+                            self.repeats
+                                .insert(alert.dedup_text(), alert.rule.clone(), ttl);
+                            return false;
+                        }
+                        Err(e) => warn!("Shared deduplication failed, sending anyway: {}", e),
+                    }
+                }
+
+                // insert into repeats map with count 1, suppressed for the severity ttl.
+                // done before sending so that duplicates arriving meanwhile are suppressed
+                self.repeats
+                    .insert(alert.dedup_text(), alert.rule.clone(), ttl);
+            }
+        }
+
+        self.recent.sent(
+            alert.rule.clone(),
+            message,
+            self.repeats.key(alert.dedup_text()),
+        );
+        true
+    }
+
+    /// Forgets an alert whose send failed, so that its next duplicate is sent.
+    pub fn forget(&self, alert: &Alert) {
+        if !alert.always_notify {
+            self.repeats.remove(alert.dedup_text());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alert::AlertKind;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn test_suppressions() {
+        let ttl = Duration::from_secs(60);
+        let suppressions = Suppressions::default();
+        assert_eq!(suppressions.duplicate("disk full"), None);
+        suppressions.insert("disk full", Some("disk".to_string()), ttl);
+        suppressions.insert("db down", Some("db".to_string()), ttl);
+        suppressions.insert("started", None, ttl);
+        assert_eq!(suppressions.duplicate("disk full"), Some(2));
+        assert_eq!(suppressions.duplicate("disk full"), Some(3));
+        // whitespace differences are the same message
+        assert_eq!(suppressions.duplicate(" disk   full\n"), Some(4));
+
+        assert_eq!(suppressions.reset(Some("disk")), 1);
+        assert_eq!(suppressions.duplicate("disk full"), None);
+        assert_eq!(suppressions.reset(None), 2);
+        assert_eq!(suppressions.reset(None), 0);
+    }
+
+    #[test]
+    fn test_dedup_key() {
+        let dedup_key = DedupKey::new(&[
+            DedupStep::Capture(r"^worker \d+: (.*)".to_string()),
+            DedupStep::Lowercase,
+            DedupStep::StripDigits,
+            DedupStep::FirstWords(3),
+        ])
+        .unwrap();
+        assert_eq!(
+            dedup_key.apply("worker 7: Request 4711 failed after 30ms"),
+            "request failed after"
+        );
+        // a capture that does not match keeps the text
+        assert_eq!(dedup_key.apply("Disk full"), "disk full");
+        let suppressions = Suppressions::default().with_dedup_key(dedup_key);
+        suppressions.insert("worker 1: job 12 failed", None, Duration::from_secs(60));
+        assert_eq!(suppressions.duplicate("worker 2: Job 13 failed"), Some(2));
+
+        assert!(DedupKey::new(&[DedupStep::Capture("(".to_string())]).is_err());
+    }
+
+    #[test]
+    fn test_suppressions_ttl() {
+        let suppressions = Suppressions::default();
+        suppressions.insert("expired", None, Duration::ZERO);
+        suppressions.insert("active", None, Duration::from_secs(60));
+        // an expired entry is treated as a new message
+        assert_eq!(suppressions.duplicate("expired"), None);
+        assert_eq!(suppressions.duplicate("active"), Some(2));
+        suppressions.insert("expired", None, Duration::ZERO);
+        suppressions.sweep();
+        assert_eq!(suppressions.reset(None), 1);
+    }
+
+    #[test]
+    fn test_reset_for() {
+        let ttl = Duration::from_secs(60);
+        let suppressions = Suppressions::default();
+        suppressions.insert("heartbeat:app:0", None, ttl);
+        suppressions.insert("disk full", Some("disk".to_string()), ttl);
+        suppressions.insert("db down", Some("db".to_string()), ttl);
+        // a recovered heartbeat clears its miss and the rules linked to it
+        suppressions.reset_for(&Alert {
+            kind: AlertKind::Recovery,
+            reset_rules: vec!["disk".to_string()],
+            reset_keys: vec!["heartbeat:app:0".to_string()],
+            ..Alert::new("recovered")
+        });
+        assert_eq!(suppressions.duplicate("heartbeat:app:0"), None);
+        assert_eq!(suppressions.duplicate("disk full"), None);
+        assert_eq!(suppressions.duplicate("db down"), Some(2));
+    }
+
+    #[test]
+    fn test_suppressions_max_entries() {
+        let metrics = Arc::new(Metrics::default());
+        let suppressions = Suppressions::new(2, metrics.clone());
+        let ttl = Duration::from_secs(60);
+        suppressions.insert("a", None, ttl);
+        suppressions.insert("b", None, ttl);
+        // seeing "a" again makes "b" the least recently seen
+        assert_eq!(suppressions.duplicate("a"), Some(2));
+        suppressions.insert("c", None, ttl);
+        assert_eq!(suppressions.duplicate("b"), None);
+        assert_eq!(suppressions.duplicate("a"), Some(3));
+        assert_eq!(suppressions.duplicate("c"), Some(2));
+        assert_eq!(metrics.suppression_entries.load(Ordering::Relaxed), 2);
+        assert_eq!(metrics.suppression_evictions.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,123 @@
+use ::syslog::{Facility, Formatter3164, Logger, LoggerBackend};
+use flume::Receiver;
+use log::{error, info, warn};
+
+use crate::alert::Alert;
+use crate::config::{Severity, SyslogConfig};
+use crate::error::{ConfigError, NotifierError};
+use crate::suppression::{DedupKey, Suppressions, Suppressor};
+
+/// Writes the alerts to the local syslog, e.g. where syslog is the canonical sink
+/// collected downstream.
+pub struct Syslog {
+    config: SyslogConfig,
+    facility: Facility,
+    suppressor: Option<Suppressor>,
+}
+
+impl Syslog {
+    pub fn new(config: SyslogConfig) -> Result<Self, ConfigError> {
+        let facility = parse_facility(&config.facility)?;
+        Ok(Syslog {
+            suppressor: config
+                .suppression_ttl
+                .clone()
+                .map(|ttl| Suppressor::default().with_suppression_ttl(ttl)),
+            config,
+            facility,
+        })
+    }
+
+    /// Detects the duplicates on the text built by the `dedup_key` steps.
+    pub fn with_dedup_key(mut self, dedup_key: DedupKey) -> Self {
+        self.suppressor = self.suppressor.map(|suppressor| {
+            suppressor.with_suppressions(Suppressions::default().with_dedup_key(dedup_key))
+        });
+        self
+    }
+
+    pub async fn start(&self, rx: Receiver<Alert>) -> Result<(), NotifierError> {
+        info!("Syslog notifier started.");
+        // connected on the first alert, and again after a failed write
+        let mut logger = None;
+        loop {
+            let Ok(alert) = rx.recv_async().await else {
+                info!("Syslog notifier receiver channel closed, exiting.");
+                break;
+            };
+            // alerts routed here explicitly are written whatever their severity
+            if alert.notify.is_empty() && !self.config.severities.contains(&alert.severity) {
+                continue;
+            }
+            // duplicates are only suppressed when the block sets their windows
+            if let Some(suppressor) = &self.suppressor {
+                let admitted = suppressor.admit(&alert).await;
+                suppressor.suppressions().sweep();
+                if !admitted {
+                    continue;
+                }
+            }
+
+            if logger.is_none() {
+                logger = self
+                    .connect()
+                    .inspect_err(|e| warn!("Failed to connect to syslog: {}", e))
+                    .ok();
+            }
+            let text = alert.text();
+            let Some(connected) = &mut logger else {
+                // without a syslog the alert is only logged
+                info!("{}", text);
+                continue;
+            };
+            if let Err(e) = write(connected, alert.severity, &text) {
+                error!("Error sending alert to syslog: {}", e);
+                logger = None;
+            }
+        }
+        Ok(())
+    }
+
+    fn connect(&self) -> Result<Logger<LoggerBackend, Formatter3164>, NotifierError> {
+        let formatter = Formatter3164 {
+            facility: self.facility,
+            hostname: None,
+            process: self.config.ident.clone(),
+            pid: std::process::id(),
+        };
+        ::syslog::unix(formatter).map_err(|e| NotifierError::Syslog(e.to_string()))
+    }
+}
+
+/// Parses a facility name such as `daemon` or `local0`.
+pub fn parse_facility(facility: &str) -> Result<Facility, ConfigError> {
+    facility
+        .parse()
+        .map_err(|_| ConfigError::Invalid(format!("Invalid syslog facility '{facility}'")))
+}
+
+/// Writes the text with the syslog severity of the alert severity.
+fn write(
+    logger: &mut Logger<LoggerBackend, Formatter3164>,
+    severity: Severity,
+    text: &str,
+) -> Result<(), NotifierError> {
+    match severity {
+        Severity::Critical => logger.crit(text),
+        Severity::Warning => logger.warning(text),
+        Severity::Info => logger.info(text),
+    }
+    .map_err(|e| NotifierError::Syslog(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_facility() {
+        assert!(matches!(parse_facility("daemon"), Ok(Facility::LOG_DAEMON)));
+        assert!(matches!(parse_facility("LOCAL3"), Ok(Facility::LOG_LOCAL3)));
+        assert!(parse_facility("nope").is_err());
+    }
+}